@@ -1,61 +1,30 @@
 extern crate pnet;
 
+use std::io;
+use std::mem::{size_of, MaybeUninit};
 use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::Duration;
 
-use pnet::datalink::Channel;
-use pnet::datalink::{self, NetworkInterface};
-use pnet::packet::arp::MutableArpPacket;
-use pnet::packet::arp::{ArpHardwareTypes, ArpOperation};
-use pnet::packet::ethernet::EtherTypes;
+use pnet::packet::arp::{ArpHardwareTypes, ArpOperation, ArpOperations};
+use pnet::packet::arp::{ArpPacket, MutableArpPacket};
 use pnet::packet::ethernet::MutableEthernetPacket;
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
 use pnet::packet::{MutablePacket, Packet};
 use pnet::util::MacAddr;
+use socket2::{Domain, SockAddr, Socket, Type};
+
+use tokio::io::unix::AsyncFd;
 
 use crate::link::interface::Interface;
 
-pub fn send_arp_packet(
-	interface: &Interface,
+fn arp_frame(
 	source_ip: Ipv4Addr,
 	source_mac: MacAddr,
 	target_ip: Ipv4Addr,
 	target_mac: MacAddr,
 	arp_operation: ArpOperation,
-) {
-	let interfaces = datalink::interfaces();
-
-	let interfaces_name_match = |iface: &NetworkInterface| iface.name == interface.name;
-	let interface = interfaces
-		.into_iter()
-		.filter(interfaces_name_match)
-		.next()
-		.unwrap();
-
-	let (mut tx, _) = match datalink::channel(&interface, Default::default()) {
-		Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-		Ok(_) => panic!("Unknown channel type"),
-		Err(e) => panic!("Error happened {}", e),
-	};
-
-	// ethernet_packet = Ethernet {
-	//     destination: [0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-	//     source: [0x28, 0xef, 0xf9, 0x5f, 0x8e, 0x2b],
-	//     ethertype: [0x08, 0x06], // Arp(0x0806)
-	//     payload: arp_packet
-	// }
-	//
-	// arp_packet = Arp {
-	//     hardware_type: [0x00, 0x01],
-	//     protocol_type: [0x08, 0x00], // Ipv4(0x0800)
-	//     hw_addr_len: [0x06],
-	//     proto_addr_len: [0x04],
-	//     operation: [0x00, 0x02], // Reply(0x0002)
-	//     sender_hw_addr: [0x28, 0xef, 0xf9, 0x5f, 0x8e, 0x2b],
-	//     sender_proto_addr: [0xc0, 0xa8, 0x00, 0x66], // Ipv4(192.168.0.102)
-	//     target_hw_addr: [0xff, 0xff, 0xff, 0xff, 0xff, 0xff], // Broadcast
-	//     target_proto_addr: [0xc0, 0xa8, 0x00, 0x65], // Ipv4(192.168.0.101)
-	//     payload: [],
-	// }
-
+) -> [u8; 42] {
 	let mut ethernet_buffer = [0u8; 42];
 	let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap();
 
@@ -77,6 +46,222 @@ pub fn send_arp_packet(
 	arp_packet.set_target_proto_addr(target_ip);
 
 	ethernet_packet.set_payload(arp_packet.packet_mut());
+	ethernet_buffer
+}
+
+/// An ARP packet as sent or received on an interface
+#[derive(Clone, Copy, Debug)]
+pub struct ArpMessage {
+	pub operation: ArpOperation,
+	pub sender_mac: MacAddr,
+	pub sender_ip: Ipv4Addr,
+	pub target_mac: MacAddr,
+	pub target_ip: Ipv4Addr,
+}
+
+impl ArpMessage {
+	fn parse(frame: &[u8]) -> Option<ArpMessage> {
+		let ethernet = EthernetPacket::new(frame)?;
+		if ethernet.get_ethertype() != EtherTypes::Arp {
+			return None;
+		}
+		let arp = ArpPacket::new(ethernet.payload())?;
+		Some(ArpMessage {
+			operation: arp.get_operation(),
+			sender_mac: arp.get_sender_hw_addr(),
+			sender_ip: arp.get_sender_proto_addr(),
+			target_mac: arp.get_target_hw_addr(),
+			target_ip: arp.get_target_proto_addr(),
+		})
+	}
+}
+
+/// Opens an AF_PACKET socket on the interface that receives and sends its ARP frames
+fn open_socket(ifindex: u32) -> io::Result<AsyncFd<Socket>> {
+	let protocol = (libc::ETH_P_ARP as u16).to_be();
+	// Without a protocol nothing is received until the socket is bound to the interface
+	let socket = Socket::new(Domain::PACKET, Type::RAW, None)?;
+	// SAFETY: the zeroed storage is large enough for the sockaddr_ll of the packet family
+	let ((), address) = unsafe {
+		SockAddr::try_init(|storage, len| {
+			let address = storage.cast::<libc::sockaddr_ll>();
+			(*address).sll_family = libc::AF_PACKET as u16;
+			(*address).sll_protocol = protocol;
+			(*address).sll_ifindex = ifindex as i32;
+			*len = size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+			Ok(())
+		})
+	}?;
+	socket.bind(&address)?;
+	socket.set_nonblocking(true)?;
+	AsyncFd::new(socket)
+}
+
+/// Receives the ARP packets arriving on an interface for as long as it is kept,
+/// and sends packets through the same socket
+pub struct ArpListener {
+	/// The MAC address of the interface
+	pub mac: MacAddr,
+	name: String,
+	socket: AsyncFd<Socket>,
+}
+
+impl ArpListener {
+	pub async fn open(interface: &Interface) -> Option<ArpListener> {
+		let name = interface.name.clone();
+		let mac = MacAddr::from_str(&interface.get_mac().await).ok()?;
+		// An index of 0 would bind to every interface
+		let ifindex = interface.get_index();
+		if ifindex == 0 {
+			println!("[{name}] Failed to listen for ARP: no such interface");
+			return None;
+		}
+		match open_socket(ifindex) {
+			Ok(socket) => Some(ArpListener { mac, name, socket }),
+			Err(err) => {
+				println!("[{name}] Failed to listen for ARP: {err}");
+				None
+			}
+		}
+	}
+
+	/// The next ARP packet received, including the ones sent by this host
+	pub async fn recv(&mut self) -> Option<ArpMessage> {
+		let mut buffer = [MaybeUninit::<u8>::uninit(); 128];
+		loop {
+			let mut guard = self.socket.readable().await.ok()?;
+			let Ok(result) = guard.try_io(|socket| socket.get_ref().recv(&mut buffer)) else {
+				continue;
+			};
+			let len = match result {
+				Ok(len) => len,
+				Err(err) => {
+					println!("[{}] Failed to receive ARP: {err}", self.name);
+					return None;
+				}
+			};
+			// SAFETY: recv initialized the first len bytes
+			let frame: Vec<u8> = buffer[..len]
+				.iter()
+				.map(|byte| unsafe { byte.assume_init() })
+				.collect();
+			if let Some(message) = ArpMessage::parse(&frame) {
+				return Some(message);
+			}
+		}
+	}
+
+	pub fn send(&mut self, message: &ArpMessage, destination: MacAddr) {
+		let mut frame = arp_frame(
+			message.sender_ip,
+			message.sender_mac,
+			message.target_ip,
+			message.target_mac,
+			message.operation,
+		);
+		MutableEthernetPacket::new(&mut frame)
+			.unwrap()
+			.set_destination(destination);
+		// The socket is bound to the interface, so the frame needs no address
+		if let Err(err) = self.socket.get_ref().send(&frame) {
+			println!("[{}] Failed to send ARP: {err}", self.name);
+		}
+	}
+
+	/// Broadcasts a request for the target from the source, which is unspecified for probes
+	pub fn send_request(&mut self, source_ip: Ipv4Addr, target_ip: Ipv4Addr) {
+		let request = ArpMessage {
+			operation: ArpOperations::Request,
+			sender_mac: self.mac,
+			sender_ip: source_ip,
+			target_mac: MacAddr::zero(),
+			target_ip,
+		};
+		self.send(&request, MacAddr::broadcast());
+	}
+
+	/// Waits for a packet sent by another host from the address, returning its MAC address
+	pub async fn wait_for_sender(
+		&mut self,
+		address: Ipv4Addr,
+		timeout: Duration,
+	) -> Option<MacAddr> {
+		self.wait_for(timeout, |message| message.sender_ip == address)
+			.await
+	}
+
+	/// Waits for another host to use the address or to probe for it (RFC 5227 2.1.1),
+	/// returning its MAC address
+	pub async fn wait_for_claim(
+		&mut self,
+		address: Ipv4Addr,
+		timeout: Duration,
+	) -> Option<MacAddr> {
+		self.wait_for(timeout, |message| {
+			message.sender_ip == address
+				|| (message.sender_ip.is_unspecified()
+					&& message.operation == ArpOperations::Request
+					&& message.target_ip == address)
+		})
+		.await
+	}
+
+	async fn wait_for(
+		&mut self,
+		timeout: Duration,
+		matches: impl Fn(&ArpMessage) -> bool,
+	) -> Option<MacAddr> {
+		let mac = self.mac;
+		tokio::time::timeout(timeout, async {
+			while let Some(message) = self.recv().await {
+				if message.sender_mac != mac && matches(&message) {
+					return Some(message.sender_mac);
+				}
+			}
+			None
+		})
+		.await
+		.ok()
+		.flatten()
+	}
+}
+
+/// Sends a request and waits for the host owning the target address to answer, returning its MAC address.
+/// Any packet the host sends from the address counts as an answer
+pub async fn request(
+	interface: &Interface,
+	source_ip: Ipv4Addr,
+	target_ip: Ipv4Addr,
+	timeout: Duration,
+) -> Option<MacAddr> {
+	let mut listener = ArpListener::open(interface).await?;
+	listener.send_request(source_ip, target_ip);
+	listener.wait_for_sender(target_ip, timeout).await
+}
+
+/// Looks up the MAC address of a host on the link, asking from an address of the interface in the same subnet
+pub async fn resolve(
+	interface: &Interface,
+	address: Ipv4Addr,
+	timeout: Duration,
+) -> Option<MacAddr> {
+	let source = interface
+		.get_ipv4_addresses()
+		.await
+		.into_iter()
+		.find(|(own, prefix)| {
+			let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+			u32::from(*own) & mask == u32::from(address) & mask
+		})
+		.map(|(own, _)| own)
+		.unwrap_or(Ipv4Addr::UNSPECIFIED);
+	request(interface, source, address, timeout).await
+}
 
-	tx.send_to(&ethernet_packet.to_immutable().packet(), Some(interface));
+/// Checks whether an address is in use by sending an ARP probe (RFC 5227) and waiting for an answer.
+/// A probe has an all zero sender address, so it does not pollute ARP caches
+pub async fn probe(interface: &Interface, address: Ipv4Addr, wait: Duration) -> bool {
+	request(interface, Ipv4Addr::UNSPECIFIED, address, wait)
+		.await
+		.is_some()
 }
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+
+use crate::config::AddressList;
+
+/// The built-in DNS forwarder, in the `dns` table
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DnsConfig {
+	/// Interfaces to answer queries on
+	pub interfaces: Vec<String>,
+	/// Upstream servers. Defaults to the ones learned by the DHCP and DHCPv6 clients
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub servers: Vec<String>,
+	/// Domain of the local names, e.g. `lan`. Queries for it are never forwarded
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub domain: Option<String>,
+	/// Answer the hostnames clients of the DHCP servers sent
+	#[serde_inline_default(true)]
+	pub dhcp_hostnames: bool,
+	/// Static host entries, e.g. `nas = "10.1.0.5"` or `nas = ["10.1.0.5", "fd00::5"]`
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	#[serde(default)]
+	pub hosts: BTreeMap<String, AddressList>,
+	/// Time to live of local answers in seconds
+	#[serde_inline_default(60)]
+	pub local_ttl: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ResolvConfMode {
+	/// netd writes the file itself, and puts back what it found once it has nothing left to write
+	File,
+	/// netd hands the configuration to `resolvconf`
+	Resolvconf,
+	/// netd leaves the resolver configuration alone
+	Disabled,
+}
+
+/// How the resolver configuration is written, in the `resolv_conf` table
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ResolvConfConfig {
+	#[serde_inline_default(ResolvConfMode::File)]
+	pub mode: ResolvConfMode,
+	#[serde_inline_default("/etc/resolv.conf".to_string())]
+	pub path: String,
+	/// Written as `options` lines, e.g. `rotate` or `timeout:2`
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub options: Vec<String>,
+}
+
+impl Default for ResolvConfConfig {
+	fn default() -> Self {
+		Self {
+			mode: ResolvConfMode::File,
+			path: "/etc/resolv.conf".to_string(),
+			options: vec![],
+		}
+	}
+}
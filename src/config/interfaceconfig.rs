@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use serde_inline_default::serde_inline_default;
 
@@ -5,7 +7,7 @@ use serde_inline_default::serde_inline_default;
 // Basic types
 //
 
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
 #[serde(rename_all = "camelCase")]
 pub enum InterfaceMode {
 	Static,
@@ -17,7 +19,7 @@ pub enum InterfaceMode {
 //
 
 #[serde_inline_default]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct GenericInterfaceConfig {
 	pub mode: InterfaceMode,
 	#[serde(skip_serializing_if = "Option::is_none")]
@@ -26,24 +28,643 @@ pub struct GenericInterfaceConfig {
 	pub netmask: Option<u8>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub gateway: Option<String>,
+	/// Duplicate address detection for the static address
+	#[serde(default)]
+	pub dad: DadConfig,
 	#[serde(skip_serializing_if = "std::ops::Not::not")]
 	#[serde_inline_default(false)]
 	pub do_failover: bool,
+	#[serde(default)]
+	pub failover: FailoverConfig,
 	#[serde(skip_serializing_if = "InterfaceDhcpConfig::is_disabled")]
 	#[serde(default)]
 	pub dhcp: InterfaceDhcpConfig,
+	/// Relay DHCP to other servers instead of running the DHCP server
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub dhcp_relay: Option<DhcpRelayConfig>,
+	#[serde(default)]
+	pub dhcp_client: DhcpClientConfig,
+	/// Run a DHCPv6 client on this interface
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub dhcpv6: Option<Dhcpv6ClientConfig>,
+	/// Assign a sub-prefix of a prefix delegated to another interface
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub delegated_prefix: Option<DelegatedPrefixConfig>,
+	/// Send IPv6 router advertisements on this interface
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub router_advertisement: Option<RouterAdvertisementConfig>,
+	/// How router advertisements received on this interface are handled
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub slaac: Option<SlaacConfig>,
+	/// DNS servers and search domains this interface contributes to resolv.conf
+	#[serde(default)]
+	pub resolver: ResolverConfig,
+	/// Virtual routers sharing addresses with other routers on the link
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub vrrp: Vec<VrrpConfig>,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictAction {
+	/// Leave the interface without the address
+	Refuse,
+	/// Try again until the address is free
+	Delay,
+}
+
+/// Checks that no other host uses the static address before assigning it (RFC 5227)
 #[serde_inline_default]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DadConfig {
+	#[serde_inline_default(true)]
+	pub enabled: bool,
+	#[serde_inline_default(ConflictAction::Delay)]
+	pub on_conflict: ConflictAction,
+	/// Seconds between attempts while the assignment is delayed
+	#[serde_inline_default(10)]
+	pub retry_interval: u64,
+	/// Keep announcing the address when another host claims it once it is assigned
+	#[serde_inline_default(true)]
+	pub defend: bool,
+}
+
+impl Default for DadConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			on_conflict: ConflictAction::Delay,
+			retry_interval: 10,
+			defend: true,
+		}
+	}
+}
+
+/// How `do_failover` behaves once netd took over from the other router
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FailoverConfig {
+	/// Give the address back and wait again when the other router returns
+	#[serde_inline_default(false)]
+	pub preempt: bool,
+	/// Seconds between checks whether the other router returned
+	#[serde_inline_default(2)]
+	pub interval: u64,
+	/// How the router that is already on the network is checked while waiting for it to fail.
+	/// Without targets the gateway of its DHCP lease is checked, with ARP unless another probe is set,
+	/// as ICMP is filtered on some networks
+	#[serde(default)]
+	pub health_check: HealthCheckConfig,
+}
+
+impl Default for FailoverConfig {
+	fn default() -> Self {
+		Self {
+			preempt: false,
+			interval: 2,
+			health_check: HealthCheckConfig::default(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum HealthProbe {
+	/// An ARP request, only for targets on the link
+	Arp,
+	Icmp,
+	/// A TCP connection, the targets include the port, e.g. `1.1.1.1:443`
+	Tcp,
+	/// A DNS query, any answer counts
+	Dns,
+}
+
+/// Decides whether something is reachable by probing one or more targets
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HealthCheckConfig {
+	/// What is checked decides the default, ARP for the failover router and ICMP otherwise
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub probe: Option<HealthProbe>,
+	/// The addresses to probe. What is checked decides what an empty list means
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub targets: Vec<String>,
+	/// Seconds between probes
+	#[serde_inline_default(2)]
+	pub interval: u64,
+	/// Milliseconds to wait for an answer
+	#[serde_inline_default(1000)]
+	pub timeout: u64,
+	/// Successful rounds in a row before something down counts as up again
+	#[serde_inline_default(2)]
+	pub rise: u32,
+	/// Failed rounds in a row before something up counts as down
+	#[serde_inline_default(3)]
+	pub fall: u32,
+	/// How many targets have to answer for a round to succeed
+	#[serde_inline_default(1)]
+	pub min_targets: usize,
+	/// The name asked for by DNS probes
+	#[serde_inline_default(".".to_string())]
+	pub query: String,
+}
+
+impl Default for HealthCheckConfig {
+	fn default() -> Self {
+		Self {
+			probe: None,
+			targets: vec![],
+			interval: 2,
+			timeout: 1000,
+			rise: 2,
+			fall: 3,
+			min_targets: 1,
+			query: ".".to_string(),
+		}
+	}
+}
+
+/// A VRRPv3 virtual router (RFC 5798)
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct VrrpConfig {
+	/// The virtual router id, the same on all routers sharing the addresses
+	pub vrid: u8,
+	/// The router with the highest priority becomes master, 255 means this router owns the addresses
+	#[serde_inline_default(100)]
+	pub priority: u8,
+	/// The shared addresses, e.g. `10.1.0.1/24`
+	pub addresses: Vec<String>,
+	/// Milliseconds between advertisements, in steps of 10
+	#[serde_inline_default(1000)]
+	pub advert_interval: u32,
+	/// Take over from a master with a lower priority
+	#[serde_inline_default(true)]
+	pub preempt: bool,
+	/// Answer for the addresses with the virtual router MAC address 00:00:5e:00:01:<vrid>,
+	/// so hosts don't have to update their ARP caches when the master changes
+	#[serde_inline_default(true)]
+	pub virtual_mac: bool,
+}
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ResolverConfig {
+	/// Static DNS servers, listed before the learned ones
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub servers: Vec<String>,
+	/// Static search domains
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub search: Vec<String>,
+	/// Use the servers and domains learned through DHCP, DHCPv6 and router advertisements
+	#[serde_inline_default(true)]
+	pub learned: bool,
+	/// Servers and domains of interfaces with a lower priority come first
+	#[serde_inline_default(100)]
+	pub priority: u32,
+}
+
+impl Default for ResolverConfig {
+	fn default() -> Self {
+		Self {
+			servers: vec![],
+			search: vec![],
+			learned: true,
+			priority: 100,
+		}
+	}
+}
+
+/// Parses a DHCP client identifier, either colon separated hex bytes (`01:aa:bb:cc:dd:ee:ff`) or a plain string
+pub fn client_id_bytes(client_id: &str) -> Vec<u8> {
+	let hex: Option<Vec<u8>> = client_id
+		.split(':')
+		.map(|byte| {
+			u8::from_str_radix(byte, 16)
+				.ok()
+				.filter(|_| byte.len() == 2)
+		})
+		.collect();
+	match hex {
+		Some(bytes) if client_id.contains(':') => bytes,
+		// Type 0 means the identifier is not a hardware address (RFC 2132 9.14)
+		_ => [&[0u8], client_id.as_bytes()].concat(),
+	}
+}
+
+/// Options for the DHCP client, used when `mode = "dhcp"`
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DhcpClientConfig {
+	/// Sent as option 12
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub hostname: Option<String>,
+	/// Sent as option 61. Either colon separated hex bytes (`01:aa:bb:cc:dd:ee:ff`) or a plain string.
+	/// Defaults to the hardware type and MAC address
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub client_id: Option<String>,
+	/// Sent as option 60
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub vendor_class: Option<String>,
+	/// Additional option codes to put into the parameter request list
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub request_options: Vec<u8>,
+	/// Address to ask for in the DISCOVER
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub requested_address: Option<String>,
+	#[serde_inline_default(true)]
+	pub use_router: bool,
+	#[serde_inline_default(true)]
+	pub use_dns: bool,
+	#[serde_inline_default(true)]
+	pub use_mtu: bool,
+	#[serde_inline_default(true)]
+	pub use_classless_routes: bool,
+}
+
+impl DhcpClientConfig {
+	/// The client identifier as sent on the wire
+	pub fn client_id_bytes(&self) -> Option<Vec<u8>> {
+		self.client_id.as_deref().map(client_id_bytes)
+	}
+}
+
+impl Default for DhcpClientConfig {
+	fn default() -> Self {
+		Self {
+			hostname: None,
+			client_id: None,
+			vendor_class: None,
+			request_options: vec![],
+			requested_address: None,
+			use_router: true,
+			use_dns: true,
+			use_mtu: true,
+			use_classless_routes: true,
+		}
+	}
+}
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Dhcpv6ClientConfig {
+	/// Request an address (IA_NA)
+	#[serde_inline_default(true)]
+	pub request_address: bool,
+	/// Request a delegated prefix (IA_PD)
+	#[serde_inline_default(false)]
+	pub request_prefix: bool,
+	/// Prefix length to ask the server for, e.g. 56
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub prefix_length_hint: Option<u8>,
+}
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DhcpRelayConfig {
+	/// Addresses of the DHCP servers requests are forwarded to
+	pub servers: Vec<String>,
+	/// Insert the relay agent information option (82)
+	#[serde_inline_default(true)]
+	pub agent_information: bool,
+	/// Sent as the circuit id sub-option, defaults to the interface name
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub circuit_id: Option<String>,
+	/// Sent as the remote id sub-option
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub remote_id: Option<String>,
+}
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DelegatedPrefixConfig {
+	/// The upstream interface the prefix is delegated to
+	pub from: String,
+	/// Which sub-prefix of the delegated prefix to use, 0 is the first one
+	#[serde_inline_default(0)]
+	pub subnet_id: u32,
+	#[serde_inline_default(64)]
+	pub prefix_length: u8,
+	/// The interface identifier of the address this interface gets in the sub-prefix
+	#[serde_inline_default("::1".to_string())]
+	pub interface_id: String,
+}
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RouterAdvertisementConfig {
+	/// Prefixes to advertise in addition to the delegated one, e.g. `2001:db8:1::/64`
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub prefixes: Vec<String>,
+	/// Advertise the sub-prefix assigned through `delegated_prefix`
+	#[serde_inline_default(true)]
+	pub delegated: bool,
+	/// Let hosts configure addresses in the prefixes themselves (SLAAC)
+	#[serde_inline_default(true)]
+	pub autonomous: bool,
+	/// Addresses are available through DHCPv6
+	#[serde_inline_default(false)]
+	pub managed: bool,
+	/// Other configuration is available through DHCPv6
+	#[serde_inline_default(false)]
+	pub other: bool,
+	/// Seconds hosts may use this router as default router, 0 means not a default router
+	#[serde_inline_default(1800)]
+	pub router_lifetime: u16,
+	#[serde_inline_default(86400)]
+	pub valid_lifetime: u32,
+	#[serde_inline_default(14400)]
+	pub preferred_lifetime: u32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub mtu: Option<u32>,
+	/// Recursive DNS servers (RDNSS)
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub dns: Vec<String>,
+	/// DNS search domains (DNSSL)
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub domains: Vec<String>,
+	/// Maximum seconds between unsolicited advertisements
+	#[serde_inline_default(600)]
+	pub interval: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum SlaacMode {
+	/// The kernel processes router advertisements, netd only records them
+	Kernel,
+	/// netd processes router advertisements and configures addresses and routes itself
+	Netd,
+	/// Router advertisements are ignored
+	Disabled,
+}
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SlaacConfig {
+	#[serde_inline_default(SlaacMode::Kernel)]
+	pub mode: SlaacMode,
+	/// Configure addresses in advertised prefixes
+	#[serde_inline_default(true)]
+	pub autoconf: bool,
+	/// Use the advertising routers as default routers
+	#[serde_inline_default(true)]
+	pub default_route: bool,
+	/// Use the advertised MTU
+	#[serde_inline_default(true)]
+	pub use_mtu: bool,
+}
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct InterfaceDhcpConfig {
 	#[serde_inline_default(false)]
 	pub enabled: bool,
+	/// The main pool, written directly in the `dhcp` table
+	#[serde(flatten)]
+	pub pool: DhcpPoolConfig,
+	/// Pools for secondary subnets, relayed subnets or specific client classes
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub pools: Vec<DhcpPoolConfig>,
+	/// Fixed addresses for specific clients
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub reservations: Vec<DhcpReservation>,
+	/// TFTP server network booting clients load the boot file from, sent as siaddr
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub next_server: Option<String>,
+	/// Boot file for clients no boot rule matches
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub boot_file: Option<String>,
+	/// Boot files for specific kinds of clients, the first matching rule is used
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub boot_rules: Vec<DhcpBootRule>,
+}
+
+/// A range of addresses and the options handed out with them.
+/// A pool whose subnet is not on the interface only serves requests relayed from that subnet
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DhcpPoolConfig {
+	#[serde(default)]
 	pub start: String,
+	#[serde(default)]
 	pub end: String,
-	pub dns: String,
-	pub router: String,
+	#[serde(default)]
 	pub netmask: String,
+	#[serde(default)]
+	pub router: String,
+	/// One DNS server or a list of them
+	#[serde(default)]
+	pub dns: AddressList,
+	/// Lease time in seconds
+	#[serde_inline_default(3600)]
+	pub lease_time: u32,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub ntp: Vec<String>,
+	/// Sent as option 15
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub domain: Option<String>,
+	/// Domain search list, sent as option 119
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub search: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub mtu: Option<u16>,
+	/// Classless static routes, sent as option 121
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub routes: Vec<DhcpRoute>,
+	/// Any other option, by number
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub options: Vec<DhcpOption>,
+	/// Only hand out addresses from this pool to matching clients.
+	/// Pools with a client class are preferred over pools without one
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub client_class: Option<DhcpClientClass>,
+}
+
+/// Selects clients by what they send. Every field that is set has to match, all accept `*` and `?` globs
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DhcpClientClass {
+	/// Vendor class from option 60
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub vendor_class: Option<String>,
+	/// User class from option 77
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub user_class: Option<String>,
+	/// MAC address like `00:11:22:*`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub mac: Option<String>,
+}
+
+/// Selects a boot file by client architecture and vendor class. Every field that is set has to match
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DhcpBootRule {
+	/// Client system architecture from option 93, e.g. 0 for BIOS and 7 for x64 UEFI
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub architecture: Option<u16>,
+	/// Vendor class from option 60, accepts `*` and `?` globs, e.g. `PXEClient:Arch:00007*`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub vendor_class: Option<String>,
+	/// Overrides the `next_server` of the interface
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub next_server: Option<String>,
+	pub boot_file: String,
+}
+
+/// A single address or a list of addresses
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum AddressList {
+	One(String),
+	List(Vec<String>),
+}
+
+impl Default for AddressList {
+	fn default() -> Self {
+		AddressList::List(vec![])
+	}
+}
+
+impl AddressList {
+	pub fn addresses(&self) -> Vec<String> {
+		match self {
+			// An empty string was how "no DNS server" used to be written
+			AddressList::One(address) if address.is_empty() => vec![],
+			AddressList::One(address) => vec![address.clone()],
+			AddressList::List(addresses) => addresses.clone(),
+		}
+	}
+}
+
+/// A route to push to clients, e.g. `destination = "10.20.0.0/16"`, `router = "10.10.99.254"`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DhcpRoute {
+	pub destination: String,
+	pub router: String,
+}
+
+/// How the value of a raw option is encoded
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DhcpOptionType {
+	/// One IPv4 address or a list of them
+	Address,
+	U8,
+	U16,
+	U32,
+	Bool,
+	String,
+	/// Colon separated hex bytes
+	Hex,
+}
+
+/// A raw DHCP option, e.g. `code = 66`, `type = "string"`, `value = "tftp.lan"`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DhcpOption {
+	pub code: u8,
+	#[serde(rename = "type")]
+	pub option_type: DhcpOptionType,
+	pub value: toml::Value,
+}
+
+/// A fixed address for a client, matched by MAC address or client identifier.
+/// The address must be in the subnet of a pool, but outside the range it hands out
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DhcpReservation {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub mac: Option<String>,
+	/// Colon separated hex bytes or a plain string, like `dhcp_client.client_id`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub client_id: Option<String>,
+	pub address: String,
+	/// Sent to the client as option 12
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub hostname: Option<String>,
+	/// Overrides the router of the interface for this client
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub router: Option<String>,
+	/// Overrides the DNS servers of the interface for this client
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub dns: Option<AddressList>,
+	/// Overrides the lease time of the pool, in seconds
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub lease_time: Option<u32>,
+	/// Overrides the NTP servers of the pool
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub ntp: Option<Vec<String>>,
+	/// Overrides the domain of the pool
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub domain: Option<String>,
+	/// Overrides the domain search list of the pool
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub search: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub mtu: Option<u16>,
+	/// Overrides the classless static routes of the pool
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub routes: Option<Vec<DhcpRoute>>,
+	/// Sent in addition to the options of the pool, replacing those with the same number
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub options: Vec<DhcpOption>,
+	/// Overrides the `next_server` of the interface and the boot rules for this client
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub next_server: Option<String>,
+	/// Overrides the boot file of the interface and the boot rules for this client
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub boot_file: Option<String>,
 }
 
 impl InterfaceDhcpConfig {
@@ -52,15 +673,22 @@ impl InterfaceDhcpConfig {
 	}
 }
 
-impl Default for InterfaceDhcpConfig {
+impl Default for DhcpPoolConfig {
 	fn default() -> Self {
 		Self {
-			enabled: false,
-			netmask: "".to_string(),
-			router: "".to_string(),
 			start: "".to_string(),
 			end: "".to_string(),
-			dns: "".to_string(),
+			netmask: "".to_string(),
+			router: "".to_string(),
+			dns: AddressList::default(),
+			lease_time: 3600,
+			ntp: vec![],
+			domain: None,
+			search: vec![],
+			mtu: None,
+			routes: vec![],
+			options: vec![],
+			client_class: None,
 		}
 	}
 }
@@ -74,6 +702,69 @@ impl Default for InterfaceDhcpConfig {
 pub struct EthernetConfig {
 	#[serde(flatten)]
 	pub generic: GenericInterfaceConfig,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub ethtool: Option<EthtoolConfig>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Duplex {
+	Half,
+	Full,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct EthtoolConfig {
+	/// Link speed in Mb/s, e.g. 1000
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub speed: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub duplex: Option<Duplex>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub autoneg: Option<bool>,
+	/// Offload features by their ethtool name (e.g. gro, tso, rx-checksumming)
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	#[serde(default)]
+	pub offloads: BTreeMap<String, bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub ring: Option<EthtoolRingConfig>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub pause: Option<EthtoolPauseConfig>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct EthtoolRingConfig {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub rx: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub rx_mini: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub rx_jumbo: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub tx: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct EthtoolPauseConfig {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub autoneg: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub rx: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub tx: Option<bool>,
 }
 
 #[serde_inline_default]
@@ -108,6 +799,14 @@ pub struct SharedInterfaceConfig {
 	#[serde(default)]
 	#[serde(skip_serializing_if = "Vec::is_empty")]
 	pub services: Vec<String>,
+	/// Do not fail if the interface does not exist, configure it once it appears
+	#[serde(default)]
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub optional: bool,
+	/// Seconds to wait for the interface to appear before giving up
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub wait_timeout: Option<u64>,
 }
 
 //
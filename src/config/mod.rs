@@ -1,7 +1,13 @@
+mod dnsconfig;
 mod interfaceconfig;
+mod renameconfig;
+mod uplinkconfig;
+pub use dnsconfig::*;
 pub use interfaceconfig::*;
+pub use renameconfig::*;
+pub use uplinkconfig::*;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde::{Deserialize, Serialize};
 use serde_inline_default::serde_inline_default;
@@ -9,9 +15,20 @@ use serde_inline_default::serde_inline_default;
 #[serde_inline_default]
 #[derive(Serialize, Deserialize)]
 pub struct Config {
-	#[serde_inline_default(HashMap::new())]
-	pub renames: HashMap<String, String>,
+	/// Applied in the order of their keys, so a link selected by two rules always gets the same name
+	#[serde_inline_default(BTreeMap::new())]
+	pub renames: BTreeMap<String, RenameRule>,
 	pub interfaces: HashMap<String, InterfaceConfig>,
+	/// The built-in DNS forwarder
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub dns: Option<DnsConfig>,
+	#[serde(default)]
+	pub resolv_conf: ResolvConfConfig,
+	/// Groups of uplinks whose default routes follow their health checks, by name
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	#[serde(default)]
+	pub uplinks: BTreeMap<String, UplinkGroupConfig>,
 }
 
 impl Config {
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// A rename entry in the `renames` table.
+/// Either the legacy `eth0 = "wan"` form, where the key is the kernel name and the value the new name,
+/// or a `[renames.wan]` table, where the key is the new name and the table selects the link
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum RenameRule {
+	Name(String),
+	Match(LinkMatch),
+}
+
+/// Selects a physical link by its properties instead of its kernel name.
+/// Every field that is set has to match. All fields except the MAC addresses accept `*` and `?` globs
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LinkMatch {
+	/// The current kernel name
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub name: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub mac: Option<String>,
+	/// The MAC address burnt into the NIC, useful when the current one is changed by bonding or failover
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub permanent_mac: Option<String>,
+	/// The kernel driver name, e.g. `igb` or `r8169`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub driver: Option<String>,
+	/// The PCI slot (`0000:03:00.0`) or the full sysfs device path
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub path: Option<String>,
+}
+
+impl LinkMatch {
+	pub fn is_empty(&self) -> bool {
+		self.name.is_none()
+			&& self.mac.is_none()
+			&& self.permanent_mac.is_none()
+			&& self.driver.is_none()
+			&& self.path.is_none()
+	}
+}
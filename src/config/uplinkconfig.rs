@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+
+use crate::config::HealthCheckConfig;
+
+/// Uplinks sharing the IPv4 default route, in the `uplinks` table
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UplinkGroupConfig {
+	pub members: Vec<UplinkMemberConfig>,
+	/// Metric of the default route. Each member also keeps a default route of its own with the
+	/// following metrics, in the order listed, used by its health check and when all members are down
+	#[serde_inline_default(0)]
+	pub metric: u32,
+}
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UplinkMemberConfig {
+	pub interface: String,
+	/// Defaults to the static gateway of the interface or the router of its DHCP lease
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub gateway: Option<String>,
+	/// Only the members with the lowest priority that are up carry traffic
+	#[serde_inline_default(100)]
+	pub priority: u32,
+	/// Share of the traffic among members of the same priority, from 1 to 256
+	#[serde_inline_default(1)]
+	pub weight: u16,
+	/// Without targets the gateway is checked
+	#[serde(default)]
+	pub health_check: HealthCheckConfig,
+}
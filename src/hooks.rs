@@ -1,4 +1,9 @@
 pub fn run_hook(hook: String) {
+	run_hook_with_env(hook, &[]);
+}
+
+/// Runs a hook with additional environment variables, e.g. the details of a DHCP lease
+pub fn run_hook_with_env(hook: String, env: &[(String, String)]) {
 	let path = format!("/etc/config/network/hooks/{}", hook);
 	let path = path.as_str();
 	// Check if the hook exists
@@ -7,6 +12,7 @@ pub fn run_hook(hook: String) {
 	}
 	// Run the hook (the shebang will determine how it's run)
 	let output = std::process::Command::new(path)
+		.envs(env.iter().map(|(key, value)| (key, value)))
 		.output()
 		.expect("Failed to run hook");
 	if !output.status.success() {
@@ -1,11 +1,11 @@
 use crate::{config::BridgeConfig, hooks::run_hook, link::interface::Interface};
 
-use super::generic;
+use super::generic::{self, ConfigureStatus};
 
 pub struct BridgeInterface {}
 
 impl BridgeInterface {
-	pub async fn configure(ifname: &String, ifconfig: &BridgeConfig) {
+	pub async fn configure(ifname: &String, ifconfig: &BridgeConfig) -> ConfigureStatus {
 		let interface = Interface::create(ifname, "bridge").await;
 		run_hook(format!("pre-up.{ifname}"));
 		interface.up().await;
@@ -15,12 +15,13 @@ impl BridgeInterface {
 		for member in ifconfig.interfaces.iter() {
 			let member_interface = Interface::get_from_name(member);
 			if !member_interface.exists().await {
-				panic!("[{ifname}] Subinterface {member} does not exist!");
+				println!("[{ifname}] Subinterface {member} does not exist, it will be added when it appears");
+				continue;
 			}
 			member_interface.up().await;
 			member_interface.set_master(ifname).await;
 		}
 
-		generic::generic_configuration(&ifconfig.generic, &interface).await;
+		generic::generic_configuration(&ifconfig.generic, &interface).await
 	}
 }
@@ -1,17 +1,24 @@
 // use tokio::process::Command;
 
-use crate::{config::EthernetConfig, hooks::run_hook, link::interface::Interface};
+use crate::{
+	config::EthernetConfig,
+	hooks::run_hook,
+	link::{ethtool, interface::Interface},
+};
 
-use super::generic;
+use super::generic::{self, ConfigureStatus};
 
 pub struct EthernetInterface {}
 
 impl EthernetInterface {
-	pub async fn configure(interface: &Interface, ifconfig: &EthernetConfig) {
+	pub async fn configure(interface: &Interface, ifconfig: &EthernetConfig) -> ConfigureStatus {
 		let ifname = interface.name.clone();
 		run_hook(format!("pre-up.{ifname}"));
 		interface.up().await;
 		run_hook(format!("post-up.{ifname}"));
-		generic::generic_configuration(&ifconfig.generic, interface).await;
+		if let Some(ethtool_config) = &ifconfig.ethtool {
+			ethtool::apply(interface, ethtool_config).await;
+		}
+		generic::generic_configuration(&ifconfig.generic, interface).await
 	}
 }
@@ -1,34 +1,125 @@
-use crate::link::{dhcpc::dhcp_client, interface::Interface};
+use std::{collections::BTreeMap, net::Ipv4Addr, str::FromStr, sync::Mutex, time::Duration};
 
-pub async fn failover(interface: &Interface, ifname: &String) -> bool {
+use tokio::task::JoinHandle;
+
+use crate::{
+	arp::{self, ArpListener},
+	config::{DhcpClientConfig, FailoverConfig, GenericInterfaceConfig, HealthProbe},
+	hooks::run_hook,
+	interface::generic,
+	link::{
+		dad,
+		dhcpc::{self, dhcp_client},
+		dhcpd, dhcrelay,
+		health::HealthCheck,
+		interface::Interface,
+		radv,
+	},
+};
+
+/// How long the returned router gets to assign and announce the address before it is checked again
+const RETURN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interfaces that took over from another router and watch for it to return, by interface name
+static WATCHERS: Mutex<BTreeMap<String, JoinHandle<()>>> = Mutex::new(BTreeMap::new());
+
+pub async fn failover(
+	interface: &Interface,
+	ifname: &String,
+	dhcp_client_config: &DhcpClientConfig,
+	failover_config: &FailoverConfig,
+) -> bool {
 	let mut failover_reconfigured = false;
 	interface.set_description("FAILOVER_PROBING").await;
 	println!("[{ifname}] Probing for existing router on network");
-	let result = dhcp_client(interface, true).await;
+	let lease = dhcp_client(interface, true, dhcp_client_config).await;
 	// If the DHCP client failed, no router is on this network, so we should continue configuring the interface like normal
-	// If the DHCP client succeeded, we should not configure the interface further and start pinging the router until it stops responding
+	// If the DHCP client succeeded, we should not configure the interface further and start probing the router until it stops responding
 	//   thats when we should start configuring the interface like normal
-	if result {
+	if let Some(gateway) = lease.and_then(|lease| lease.router) {
 		interface.set_description("FAILOVER_WAITING").await;
 		println!("[{ifname}] Router already on this network, failover mode enabled");
-		// Get the gateway IP for this interface
-		let gateway = interface.get_gateway().await;
-		println!("[{ifname}] Detected gateway: {}", gateway);
-		let ping_payload = [0; 8];
+		match arp::resolve(interface, gateway, Duration::from_secs(1)).await {
+			Some(mac) => println!("[{ifname}] Detected gateway: {gateway} at {mac}"),
+			None => println!("[{ifname}] Detected gateway: {gateway}"),
+		}
+		router_health_check(interface, failover_config, gateway)
+			.wait_for(false)
+			.await;
+		println!("[{ifname}] Router is down, beginning normal configuration");
+		interface.flush_addresses().await;
+		dhcpc::forget_lease(ifname);
+		failover_reconfigured = true;
+	} else {
+		println!("[{ifname}] No router found on this network, beginning normal configuration");
+	}
+	failover_reconfigured
+}
+
+/// Checks the router with ARP, unless another probe is configured, starting out up
+fn router_health_check(
+	interface: &Interface,
+	config: &FailoverConfig,
+	router: Ipv4Addr,
+) -> HealthCheck {
+	let mut health_check = config.health_check.clone();
+	health_check.probe.get_or_insert(HealthProbe::Arp);
+	HealthCheck::new(interface, &health_check, &[router.into()], true)
+}
+
+/// Keeps checking whether the router netd took over from returned, by probing for the address with ARP.
+/// When it did, the address is given back and the interface waits for the router to fail again
+pub fn watch_for_router(interface: &Interface, ifconfig: &GenericInterfaceConfig) {
+	let ifname = interface.name.clone();
+	let Some(address) = ifconfig
+		.address
+		.as_deref()
+		.and_then(|address| Ipv4Addr::from_str(address).ok())
+	else {
+		println!("[{ifname}] Failover preemption requires a static address");
+		return;
+	};
+	let ifconfig = ifconfig.clone();
+	let interval = Duration::from_secs(ifconfig.failover.interval.max(1));
+	let task = tokio::spawn(async move {
+		let interface = Interface::get_from_name(&ifname);
+		let Some(mut listener) = ArpListener::open(&interface).await else {
+			return;
+		};
+		// The router answers the probes once it has the address, and probes for it itself before assigning it
 		loop {
-			let ping = surge_ping::ping(gateway.parse().unwrap(), &ping_payload).await;
-			if ping.is_err() {
-				println!("[{ifname}] Router is down, beginning normal configuration");
-				interface.flush_addresses().await;
-				failover_reconfigured = true;
+			listener.send_request(Ipv4Addr::UNSPECIFIED, address);
+			if listener.wait_for_claim(address, interval).await.is_some() {
 				break;
-			} else {
-				println!("[{ifname}] Router is still up, continuing failover mode");
-				tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 			}
 		}
-	} else {
-		println!("[{ifname}] No router found on this network, beginning normal configuration");
+		println!("[{ifname}] Router returned, giving back {address}");
+		dad::stop(&interface);
+		dhcpd::stop_server(&interface).await;
+		dhcrelay::stop_relay(&interface).await;
+		radv::stop(&interface).await;
+		interface.flush_addresses().await;
+		interface.set_description("FAILOVER_WAITING").await;
+		run_hook(format!("post-failback.{ifname}"));
+		// A router that found the address in use only assigns it when it tries again. Probing meanwhile
+		// would look like another host claiming the address, so its announcement is awaited quietly
+		let _ = listener.wait_for_sender(address, RETURN_TIMEOUT).await;
+		drop(listener);
+		router_health_check(&interface, &ifconfig.failover, address)
+			.wait_for(false)
+			.await;
+		println!("[{ifname}] Router is down again, taking over");
+		generic::apply_configuration(&ifconfig, &interface, true).await;
+	});
+	WATCHERS
+		.lock()
+		.unwrap()
+		.insert(interface.name.clone(), task);
+}
+
+/// Stops watching for the router to return
+pub fn stop(interface: &Interface) {
+	if let Some(task) = WATCHERS.lock().unwrap().remove(&interface.name) {
+		task.abort();
 	}
-	return failover_reconfigured;
 }
@@ -1,86 +1,202 @@
 use std::{net::Ipv4Addr, str::FromStr};
 
 use pnet::{packet::arp::ArpOperations, util::MacAddr};
+use tokio::sync::oneshot;
 
 use crate::{
-	arp::send_arp_packet,
-	config::{GenericInterfaceConfig, InterfaceMode},
+	arp::{ArpListener, ArpMessage},
+	config::{ConflictAction, GenericInterfaceConfig, InterfaceMode},
 	hooks::run_hook,
 	interface::failover,
-	link::{dhcpc::dhcp_client, dhcpd, interface::Interface, routing},
+	link::{
+		dad, dhcp6c, dhcpc::dhcp_client, dhcpd, dhcrelay, interface::Interface, radv, resolver,
+		routing, slaac, uplink, vrrp,
+	},
 };
 
-pub async fn generic_configuration(ifconfig: &GenericInterfaceConfig, interface: &Interface) {
+/// Whether the interface got its address, so the services that need it can be started
+pub enum ConfigureStatus {
+	Configured,
+	/// Another host uses the static address, so it was not assigned
+	Refused,
+	/// The static address is assigned once it is free. Nothing is sent if the retry is stopped first
+	Delayed(oneshot::Receiver<()>),
+}
+
+pub async fn generic_configuration(
+	ifconfig: &GenericInterfaceConfig,
+	interface: &Interface,
+) -> ConfigureStatus {
 	let ifname = interface.name.clone();
+	resolver::add_interface(&ifname, &ifconfig.resolver, ifconfig.dhcp_client.use_dns);
 	let mut failover_reconfigured = false;
 	if ifconfig.do_failover {
-		failover_reconfigured = failover::failover(interface, &ifname).await;
+		failover_reconfigured = failover::failover(
+			interface,
+			&ifname,
+			&ifconfig.dhcp_client,
+			&ifconfig.failover,
+		)
+		.await;
 		run_hook(format!("post-failover.{ifname}"));
 	}
+	apply_configuration(ifconfig, interface, failover_reconfigured).await
+}
+
+/// Configures addresses and starts the services of the interface.
+/// `failover_reconfigured` means the interface takes over from another router
+pub async fn apply_configuration(
+	ifconfig: &GenericInterfaceConfig,
+	interface: &Interface,
+	failover_reconfigured: bool,
+) -> ConfigureStatus {
+	let ifname = interface.name.clone();
 	interface.set_description("CONFIGURING").await;
 	if ifconfig.mode == InterfaceMode::Dhcp {
 		println!("[{ifname}] Obtaining DHCP lease");
-		let result = dhcp_client(interface, false).await;
-		if !result {
-			println!("[{ifname}] Could not get DHCP lease");
-		} else {
-			println!("[{ifname}] Got DHCP lease"); // TODO: show the IP address
+		match dhcp_client(interface, false, &ifconfig.dhcp_client).await {
+			Some(lease) => println!(
+				"[{ifname}] Got DHCP lease: {}/{}",
+				lease.address, lease.prefix
+			),
+			None => println!("[{ifname}] Could not get DHCP lease"),
 		}
 	} else {
 		if ifconfig.address.is_none() || ifconfig.netmask.is_none() {
 			panic!("[{ifname}] Static interface configuration requires an address and a netmask");
 		}
-		interface
-			.add_address(
-				&ifconfig.address.as_ref().unwrap(),
-				ifconfig.netmask.unwrap(),
-			)
-			.await;
+		let address = ifconfig.address.as_ref().unwrap();
+		match Ipv4Addr::from_str(address) {
+			Ok(parsed) if ifconfig.dad.enabled && !dad::claim(interface, parsed).await => {
+				// Neither the gateway nor the services work without the address
+				interface.set_description("ADDRESS_CONFLICT").await;
+				if ifconfig.dad.on_conflict == ConflictAction::Refuse {
+					println!(
+						"[{ifname}] Not assigning {address}, leaving the interface unconfigured"
+					);
+					return ConfigureStatus::Refused;
+				}
+				let retried = ifconfig.clone();
+				let (assigned, status) = oneshot::channel();
+				dad::retry(interface, parsed, &ifconfig.dad, async move {
+					let interface = Interface::get_from_name(&ifname);
+					interface.set_description("CONFIGURING").await;
+					add_static_address(&retried, &interface).await;
+					finish_configuration(&retried, &interface, failover_reconfigured).await;
+					let _ = assigned.send(());
+				});
+				return ConfigureStatus::Delayed(status);
+			}
+			_ => add_static_address(ifconfig, interface).await,
+		}
 	}
 
-	if ifconfig.gateway.is_some() {
-		routing::add_route_via(ifconfig.gateway.as_ref().unwrap().as_str(), "default").await;
+	finish_configuration(ifconfig, interface, failover_reconfigured).await;
+	ConfigureStatus::Configured
+}
+
+/// Assigns the static address, announcing and defending it when duplicate address detection is enabled
+async fn add_static_address(ifconfig: &GenericInterfaceConfig, interface: &Interface) {
+	let address = ifconfig.address.as_ref().unwrap();
+	interface
+		.add_address(address, ifconfig.netmask.unwrap())
+		.await;
+	if let (true, Ok(parsed)) = (ifconfig.dad.enabled, Ipv4Addr::from_str(address)) {
+		dad::announce(interface, parsed, &ifconfig.dad).await;
+	}
+}
+
+/// Adds the default route and starts the services of the interface once it has its address
+async fn finish_configuration(
+	ifconfig: &GenericInterfaceConfig,
+	interface: &Interface,
+	failover_reconfigured: bool,
+) {
+	let ifname = interface.name.clone();
+	if let Some(gateway) = &ifconfig.gateway {
+		if uplink::is_member(&ifname) {
+			uplink::set_gateway(&ifname, gateway);
+		} else {
+			routing::add_route_via(gateway.as_str(), "default").await;
+		}
+	}
+
+	if !ifconfig.vrrp.is_empty() {
+		vrrp::start(interface, &ifconfig.vrrp).await;
+	}
+
+	if let Some(slaac) = &ifconfig.slaac {
+		slaac::start(interface, slaac).await;
+	}
+	if let Some(dhcpv6) = &ifconfig.dhcpv6 {
+		println!("[{ifname}] Starting DHCPv6 client");
+		dhcp6c::start_client(interface, dhcpv6).await;
+	}
+	if let Some(delegated_prefix) = &ifconfig.delegated_prefix {
+		println!(
+			"[{ifname}] Using a prefix delegated to {}",
+			delegated_prefix.from
+		);
+		dhcp6c::add_downstream(&ifname, delegated_prefix).await;
 	}
 
 	println!("[{ifname}] DHCP: {:?}", ifconfig.dhcp.enabled);
 
-	if ifconfig.dhcp.enabled {
-		// Run a DHCP Server
-		// println!("WARNING: DHCP Server not fully implemented yet! Running dhcpd service");
-		// let mut status = Command::new("rc-service")
-		// 	.arg("dhcpd")
-		// 	.arg("start")
-		// 	.spawn()
-		// 	.expect("Failed to start service!");
+	if let Some(dhcp_relay) = &ifconfig.dhcp_relay {
+		if ifconfig.dhcp.enabled {
+			println!("[{ifname}] DHCP relay is configured, not starting the DHCP server");
+		}
+		let address = ifconfig
+			.address
+			.as_deref()
+			.and_then(|address| Ipv4Addr::from_str(address).ok());
+		match address {
+			Some(address) => dhcrelay::start_relay(interface, address, dhcp_relay).await,
+			None => println!("[{ifname}] DHCP relay requires a static address"),
+		}
+	} else if ifconfig.dhcp.enabled {
 		run_hook(format!("pre-dhcp-server.{ifname}"));
-		let dhcpserver = dhcpd::DHCPServer::new(
-			ifconfig.dhcp.start.clone(),
-			ifconfig.dhcp.end.clone(),
-			ifname.clone(),
-			ifconfig.dhcp.dns.clone(),
-			ifconfig.dhcp.netmask.clone(),
-			ifconfig.dhcp.router.clone(),
-			3600,
-		);
-		dhcpserver.start();
+		// Answer from the interface's own address, which usually is the router handed out
+		let server_address = ifconfig
+			.address
+			.as_deref()
+			.or(Some(ifconfig.dhcp.pool.router.as_str()))
+			.and_then(|address| Ipv4Addr::from_str(address).ok());
+		match server_address {
+			Some(server_address) => {
+				dhcpd::start_server(interface, server_address, &ifconfig.dhcp).await
+			}
+			None => println!("[{ifname}] DHCP server requires a static address"),
+		}
 		run_hook(format!("post-dhcp-server.{ifname}"));
 	}
+
+	if let Some(router_advertisement) = &ifconfig.router_advertisement {
+		println!("[{ifname}] Sending router advertisements");
+		radv::start(interface, router_advertisement).await;
+	}
 	interface.set_description("CONFIGURED").await;
 	run_hook(format!("post-configure.{ifname}"));
 
-	if failover_reconfigured && ifconfig.address.is_some() {
-		for _ in 0..3 {
-			println!("[{ifname}] Sending gratuitous ARP packet");
-			send_arp_packet(
-				interface,
-				Ipv4Addr::from_str(&ifconfig.address.as_ref().unwrap()).unwrap(),
-				MacAddr::from_str(&interface.get_mac().await).unwrap(),
-				Ipv4Addr::from_str(&ifconfig.address.as_ref().unwrap()).unwrap(),
-				MacAddr::from_str("ff:ff:ff:ff:ff:ff").unwrap(),
-				ArpOperations::Request,
-			);
-			tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+	if let (true, Some(address)) = (failover_reconfigured, &ifconfig.address) {
+		let address = Ipv4Addr::from_str(address).ok();
+		if let (Some(address), Some(mut listener)) = (address, ArpListener::open(interface).await) {
+			let announcement = ArpMessage {
+				operation: ArpOperations::Request,
+				sender_mac: listener.mac,
+				sender_ip: address,
+				target_mac: MacAddr::broadcast(),
+				target_ip: address,
+			};
+			for _ in 0..3 {
+				println!("[{ifname}] Sending gratuitous ARP packet");
+				listener.send(&announcement, MacAddr::broadcast());
+				tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+			}
 		}
 		run_hook(format!("post-garp.{ifname}"));
+		if ifconfig.failover.preempt {
+			failover::watch_for_router(interface, ifconfig);
+		}
 	}
 }
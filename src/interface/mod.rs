@@ -2,3 +2,4 @@ pub mod bridge;
 pub mod ethernet;
 pub mod failover;
 pub mod generic;
+pub mod rename;
@@ -0,0 +1,152 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::Mutex,
+};
+
+use crate::{
+	config::RenameRule,
+	link::{
+		interface::Interface,
+		matching::{find_link, MatchError},
+	},
+	state,
+};
+
+/// Kernel names of links renamed by match rules, keyed by their new name, so reset can restore them.
+/// Saved to disk, as the kernel name is lost once the link is renamed and netd may restart in between
+static ORIGINAL_NAMES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+const STATE_NAME: &str = "renames";
+
+/// Adds the names saved by a previous run, entries of this run take precedence
+fn load_original_names() {
+	let saved: BTreeMap<String, String> = state::load(STATE_NAME).unwrap_or_default();
+	let mut names = ORIGINAL_NAMES.lock().unwrap();
+	for (new_name, old_name) in saved {
+		names.entry(new_name).or_insert(old_name);
+	}
+}
+
+fn save_original_names() {
+	let names = ORIGINAL_NAMES.lock().unwrap().clone();
+	if names.is_empty() {
+		state::remove(STATE_NAME);
+	} else {
+		state::save(STATE_NAME, &names);
+	}
+}
+
+/// Resolves a rename entry to the current name of the link and the name it should get
+pub async fn resolve(key: &str, rule: &RenameRule) -> Result<(String, String), MatchError> {
+	match rule {
+		RenameRule::Name(new_name) => {
+			if Interface::get_from_name(key).exists().await {
+				Ok((key.to_string(), new_name.clone()))
+			} else if Interface::get_from_name(new_name).exists().await {
+				// Already renamed, e.g. by a previous run that was not reset
+				Ok((new_name.clone(), new_name.clone()))
+			} else {
+				Err(MatchError::NotFound)
+			}
+		}
+		RenameRule::Match(link_match) => {
+			let old_name = find_link(link_match).await?;
+			Ok((old_name, key.to_string()))
+		}
+	}
+}
+
+/// Returns false if the kernel refused the new name, e.g. because it is taken
+async fn rename_link(old_name: &str, new_name: &str, rule: &RenameRule) -> bool {
+	if old_name == new_name {
+		return true;
+	}
+	println!("[renames] Renaming {old_name} to {new_name}");
+	let mut interface = Interface::get_from_name(old_name);
+	if let Err(err) = interface.rename(new_name).await {
+		println!("[renames] Could not rename {old_name} to {new_name}: {err}");
+		return false;
+	}
+	if let RenameRule::Match(_) = rule {
+		ORIGINAL_NAMES
+			.lock()
+			.unwrap()
+			.insert(new_name.to_string(), old_name.to_string());
+		save_original_names();
+	}
+	true
+}
+
+/// Renames a link that just appeared if any rule selects it.
+/// Returns the new name if it was renamed
+pub async fn rename_if_matches(
+	name: &str,
+	renames: &BTreeMap<String, RenameRule>,
+) -> Option<String> {
+	for (key, rule) in renames {
+		if let Ok((old_name, new_name)) = resolve(key, rule).await {
+			if old_name == name && new_name != name {
+				return rename_link(&old_name, &new_name, rule)
+					.await
+					.then_some(new_name);
+			}
+		}
+	}
+	None
+}
+
+/// Renames all links. Rules that are missing, ambiguous or select a link already claimed by another rule are skipped
+pub async fn apply_renames(renames: &BTreeMap<String, RenameRule>) {
+	println!("Renaming {} interfaces!", renames.len());
+	load_original_names();
+	let mut claimed: HashMap<String, String> = HashMap::new();
+	for (key, rule) in renames {
+		let (old_name, new_name) = match resolve(key, rule).await {
+			Ok(names) => names,
+			Err(err) => {
+				println!("[renames] Could not rename {key}: {err}");
+				continue;
+			}
+		};
+		if let Some(other) = claimed.get(&old_name) {
+			println!("[renames] Could not rename {key}: {old_name} is already matched by {other}");
+			continue;
+		}
+		if rename_link(&old_name, &new_name, rule).await {
+			claimed.insert(old_name, key.clone());
+		}
+	}
+}
+
+/// Restores the names links had before they were renamed
+pub async fn revert_renames(renames: &BTreeMap<String, RenameRule>) {
+	println!("Renaming {} interfaces!", renames.len());
+	load_original_names();
+	for (key, rule) in renames {
+		let (new_name, old_name) = match rule {
+			RenameRule::Name(new_name) => (new_name.clone(), key.clone()),
+			RenameRule::Match(_) => {
+				let Some(old_name) = ORIGINAL_NAMES.lock().unwrap().get(key).cloned() else {
+					continue;
+				};
+				(key.clone(), old_name)
+			}
+		};
+		let mut interface = Interface::get_from_name(&new_name);
+		if !interface.exists().await {
+			println!("Interface {new_name} does not exist, ignoring");
+			ORIGINAL_NAMES.lock().unwrap().remove(&new_name);
+			continue;
+		}
+		// Kept on failure, so the next reset can try again
+		match interface.rename(&old_name).await {
+			Ok(()) => {
+				ORIGINAL_NAMES.lock().unwrap().remove(&new_name);
+			}
+			Err(err) => {
+				println!("[renames] Could not rename {new_name} back to {old_name}: {err}")
+			}
+		}
+	}
+	save_original_names();
+}
@@ -0,0 +1,182 @@
+use std::{
+	collections::BTreeMap,
+	future::Future,
+	net::Ipv4Addr,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use pnet::util::MacAddr;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+use crate::{
+	arp::ArpListener, config::DadConfig, hooks::run_hook_with_env, link::interface::Interface,
+};
+
+/// Protocol constants (RFC 5227 1.1)
+const PROBE_WAIT: Duration = Duration::from_secs(1);
+const PROBE_NUM: u32 = 3;
+const PROBE_MIN: Duration = Duration::from_secs(1);
+const PROBE_MAX: Duration = Duration::from_secs(2);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+const ANNOUNCE_NUM: u32 = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Another host found using an address of the interface
+#[derive(Serialize, Clone)]
+pub struct AddressConflict {
+	pub address: Ipv4Addr,
+	pub mac: String,
+}
+
+/// The last conflict of each interface, by interface name
+static CONFLICTS: Mutex<BTreeMap<String, AddressConflict>> = Mutex::new(BTreeMap::new());
+/// Interfaces defending their address, by interface name
+static DEFENDERS: Mutex<BTreeMap<String, JoinHandle<()>>> = Mutex::new(BTreeMap::new());
+/// Interfaces waiting for their address to become free, by interface name
+static RETRIES: Mutex<BTreeMap<String, JoinHandle<()>>> = Mutex::new(BTreeMap::new());
+
+pub fn get_conflict(ifname: &str) -> Option<AddressConflict> {
+	CONFLICTS.lock().unwrap().get(ifname).cloned()
+}
+
+fn conflict(ifname: &str, address: Ipv4Addr, mac: MacAddr) {
+	CONFLICTS.lock().unwrap().insert(
+		ifname.to_string(),
+		AddressConflict {
+			address,
+			mac: mac.to_string(),
+		},
+	);
+	run_hook_with_env(
+		format!("address-conflict.{ifname}"),
+		&[
+			("CONFLICT_INTERFACE".to_string(), ifname.to_string()),
+			("CONFLICT_ADDRESS".to_string(), address.to_string()),
+			("CONFLICT_MAC".to_string(), mac.to_string()),
+		],
+	);
+}
+
+/// Probes for the address, returning the MAC address of a host that claims it
+async fn probe(interface: &Interface, address: Ipv4Addr) -> Option<MacAddr> {
+	let mut listener = ArpListener::open(interface).await?;
+	let wait = thread_rng().gen_range(Duration::ZERO..PROBE_WAIT);
+	if let Some(mac) = listener.wait_for_claim(address, wait).await {
+		return Some(mac);
+	}
+	for probe in 1..=PROBE_NUM {
+		listener.send_request(Ipv4Addr::UNSPECIFIED, address);
+		let wait = if probe == PROBE_NUM {
+			ANNOUNCE_WAIT
+		} else {
+			thread_rng().gen_range(PROBE_MIN..=PROBE_MAX)
+		};
+		if let Some(mac) = listener.wait_for_claim(address, wait).await {
+			return Some(mac);
+		}
+	}
+	None
+}
+
+/// Checks that no other host uses the address before it is assigned
+pub async fn claim(interface: &Interface, address: Ipv4Addr) -> bool {
+	let ifname = &interface.name;
+	match probe(interface, address).await {
+		Some(mac) => {
+			println!("[{ifname}] {address} is already used by {mac}");
+			conflict(ifname, address, mac);
+			false
+		}
+		None => {
+			CONFLICTS.lock().unwrap().remove(ifname);
+			true
+		}
+	}
+}
+
+/// Probes for the address in the background every `retry_interval`, and runs `assign` once it is free
+pub fn retry<F>(interface: &Interface, address: Ipv4Addr, config: &DadConfig, assign: F)
+where
+	F: Future<Output = ()> + Send + 'static,
+{
+	let ifname = interface.name.clone();
+	let interval = Duration::from_secs(config.retry_interval.max(1));
+	println!(
+		"[{ifname}] Trying {address} again in {}s",
+		interval.as_secs()
+	);
+	let task = tokio::spawn(async move {
+		let interface = Interface::get_from_name(&ifname);
+		loop {
+			tokio::time::sleep(interval).await;
+			if claim(&interface, address).await {
+				break;
+			}
+		}
+		println!("[{ifname}] {address} is free now");
+		assign.await;
+	});
+	if let Some(previous) = RETRIES.lock().unwrap().insert(interface.name.clone(), task) {
+		previous.abort();
+	}
+}
+
+/// Announces the newly assigned address and, if configured,
+/// keeps defending it against other hosts claiming it (RFC 5227 2.3, 2.4)
+pub async fn announce(interface: &Interface, address: Ipv4Addr, config: &DadConfig) {
+	if let Some(task) = DEFENDERS.lock().unwrap().remove(&interface.name) {
+		task.abort();
+	}
+	let Some(mut listener) = ArpListener::open(interface).await else {
+		return;
+	};
+	let ifname = interface.name.clone();
+	let defend = config.defend;
+	let task = tokio::spawn(async move {
+		for announcement in 0..ANNOUNCE_NUM {
+			if announcement > 0 {
+				tokio::time::sleep(ANNOUNCE_INTERVAL).await;
+			}
+			listener.send_request(address, address);
+		}
+		if !defend {
+			return;
+		}
+		// Defends indefinitely, as a router can't give up its address, but at most once per interval
+		let mut defended: Option<Instant> = None;
+		while let Some(message) = listener.recv().await {
+			if message.sender_ip != address || message.sender_mac == listener.mac {
+				continue;
+			}
+			if defended.is_some_and(|defended| defended.elapsed() < DEFEND_INTERVAL) {
+				continue;
+			}
+			println!(
+				"[{ifname}] {address} is claimed by {}, defending it",
+				message.sender_mac
+			);
+			conflict(&ifname, address, message.sender_mac);
+			listener.send_request(address, address);
+			defended = Some(Instant::now());
+		}
+	});
+	DEFENDERS
+		.lock()
+		.unwrap()
+		.insert(interface.name.clone(), task);
+}
+
+/// Stops waiting for and defending the address of the interface and forgets its conflicts
+pub fn stop(interface: &Interface) {
+	if let Some(task) = DEFENDERS.lock().unwrap().remove(&interface.name) {
+		task.abort();
+	}
+	if let Some(task) = RETRIES.lock().unwrap().remove(&interface.name) {
+		task.abort();
+	}
+	CONFLICTS.lock().unwrap().remove(&interface.name);
+}
@@ -0,0 +1,552 @@
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use crate::link::vrrp::checksum;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const PROTOCOL_UDP: u8 = 17;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Size of the fixed BOOTP header, including the magic cookie
+const HEADER_LEN: usize = 240;
+
+pub const BOOTREQUEST: u8 = 1;
+pub const BOOTREPLY: u8 = 2;
+/// Asks the server to broadcast its reply, as we can't receive unicast before having an address
+pub const FLAG_BROADCAST: u16 = 0x8000;
+
+/// DHCP option codes (RFC 2132 and friends)
+pub mod options {
+	pub const PAD: u8 = 0;
+	pub const SUBNET_MASK: u8 = 1;
+	pub const ROUTER: u8 = 3;
+	pub const DNS: u8 = 6;
+	pub const HOSTNAME: u8 = 12;
+	pub const DOMAIN_NAME: u8 = 15;
+	pub const MTU: u8 = 26;
+	pub const NTP: u8 = 42;
+	pub const REQUESTED_ADDRESS: u8 = 50;
+	pub const LEASE_TIME: u8 = 51;
+	pub const MESSAGE_TYPE: u8 = 53;
+	pub const SERVER_ID: u8 = 54;
+	pub const PARAMETER_REQUEST_LIST: u8 = 55;
+	pub const MESSAGE: u8 = 56;
+	pub const RENEWAL_TIME: u8 = 58;
+	pub const REBINDING_TIME: u8 = 59;
+	pub const VENDOR_CLASS: u8 = 60;
+	pub const CLIENT_ID: u8 = 61;
+	pub const USER_CLASS: u8 = 77;
+	pub const RELAY_AGENT_INFORMATION: u8 = 82;
+	pub const CLIENT_ARCHITECTURE: u8 = 93;
+	pub const DOMAIN_SEARCH: u8 = 119;
+	pub const CLASSLESS_ROUTES: u8 = 121;
+	pub const END: u8 = 255;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageType {
+	Discover = 1,
+	Offer = 2,
+	Request = 3,
+	Decline = 4,
+	Ack = 5,
+	Nak = 6,
+	Release = 7,
+	Inform = 8,
+}
+
+impl MessageType {
+	pub fn from_u8(value: u8) -> Option<MessageType> {
+		match value {
+			1 => Some(MessageType::Discover),
+			2 => Some(MessageType::Offer),
+			3 => Some(MessageType::Request),
+			4 => Some(MessageType::Decline),
+			5 => Some(MessageType::Ack),
+			6 => Some(MessageType::Nak),
+			7 => Some(MessageType::Release),
+			8 => Some(MessageType::Inform),
+			_ => None,
+		}
+	}
+}
+
+/// A DHCPv4 message.
+/// Options are kept in the order they were added or received, repeated options are concatenated (RFC 3396)
+#[derive(Clone)]
+pub struct DhcpMessage {
+	pub op: u8,
+	pub hops: u8,
+	pub xid: u32,
+	pub secs: u16,
+	pub flags: u16,
+	pub ciaddr: Ipv4Addr,
+	pub yiaddr: Ipv4Addr,
+	pub siaddr: Ipv4Addr,
+	pub giaddr: Ipv4Addr,
+	pub chaddr: [u8; 6],
+	pub sname: String,
+	pub file: String,
+	pub options: Vec<(u8, Vec<u8>)>,
+}
+
+fn read_ipv4(data: &[u8]) -> Ipv4Addr {
+	Ipv4Addr::new(data[0], data[1], data[2], data[3])
+}
+
+fn read_cstring(data: &[u8]) -> String {
+	let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+	String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+fn write_cstring(buffer: &mut [u8], value: &str) {
+	let bytes = value.as_bytes();
+	// Always leave room for the terminating zero
+	let len = bytes.len().min(buffer.len() - 1);
+	buffer[..len].copy_from_slice(&bytes[..len]);
+}
+
+impl DhcpMessage {
+	/// Creates an empty client message of the given type
+	pub fn new(op: u8, message_type: MessageType, xid: u32, chaddr: [u8; 6]) -> DhcpMessage {
+		let mut message = DhcpMessage {
+			op,
+			hops: 0,
+			xid,
+			secs: 0,
+			flags: 0,
+			ciaddr: Ipv4Addr::UNSPECIFIED,
+			yiaddr: Ipv4Addr::UNSPECIFIED,
+			siaddr: Ipv4Addr::UNSPECIFIED,
+			giaddr: Ipv4Addr::UNSPECIFIED,
+			chaddr,
+			sname: String::new(),
+			file: String::new(),
+			options: vec![],
+		};
+		message.set_option(options::MESSAGE_TYPE, vec![message_type as u8]);
+		message
+	}
+
+	pub fn message_type(&self) -> Option<MessageType> {
+		MessageType::from_u8(*self.option(options::MESSAGE_TYPE)?.first()?)
+	}
+
+	pub fn option(&self, code: u8) -> Option<&[u8]> {
+		self.options
+			.iter()
+			.find(|(option, _)| *option == code)
+			.map(|(_, data)| data.as_slice())
+	}
+
+	/// Sets an option, replacing it if it already exists
+	pub fn set_option(&mut self, code: u8, data: Vec<u8>) {
+		if let Some(option) = self.options.iter_mut().find(|(option, _)| *option == code) {
+			option.1 = data;
+		} else {
+			self.options.push((code, data));
+		}
+	}
+
+	pub fn remove_option(&mut self, code: u8) {
+		self.options.retain(|(option, _)| *option != code);
+	}
+
+	pub fn option_ipv4(&self, code: u8) -> Option<Ipv4Addr> {
+		self.option(code)
+			.filter(|data| data.len() >= 4)
+			.map(read_ipv4)
+	}
+
+	pub fn option_ipv4_list(&self, code: u8) -> Vec<Ipv4Addr> {
+		self.option(code)
+			.map(|data| data.chunks_exact(4).map(read_ipv4).collect())
+			.unwrap_or_default()
+	}
+
+	pub fn option_u16(&self, code: u8) -> Option<u16> {
+		self.option(code)
+			.filter(|data| data.len() >= 2)
+			.map(|data| u16::from_be_bytes([data[0], data[1]]))
+	}
+
+	pub fn option_u32(&self, code: u8) -> Option<u32> {
+		self.option(code)
+			.filter(|data| data.len() >= 4)
+			.map(|data| u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+	}
+
+	pub fn option_string(&self, code: u8) -> Option<String> {
+		self.option(code).map(read_cstring)
+	}
+
+	/// Parses option 121 (RFC 3442) into destination, prefix length and router
+	pub fn option_classless_routes(&self) -> Vec<(Ipv4Addr, u8, Ipv4Addr)> {
+		let Some(data) = self.option(options::CLASSLESS_ROUTES) else {
+			return vec![];
+		};
+		let mut routes = vec![];
+		let mut i = 0;
+		while i < data.len() {
+			let prefix = data[i];
+			// Only the significant octets of the destination are sent
+			let significant = (prefix as usize).div_ceil(8);
+			let Some(route) = data.get(i + 1..i + 1 + significant + 4) else {
+				break;
+			};
+			if prefix > 32 {
+				break;
+			}
+			let mut destination = [0u8; 4];
+			destination[..significant].copy_from_slice(&route[..significant]);
+			routes.push((
+				Ipv4Addr::from(destination),
+				prefix,
+				read_ipv4(&route[significant..]),
+			));
+			i += 1 + significant + 4;
+		}
+		routes
+	}
+
+	pub fn decode(data: &[u8]) -> Option<DhcpMessage> {
+		if data.len() < HEADER_LEN || data[236..240] != MAGIC_COOKIE {
+			return None;
+		}
+		let mut chaddr = [0u8; 6];
+		chaddr.copy_from_slice(&data[28..34]);
+		let mut message = DhcpMessage {
+			op: data[0],
+			hops: data[3],
+			xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+			secs: u16::from_be_bytes([data[8], data[9]]),
+			flags: u16::from_be_bytes([data[10], data[11]]),
+			ciaddr: read_ipv4(&data[12..16]),
+			yiaddr: read_ipv4(&data[16..20]),
+			siaddr: read_ipv4(&data[20..24]),
+			giaddr: read_ipv4(&data[24..28]),
+			chaddr,
+			sname: read_cstring(&data[44..108]),
+			file: read_cstring(&data[108..236]),
+			options: vec![],
+		};
+
+		let mut i = HEADER_LEN;
+		while i < data.len() {
+			let code = data[i];
+			match code {
+				options::PAD => i += 1,
+				options::END => break,
+				_ => {
+					let len = *data.get(i + 1)? as usize;
+					let value = data.get(i + 2..i + 2 + len)?;
+					if let Some(option) = message
+						.options
+						.iter_mut()
+						.find(|(option, _)| *option == code)
+					{
+						option.1.extend_from_slice(value);
+					} else {
+						message.options.push((code, value.to_vec()));
+					}
+					i += 2 + len;
+				}
+			}
+		}
+		Some(message)
+	}
+
+	pub fn encode(&self) -> Vec<u8> {
+		let mut data = vec![0u8; HEADER_LEN];
+		data[0] = self.op;
+		data[1] = 1; // Ethernet
+		data[2] = 6;
+		data[3] = self.hops;
+		data[4..8].copy_from_slice(&self.xid.to_be_bytes());
+		data[8..10].copy_from_slice(&self.secs.to_be_bytes());
+		data[10..12].copy_from_slice(&self.flags.to_be_bytes());
+		data[12..16].copy_from_slice(&self.ciaddr.octets());
+		data[16..20].copy_from_slice(&self.yiaddr.octets());
+		data[20..24].copy_from_slice(&self.siaddr.octets());
+		data[24..28].copy_from_slice(&self.giaddr.octets());
+		data[28..34].copy_from_slice(&self.chaddr);
+		write_cstring(&mut data[44..108], &self.sname);
+		write_cstring(&mut data[108..236], &self.file);
+		data[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+		for (code, value) in &self.options {
+			// Options longer than 255 bytes are split into multiple instances
+			let mut chunks = value.chunks(255).peekable();
+			if chunks.peek().is_none() {
+				data.extend_from_slice(&[*code, 0]);
+			}
+			for chunk in chunks {
+				data.push(*code);
+				data.push(chunk.len() as u8);
+				data.extend_from_slice(chunk);
+			}
+		}
+		data.push(options::END);
+		// Some old BOOTP relays drop packets smaller than the BOOTP minimum
+		if data.len() < 300 {
+			data.resize(300, options::PAD);
+		}
+		data
+	}
+}
+
+/// Encodes routes as destination, prefix length and router into option 121 (RFC 3442)
+pub fn encode_classless_routes(routes: &[(Ipv4Addr, u8, Ipv4Addr)]) -> Vec<u8> {
+	let mut data = vec![];
+	for (destination, prefix, router) in routes {
+		data.push(*prefix);
+		data.extend_from_slice(&destination.octets()[..(*prefix as usize).div_ceil(8)]);
+		data.extend_from_slice(&router.octets());
+	}
+	data
+}
+
+/// Wraps a message in IPv4 and UDP headers, for sending through a packet socket
+/// when the interface has no address to send from yet
+pub fn encode_udp_packet(
+	source: SocketAddrV4,
+	destination: SocketAddrV4,
+	payload: &[u8],
+) -> Vec<u8> {
+	let udp_len = (UDP_HEADER_LEN + payload.len()) as u16;
+	let mut udp = vec![];
+	udp.extend_from_slice(&source.port().to_be_bytes());
+	udp.extend_from_slice(&destination.port().to_be_bytes());
+	udp.extend_from_slice(&udp_len.to_be_bytes());
+	udp.extend_from_slice(&[0, 0]);
+	udp.extend_from_slice(payload);
+	// The UDP checksum covers a pseudo header of the addresses, protocol and length
+	let mut pseudo_header = source.ip().octets().to_vec();
+	pseudo_header.extend_from_slice(&destination.ip().octets());
+	pseudo_header.extend_from_slice(&[0, PROTOCOL_UDP]);
+	pseudo_header.extend_from_slice(&udp_len.to_be_bytes());
+	let sum = match checksum(&[pseudo_header, udp.clone()].concat()) {
+		// A checksum of 0 means none was computed, so it is sent as all ones
+		0 => 0xffff,
+		sum => sum,
+	};
+	udp[6..8].copy_from_slice(&sum.to_be_bytes());
+
+	let mut packet = vec![0x45, 0];
+	packet.extend_from_slice(&(IPV4_HEADER_LEN as u16 + udp_len).to_be_bytes());
+	// Identification, no fragmentation, TTL and protocol
+	packet.extend_from_slice(&[0, 0, 0, 0, 64, PROTOCOL_UDP, 0, 0]);
+	packet.extend_from_slice(&source.ip().octets());
+	packet.extend_from_slice(&destination.ip().octets());
+	let sum = checksum(&packet);
+	packet[10..12].copy_from_slice(&sum.to_be_bytes());
+	packet.extend_from_slice(&udp);
+	packet
+}
+
+/// The payload of an IPv4 packet if it is UDP to the port.
+/// Checksums are not verified, packets looped back or passed up by offloading NICs do not have them filled in
+pub fn decode_udp_packet(packet: &[u8], port: u16) -> Option<&[u8]> {
+	let version = packet.first()? >> 4;
+	let header_len = (packet.first()? & 0x0f) as usize * 4;
+	let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+	// Fragments can't be reassembled here, DHCP messages fit in a single packet
+	let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x3fff;
+	if version != 4
+		|| header_len < IPV4_HEADER_LEN
+		|| *packet.get(9)? != PROTOCOL_UDP
+		|| fragment != 0
+	{
+		return None;
+	}
+	let udp = packet.get(header_len..total_len)?;
+	let destination = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+	let udp_len = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+	if destination != port || udp_len < UDP_HEADER_LEN {
+		return None;
+	}
+	udp.get(UDP_HEADER_LEN..udp_len)
+}
+
+/// Converts a netmask like 255.255.255.0 to a prefix length
+pub fn netmask_to_prefix(netmask: Ipv4Addr) -> u8 {
+	u32::from(netmask).leading_ones() as u8
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn message() -> DhcpMessage {
+		let mut message = DhcpMessage::new(
+			BOOTREQUEST,
+			MessageType::Request,
+			0x12345678,
+			[2, 0, 0, 0, 0, 1],
+		);
+		message.flags = FLAG_BROADCAST;
+		message.ciaddr = Ipv4Addr::new(192, 168, 1, 10);
+		message.giaddr = Ipv4Addr::new(10, 0, 0, 1);
+		message.file = "pxelinux.0".to_string();
+		message.set_option(options::REQUESTED_ADDRESS, vec![192, 168, 1, 10]);
+		message.set_option(options::HOSTNAME, b"host".to_vec());
+		message
+	}
+
+	#[test]
+	fn round_trip() {
+		let data = message().encode();
+		assert_eq!(data.len(), 300);
+		let decoded = DhcpMessage::decode(&data).unwrap();
+		assert_eq!(decoded.op, BOOTREQUEST);
+		assert_eq!(decoded.xid, 0x12345678);
+		assert_eq!(decoded.flags, FLAG_BROADCAST);
+		assert_eq!(decoded.ciaddr, Ipv4Addr::new(192, 168, 1, 10));
+		assert_eq!(decoded.giaddr, Ipv4Addr::new(10, 0, 0, 1));
+		assert_eq!(decoded.chaddr, [2, 0, 0, 0, 0, 1]);
+		assert_eq!(decoded.file, "pxelinux.0");
+		assert_eq!(decoded.message_type(), Some(MessageType::Request));
+		assert_eq!(
+			decoded.option_ipv4(options::REQUESTED_ADDRESS),
+			Some(Ipv4Addr::new(192, 168, 1, 10))
+		);
+		assert_eq!(
+			decoded.option_string(options::HOSTNAME).as_deref(),
+			Some("host")
+		);
+	}
+
+	#[test]
+	fn long_strings_are_truncated() {
+		let mut message = message();
+		message.file = "a".repeat(200);
+		let decoded = DhcpMessage::decode(&message.encode()).unwrap();
+		assert_eq!(decoded.file.len(), 127);
+	}
+
+	#[test]
+	fn rejects_malformed_messages() {
+		let data = message().encode();
+		assert!(DhcpMessage::decode(&data[..HEADER_LEN - 1]).is_none());
+		let mut cookie = data.clone();
+		cookie[236] = 0;
+		assert!(DhcpMessage::decode(&cookie).is_none());
+		// An option running past the end of the message
+		let mut truncated = data[..HEADER_LEN].to_vec();
+		truncated.extend_from_slice(&[options::HOSTNAME, 10, b'h']);
+		assert!(DhcpMessage::decode(&truncated).is_none());
+	}
+
+	#[test]
+	fn splits_long_options() {
+		let value: Vec<u8> = (0..600).map(|i| i as u8).collect();
+		let mut message = message();
+		message.set_option(options::DOMAIN_SEARCH, value.clone());
+		let data = message.encode();
+		let instances = data[HEADER_LEN..]
+			.windows(2)
+			.filter(|window| window[0] == options::DOMAIN_SEARCH && window[1] == 255)
+			.count();
+		assert!(instances >= 2);
+		let decoded = DhcpMessage::decode(&data).unwrap();
+		assert_eq!(
+			decoded.option(options::DOMAIN_SEARCH),
+			Some(value.as_slice())
+		);
+	}
+
+	#[test]
+	fn concatenates_repeated_options() {
+		let mut data = message().encode();
+		data.truncate(data.iter().rposition(|&byte| byte == options::END).unwrap());
+		data.extend_from_slice(&[options::MESSAGE, 2, b'a', b'b']);
+		data.extend_from_slice(&[options::PAD, options::MESSAGE, 1, b'c', options::END]);
+		let decoded = DhcpMessage::decode(&data).unwrap();
+		assert_eq!(
+			decoded.option_string(options::MESSAGE).as_deref(),
+			Some("abc")
+		);
+	}
+
+	#[test]
+	fn classless_routes() {
+		let routes = vec![
+			(Ipv4Addr::UNSPECIFIED, 0, Ipv4Addr::new(192, 168, 1, 1)),
+			(Ipv4Addr::new(10, 0, 0, 0), 8, Ipv4Addr::new(192, 168, 1, 2)),
+			(
+				Ipv4Addr::new(172, 16, 128, 0),
+				17,
+				Ipv4Addr::new(192, 168, 1, 3),
+			),
+			(
+				Ipv4Addr::new(192, 0, 2, 1),
+				32,
+				Ipv4Addr::new(192, 168, 1, 4),
+			),
+		];
+		let data = encode_classless_routes(&routes);
+		assert_eq!(&data[..5], &[0, 192, 168, 1, 1]);
+		assert_eq!(&data[5..11], &[8, 10, 192, 168, 1, 2]);
+		let mut message = message();
+		message.set_option(options::CLASSLESS_ROUTES, data);
+		assert_eq!(message.option_classless_routes(), routes);
+	}
+
+	#[test]
+	fn malformed_classless_routes() {
+		let mut message = message();
+		// A valid route followed by a truncated one
+		message.set_option(
+			options::CLASSLESS_ROUTES,
+			vec![8, 10, 192, 168, 1, 1, 24, 10, 1],
+		);
+		assert_eq!(
+			message.option_classless_routes(),
+			vec![(Ipv4Addr::new(10, 0, 0, 0), 8, Ipv4Addr::new(192, 168, 1, 1))]
+		);
+		// A prefix longer than 32 bits
+		message.set_option(
+			options::CLASSLESS_ROUTES,
+			vec![33, 10, 0, 0, 0, 0, 192, 168, 1, 1],
+		);
+		assert!(message.option_classless_routes().is_empty());
+	}
+
+	#[test]
+	fn netmask() {
+		assert_eq!(netmask_to_prefix(Ipv4Addr::new(255, 255, 255, 0)), 24);
+		assert_eq!(netmask_to_prefix(Ipv4Addr::new(255, 255, 128, 0)), 17);
+		assert_eq!(netmask_to_prefix(Ipv4Addr::UNSPECIFIED), 0);
+	}
+
+	#[test]
+	fn udp_packet_round_trip() {
+		let source = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, CLIENT_PORT);
+		let destination = SocketAddrV4::new(Ipv4Addr::BROADCAST, SERVER_PORT);
+		let payload = message().encode();
+		let packet = encode_udp_packet(source, destination, &payload);
+		assert_eq!(
+			packet.len(),
+			IPV4_HEADER_LEN + UDP_HEADER_LEN + payload.len()
+		);
+		// Both checksums verify to 0
+		assert_eq!(checksum(&packet[..IPV4_HEADER_LEN]), 0);
+		let mut pseudo_header = vec![0, 0, 0, 0, 255, 255, 255, 255, 0, PROTOCOL_UDP];
+		pseudo_header.extend_from_slice(&packet[24..26]);
+		assert_eq!(
+			checksum(&[pseudo_header, packet[IPV4_HEADER_LEN..].to_vec()].concat()),
+			0
+		);
+
+		assert_eq!(decode_udp_packet(&packet, SERVER_PORT), Some(&payload[..]));
+		assert_eq!(decode_udp_packet(&packet, CLIENT_PORT), None);
+		assert_eq!(decode_udp_packet(&packet[..100], SERVER_PORT), None);
+		// Ethernet pads short frames, the padding is not part of the payload
+		let mut padded = packet.clone();
+		padded.extend_from_slice(&[0; 16]);
+		assert_eq!(decode_udp_packet(&padded, SERVER_PORT), Some(&payload[..]));
+	}
+}
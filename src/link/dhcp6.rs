@@ -0,0 +1,235 @@
+use std::net::Ipv6Addr;
+
+pub const CLIENT_PORT: u16 = 546;
+pub const SERVER_PORT: u16 = 547;
+pub const ALL_DHCP_RELAY_AGENTS_AND_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+
+/// DHCPv6 option codes (RFC 8415 and friends)
+pub mod options {
+	pub const CLIENTID: u16 = 1;
+	pub const SERVERID: u16 = 2;
+	pub const IA_NA: u16 = 3;
+	pub const IAADDR: u16 = 5;
+	pub const ORO: u16 = 6;
+	pub const ELAPSED_TIME: u16 = 8;
+	pub const STATUS_CODE: u16 = 13;
+	pub const DNS_SERVERS: u16 = 23;
+	pub const DOMAIN_LIST: u16 = 24;
+	pub const IA_PD: u16 = 25;
+	pub const IAPREFIX: u16 = 26;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageType {
+	Solicit = 1,
+	Advertise = 2,
+	Request = 3,
+	Renew = 5,
+	Rebind = 6,
+	Reply = 7,
+	Release = 8,
+}
+
+impl MessageType {
+	pub fn from_u8(value: u8) -> Option<MessageType> {
+		match value {
+			1 => Some(MessageType::Solicit),
+			2 => Some(MessageType::Advertise),
+			3 => Some(MessageType::Request),
+			5 => Some(MessageType::Renew),
+			6 => Some(MessageType::Rebind),
+			7 => Some(MessageType::Reply),
+			8 => Some(MessageType::Release),
+			_ => None,
+		}
+	}
+}
+
+/// Status code 0, everything else is an error
+pub const STATUS_SUCCESS: u16 = 0;
+
+pub type Options = Vec<(u16, Vec<u8>)>;
+
+/// Parses a sequence of options, used both for the message and for options nested in IAs
+pub fn decode_options(data: &[u8]) -> Option<Options> {
+	let mut options = vec![];
+	let mut i = 0;
+	while i < data.len() {
+		let code = u16::from_be_bytes([*data.get(i)?, *data.get(i + 1)?]);
+		let len = u16::from_be_bytes([*data.get(i + 2)?, *data.get(i + 3)?]) as usize;
+		options.push((code, data.get(i + 4..i + 4 + len)?.to_vec()));
+		i += 4 + len;
+	}
+	Some(options)
+}
+
+pub fn encode_options(options: &Options) -> Vec<u8> {
+	let mut data = vec![];
+	for (code, value) in options {
+		data.extend_from_slice(&code.to_be_bytes());
+		data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+		data.extend_from_slice(value);
+	}
+	data
+}
+
+pub fn find_option(options: &Options, code: u16) -> Option<&[u8]> {
+	options
+		.iter()
+		.find(|(option, _)| *option == code)
+		.map(|(_, data)| data.as_slice())
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+	Some(u32::from_be_bytes(
+		data.get(offset..offset + 4)?.try_into().ok()?,
+	))
+}
+
+pub fn read_ipv6(data: &[u8], offset: usize) -> Option<Ipv6Addr> {
+	let octets: [u8; 16] = data.get(offset..offset + 16)?.try_into().ok()?;
+	Some(Ipv6Addr::from(octets))
+}
+
+/// Encodes domain names in DNS wire format (RFC 1035 3.1), without compression
+pub fn encode_domain_list(domains: &[String]) -> Vec<u8> {
+	let mut data = vec![];
+	for domain in domains {
+		for label in domain.split('.').filter(|label| !label.is_empty()) {
+			data.push(label.len() as u8);
+			data.extend_from_slice(label.as_bytes());
+		}
+		data.push(0);
+	}
+	data
+}
+
+/// Decodes a list of domain names in DNS wire format (RFC 1035 3.1), as used by the domain list option
+pub fn decode_domain_list(data: &[u8]) -> Vec<String> {
+	let mut domains = vec![];
+	let mut labels: Vec<String> = vec![];
+	let mut i = 0;
+	while i < data.len() {
+		let len = data[i] as usize;
+		if len == 0 {
+			if !labels.is_empty() {
+				domains.push(labels.join("."));
+				labels.clear();
+			}
+			i += 1;
+			continue;
+		}
+		let Some(label) = data.get(i + 1..i + 1 + len) else {
+			break;
+		};
+		labels.push(String::from_utf8_lossy(label).to_string());
+		i += 1 + len;
+	}
+	domains
+}
+
+/// Returns the status code option in the options, if there is one
+pub fn status_code(options: &Options) -> Option<(u16, String)> {
+	let data = find_option(options, options::STATUS_CODE)?;
+	let code = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+	Some((code, String::from_utf8_lossy(&data[2..]).to_string()))
+}
+
+#[derive(Clone)]
+pub struct Dhcp6Message {
+	pub message_type: MessageType,
+	pub transaction_id: [u8; 3],
+	pub options: Options,
+}
+
+impl Dhcp6Message {
+	pub fn new(message_type: MessageType, transaction_id: [u8; 3]) -> Dhcp6Message {
+		Dhcp6Message {
+			message_type,
+			transaction_id,
+			options: vec![],
+		}
+	}
+
+	pub fn option(&self, code: u16) -> Option<&[u8]> {
+		find_option(&self.options, code)
+	}
+
+	pub fn add_option(&mut self, code: u16, data: Vec<u8>) {
+		self.options.push((code, data));
+	}
+
+	pub fn decode(data: &[u8]) -> Option<Dhcp6Message> {
+		Some(Dhcp6Message {
+			message_type: MessageType::from_u8(*data.first()?)?,
+			transaction_id: data.get(1..4)?.try_into().ok()?,
+			options: decode_options(data.get(4..)?)?,
+		})
+	}
+
+	pub fn encode(&self) -> Vec<u8> {
+		let mut data = vec![self.message_type as u8];
+		data.extend_from_slice(&self.transaction_id);
+		data.extend_from_slice(&encode_options(&self.options));
+		data
+	}
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+	data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn from_hex(hex: &str) -> Vec<u8> {
+	(0..hex.len() / 2)
+		.filter_map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn domain_list_round_trip() {
+		let domains = vec!["example.com".to_string(), "lan".to_string()];
+		let data = encode_domain_list(&domains);
+		assert_eq!(&data[..13], b"\x07example\x03com\x00");
+		assert_eq!(decode_domain_list(&data), domains);
+	}
+
+	#[test]
+	fn malformed_domain_list() {
+		// A label running past the end is dropped with the unterminated name
+		assert_eq!(
+			decode_domain_list(b"\x03lan\x00\x07exa"),
+			vec!["lan".to_string()]
+		);
+		assert_eq!(decode_domain_list(b"\x03lan"), Vec::<String>::new());
+		assert_eq!(decode_domain_list(b"\x00\x00"), Vec::<String>::new());
+	}
+
+	#[test]
+	fn options_round_trip() {
+		let options = vec![
+			(options::CLIENTID, vec![0, 3, 0, 1, 2, 0, 0, 0, 0, 1]),
+			(options::ELAPSED_TIME, vec![0, 0]),
+			(options::ORO, vec![]),
+		];
+		let data = encode_options(&options);
+		assert_eq!(decode_options(&data), Some(options));
+		assert!(decode_options(&data[..data.len() - 1]).is_none());
+		assert!(decode_options(&[0, 1, 0]).is_none());
+	}
+
+	#[test]
+	fn message_round_trip() {
+		let mut message = Dhcp6Message::new(MessageType::Solicit, [1, 2, 3]);
+		message.add_option(options::ELAPSED_TIME, vec![0, 10]);
+		let decoded = Dhcp6Message::decode(&message.encode()).unwrap();
+		assert_eq!(decoded.message_type, MessageType::Solicit);
+		assert_eq!(decoded.transaction_id, [1, 2, 3]);
+		assert_eq!(decoded.option(options::ELAPSED_TIME), Some(&[0, 10][..]));
+		assert!(Dhcp6Message::decode(&[4, 1, 2, 3]).is_none());
+		assert!(Dhcp6Message::decode(&[1, 2]).is_none());
+	}
+}
@@ -0,0 +1,878 @@
+use std::{
+	collections::BTreeMap,
+	net::{Ipv6Addr, SocketAddrV6},
+	str::FromStr,
+	sync::Mutex,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pnet::util::MacAddr;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+	net::UdpSocket,
+	task::JoinHandle,
+	time::{sleep, sleep_until, timeout_at, Instant},
+};
+
+use crate::{
+	config::{DelegatedPrefixConfig, Dhcpv6ClientConfig},
+	hooks::run_hook_with_env,
+	link::{
+		dhcp6::{
+			self, decode_domain_list, decode_options, encode_options, from_hex, options, read_ipv6,
+			read_u32, status_code, to_hex, Dhcp6Message, MessageType, STATUS_SUCCESS,
+		},
+		interface::Interface,
+		radv, resolver, routing,
+	},
+	state,
+};
+
+/// The identity association ID used for both IA_NA and IA_PD, netd only requests one of each
+const IAID: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeasedAddress {
+	pub address: Ipv6Addr,
+	pub preferred: u32,
+	pub valid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DelegatedPrefix {
+	pub prefix: Ipv6Addr,
+	pub length: u8,
+	pub preferred: u32,
+	pub valid: u32,
+}
+
+/// A lease obtained from a DHCPv6 server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Dhcpv6Lease {
+	/// The server DUID, hex encoded
+	pub server_id: String,
+	#[serde(default)]
+	pub addresses: Vec<LeasedAddress>,
+	#[serde(default)]
+	pub prefixes: Vec<DelegatedPrefix>,
+	#[serde(default)]
+	pub dns: Vec<Ipv6Addr>,
+	#[serde(default)]
+	pub domains: Vec<String>,
+	/// Renewal (T1) and rebinding (T2) times in seconds
+	pub renew_time: u32,
+	pub rebind_time: u32,
+	/// When the lease was last acquired or renewed, in seconds since the epoch
+	pub acquired: u64,
+}
+
+/// Whether an address or prefix with these lifetimes belongs in the lease.
+/// Ones with a preferred lifetime longer than the valid one must be discarded (RFC 8415 21.6, 21.22),
+/// and a valid lifetime of 0 withdraws them, so they are removed as they are no longer in the lease
+fn valid_lifetimes(name: &str, preferred: u32, valid: u32) -> bool {
+	if preferred > valid {
+		println!("Ignoring DHCPv6 lease of {name}: preferred lifetime {preferred} is longer than valid lifetime {valid}");
+		return false;
+	}
+	valid > 0
+}
+
+impl Dhcpv6Lease {
+	fn from_reply(reply: &Dhcp6Message) -> Option<Dhcpv6Lease> {
+		let mut lease = Dhcpv6Lease {
+			server_id: to_hex(reply.option(options::SERVERID)?),
+			addresses: vec![],
+			prefixes: vec![],
+			dns: reply
+				.option(options::DNS_SERVERS)
+				.map(|data| {
+					(0..data.len() / 16)
+						.filter_map(|i| read_ipv6(data, i * 16))
+						.collect()
+				})
+				.unwrap_or_default(),
+			domains: reply
+				.option(options::DOMAIN_LIST)
+				.map(decode_domain_list)
+				.unwrap_or_default(),
+			renew_time: 0,
+			rebind_time: 0,
+			acquired: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.unwrap_or_default()
+				.as_secs(),
+		};
+
+		for (code, data) in &reply.options {
+			if *code != options::IA_NA && *code != options::IA_PD {
+				continue;
+			}
+			// IAID, T1, T2, then the nested options
+			let (Some(t1), Some(t2), Some(nested)) = (
+				read_u32(data, 4),
+				read_u32(data, 8),
+				data.get(12..).and_then(decode_options),
+			) else {
+				continue;
+			};
+			if let Some((status, message)) = status_code(&nested) {
+				if status != STATUS_SUCCESS {
+					println!("DHCPv6 server refused IA {code}: {message} ({status})");
+					continue;
+				}
+			}
+			if t1 > 0 && (lease.renew_time == 0 || t1 < lease.renew_time) {
+				lease.renew_time = t1;
+				lease.rebind_time = t2;
+			}
+			for (nested_code, nested_data) in &nested {
+				match *nested_code {
+					options::IAADDR => {
+						// Address, preferred lifetime, valid lifetime
+						if let (Some(address), Some(preferred), Some(valid)) = (
+							read_ipv6(nested_data, 0),
+							read_u32(nested_data, 16),
+							read_u32(nested_data, 20),
+						) {
+							if !valid_lifetimes(&address.to_string(), preferred, valid) {
+								continue;
+							}
+							lease.addresses.push(LeasedAddress {
+								address,
+								preferred,
+								valid,
+							});
+						}
+					}
+					options::IAPREFIX => {
+						// Preferred lifetime, valid lifetime, prefix length, prefix
+						if let (Some(preferred), Some(valid), Some(&length), Some(prefix)) = (
+							read_u32(nested_data, 0),
+							read_u32(nested_data, 4),
+							nested_data.get(8),
+							read_ipv6(nested_data, 9),
+						) {
+							if !valid_lifetimes(&format!("{prefix}/{length}"), preferred, valid) {
+								continue;
+							}
+							lease.prefixes.push(DelegatedPrefix {
+								prefix,
+								length,
+								preferred,
+								valid,
+							});
+						}
+					}
+					_ => {}
+				}
+			}
+		}
+
+		if lease.addresses.is_empty() && lease.prefixes.is_empty() {
+			return None;
+		}
+		// Servers may leave T1 and T2 to the client, use the recommended 0.5 and 0.8 of the shortest preferred lifetime
+		if lease.renew_time == 0 {
+			let shortest = lease
+				.addresses
+				.iter()
+				.map(|address| address.preferred)
+				.chain(lease.prefixes.iter().map(|prefix| prefix.preferred))
+				.min()
+				.unwrap_or(3600);
+			lease.renew_time = shortest / 2;
+			lease.rebind_time = shortest / 5 * 4;
+		}
+		Some(lease)
+	}
+
+	/// When the lease is gone, i.e. the longest valid lifetime
+	fn valid_time(&self) -> u32 {
+		self.addresses
+			.iter()
+			.map(|address| address.valid)
+			.chain(self.prefixes.iter().map(|prefix| prefix.valid))
+			.max()
+			.unwrap_or_default()
+	}
+
+	fn hook_env(&self, ifname: &str) -> Vec<(String, String)> {
+		vec![
+			("DHCPV6_INTERFACE".to_string(), ifname.to_string()),
+			(
+				"DHCPV6_ADDRESSES".to_string(),
+				self.addresses
+					.iter()
+					.map(|address| address.address.to_string())
+					.collect::<Vec<_>>()
+					.join(" "),
+			),
+			(
+				"DHCPV6_PREFIXES".to_string(),
+				self.prefixes
+					.iter()
+					.map(|prefix| format!("{}/{}", prefix.prefix, prefix.length))
+					.collect::<Vec<_>>()
+					.join(" "),
+			),
+			(
+				"DHCPV6_DNS".to_string(),
+				self.dns
+					.iter()
+					.map(|dns| dns.to_string())
+					.collect::<Vec<_>>()
+					.join(" "),
+			),
+			("DHCPV6_DOMAINS".to_string(), self.domains.join(" ")),
+		]
+	}
+}
+
+/// Background clients, by interface name
+static CLIENTS: Mutex<BTreeMap<String, JoinHandle<()>>> = Mutex::new(BTreeMap::new());
+/// Currently bound leases, by interface name
+static LEASES: Mutex<BTreeMap<String, Dhcpv6Lease>> = Mutex::new(BTreeMap::new());
+/// Interfaces that get a sub-prefix of a delegated prefix, with the subnet currently assigned to them
+static DOWNSTREAMS: Mutex<BTreeMap<String, (DelegatedPrefixConfig, Option<Subnet>)>> =
+	Mutex::new(BTreeMap::new());
+
+/// A sub-prefix assigned to a downstream interface
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Subnet {
+	pub prefix: Ipv6Addr,
+	pub length: u8,
+	pub address: Ipv6Addr,
+	pub preferred: u32,
+	pub valid: u32,
+}
+
+/// Get the lease currently held on an interface
+pub fn get_lease(ifname: &str) -> Option<Dhcpv6Lease> {
+	LEASES.lock().unwrap().get(ifname).cloned()
+}
+
+/// Get the DNS servers of the leases on all interfaces
+pub fn get_dns_servers() -> Vec<Ipv6Addr> {
+	LEASES
+		.lock()
+		.unwrap()
+		.values()
+		.flat_map(|lease| lease.dns.clone())
+		.collect()
+}
+
+/// Get the sub-prefix currently assigned to a downstream interface
+pub fn get_subnet(ifname: &str) -> Option<Subnet> {
+	DOWNSTREAMS
+		.lock()
+		.unwrap()
+		.get(ifname)
+		.and_then(|(_, subnet)| subnet.clone())
+}
+
+fn store_lease(ifname: &str, lease: Option<&Dhcpv6Lease>) {
+	{
+		let mut leases = LEASES.lock().unwrap();
+		match lease {
+			Some(lease) => {
+				state::save(&format!("dhcp6c/{ifname}"), lease);
+				leases.insert(ifname.to_string(), lease.clone());
+			}
+			None => {
+				state::remove(&format!("dhcp6c/{ifname}"));
+				leases.remove(ifname);
+			}
+		};
+	}
+	resolver::update();
+}
+
+/// Carves the configured sub-prefix out of a delegated prefix
+fn compute_subnet(prefix: &DelegatedPrefix, config: &DelegatedPrefixConfig) -> Option<Subnet> {
+	if config.prefix_length < prefix.length || config.prefix_length > 128 {
+		return None;
+	}
+	let bits = config.prefix_length - prefix.length;
+	if bits < 32 && config.subnet_id >= (1u32 << bits) {
+		return None;
+	}
+	let host_bits = 128 - config.prefix_length as u32;
+	let subnet_id = (config.subnet_id as u128)
+		.checked_shl(host_bits)
+		.unwrap_or(0);
+	let subnet = u128::from(prefix.prefix) | subnet_id;
+	let host_mask = u128::MAX
+		.checked_shr(config.prefix_length as u32)
+		.unwrap_or(0);
+	let interface_id = Ipv6Addr::from_str(&config.interface_id)
+		.map(u128::from)
+		.unwrap_or(1);
+	Some(Subnet {
+		prefix: Ipv6Addr::from(subnet),
+		length: config.prefix_length,
+		address: Ipv6Addr::from(subnet | (interface_id & host_mask)),
+		preferred: prefix.preferred,
+		valid: prefix.valid,
+	})
+}
+
+async fn assign_subnet(ifname: &str, subnet: &Subnet) {
+	let interface = Interface::get_from_name(ifname);
+	if !interface.exists().await {
+		return;
+	}
+	if let Err(err) = interface
+		.replace_address_with_lifetime(
+			&subnet.address.to_string(),
+			subnet.length,
+			subnet.valid,
+			subnet.preferred,
+			true,
+		)
+		.await
+	{
+		println!(
+			"[{ifname}] Could not assign {}/{}: {err}",
+			subnet.address, subnet.length
+		);
+	}
+}
+
+async fn unassign_subnet(ifname: &str, subnet: &Subnet) {
+	let interface = Interface::get_from_name(ifname);
+	if !interface.exists().await {
+		return;
+	}
+	interface
+		.delete_address(&subnet.address.to_string(), subnet.length)
+		.await;
+}
+
+/// Assigns sub-prefixes of the prefix delegated to `upstream` to its downstream interfaces,
+/// and moves them to the new prefix when it changes
+async fn renumber(upstream: &str) {
+	let prefix = get_lease(upstream).and_then(|lease| lease.prefixes.first().cloned());
+	let downstreams: Vec<(String, DelegatedPrefixConfig, Option<Subnet>)> = DOWNSTREAMS
+		.lock()
+		.unwrap()
+		.iter()
+		.filter(|(_, (config, _))| config.from == upstream)
+		.map(|(name, (config, subnet))| (name.clone(), config.clone(), subnet.clone()))
+		.collect();
+
+	for (ifname, config, current) in downstreams {
+		let wanted = prefix.as_ref().and_then(|prefix| {
+			let subnet = compute_subnet(prefix, &config);
+			if subnet.is_none() {
+				println!(
+					"[{ifname}] Sub-prefix {} /{} does not fit into {}/{}",
+					config.subnet_id, config.prefix_length, prefix.prefix, prefix.length
+				);
+			}
+			subnet
+		});
+		if wanted == current {
+			continue;
+		}
+		if let Some(current) = &current {
+			if wanted.as_ref().map(|wanted| wanted.address) != Some(current.address) {
+				println!("[{ifname}] Removing delegated address {}", current.address);
+				unassign_subnet(&ifname, current).await;
+			}
+		}
+		if let Some(wanted) = &wanted {
+			println!(
+				"[{ifname}] Assigning delegated prefix {}/{}",
+				wanted.prefix, wanted.length
+			);
+			assign_subnet(&ifname, wanted).await;
+		}
+		if let Some((_, subnet)) = DOWNSTREAMS.lock().unwrap().get_mut(&ifname) {
+			*subnet = wanted;
+		}
+		radv::update(&ifname);
+	}
+}
+
+/// Registers an interface to get a sub-prefix of the prefix delegated to another interface
+pub async fn add_downstream(ifname: &str, config: &DelegatedPrefixConfig) {
+	DOWNSTREAMS
+		.lock()
+		.unwrap()
+		.insert(ifname.to_string(), (config.clone(), None));
+	renumber(&config.from).await;
+}
+
+pub async fn remove_downstream(ifname: &str) {
+	let downstream = DOWNSTREAMS.lock().unwrap().remove(ifname);
+	if let Some((_, Some(subnet))) = downstream {
+		unassign_subnet(ifname, &subnet).await;
+	}
+}
+
+fn open_socket(ifname: &str) -> std::io::Result<UdpSocket> {
+	let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+	socket.set_only_v6(true)?;
+	socket.set_reuse_address(true)?;
+	socket.bind_device(Some(ifname.as_bytes()))?;
+	socket.set_nonblocking(true)?;
+	socket.bind(&SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, dhcp6::CLIENT_PORT, 0, 0).into())?;
+	UdpSocket::from_std(socket.into())
+}
+
+enum Reply {
+	Lease(Dhcpv6Lease),
+	Refused,
+}
+
+struct Client {
+	ifname: String,
+	duid: Vec<u8>,
+	socket: UdpSocket,
+	destination: SocketAddrV6,
+	config: Dhcpv6ClientConfig,
+}
+
+impl Client {
+	async fn new(interface: &Interface, config: &Dhcpv6ClientConfig) -> std::io::Result<Client> {
+		let mac = MacAddr::from_str(&interface.get_mac().await).expect("Invalid MAC address");
+		// DUID-LL: type 3, hardware type 1 (Ethernet), MAC address
+		let mut duid = vec![0, 3, 0, 1];
+		duid.extend_from_slice(&mac.octets());
+		Ok(Client {
+			ifname: interface.name.clone(),
+			duid,
+			socket: open_socket(&interface.name)?,
+			destination: SocketAddrV6::new(
+				dhcp6::ALL_DHCP_RELAY_AGENTS_AND_SERVERS,
+				dhcp6::SERVER_PORT,
+				0,
+				interface.get_index(),
+			),
+			config: config.clone(),
+		})
+	}
+
+	/// Builds the IA_NA and IA_PD options, either empty (with a hint) or for the leased addresses and prefixes
+	fn identity_associations(&self, lease: Option<&Dhcpv6Lease>) -> Vec<(u16, Vec<u8>)> {
+		let ia_header = |nested: Vec<(u16, Vec<u8>)>| {
+			let mut data = IAID.to_be_bytes().to_vec();
+			data.extend_from_slice(&[0; 8]);
+			data.extend_from_slice(&encode_options(&nested));
+			data
+		};
+		let mut associations = vec![];
+		if self.config.request_address {
+			let nested = lease
+				.map(|lease| {
+					lease
+						.addresses
+						.iter()
+						.map(|address| {
+							let mut data = address.address.octets().to_vec();
+							data.extend_from_slice(&[0; 8]);
+							(options::IAADDR, data)
+						})
+						.collect()
+				})
+				.unwrap_or_default();
+			associations.push((options::IA_NA, ia_header(nested)));
+		}
+		if self.config.request_prefix {
+			let prefixes: Vec<(u8, Ipv6Addr)> = match lease {
+				Some(lease) => lease
+					.prefixes
+					.iter()
+					.map(|prefix| (prefix.length, prefix.prefix))
+					.collect(),
+				None => self
+					.config
+					.prefix_length_hint
+					.map(|hint| vec![(hint, Ipv6Addr::UNSPECIFIED)])
+					.unwrap_or_default(),
+			};
+			let nested = prefixes
+				.into_iter()
+				.map(|(length, prefix)| {
+					let mut data = vec![0; 8];
+					data.push(length);
+					data.extend_from_slice(&prefix.octets());
+					(options::IAPREFIX, data)
+				})
+				.collect();
+			associations.push((options::IA_PD, ia_header(nested)));
+		}
+		associations
+	}
+
+	fn message(
+		&self,
+		message_type: MessageType,
+		lease: Option<&Dhcpv6Lease>,
+		started: Instant,
+	) -> Dhcp6Message {
+		let mut message = Dhcp6Message::new(message_type, thread_rng().gen());
+		message.add_option(options::CLIENTID, self.duid.clone());
+		if let Some(lease) = lease {
+			if message_type != MessageType::Rebind {
+				message.add_option(options::SERVERID, from_hex(&lease.server_id));
+			}
+		}
+		// Elapsed time in hundredths of a second, updated on retransmissions
+		let elapsed = (started.elapsed().as_millis() / 10).min(u16::MAX as u128) as u16;
+		message.add_option(options::ELAPSED_TIME, elapsed.to_be_bytes().to_vec());
+		if message_type != MessageType::Release {
+			let mut requested = vec![];
+			for option in [options::DNS_SERVERS, options::DOMAIN_LIST] {
+				requested.extend_from_slice(&option.to_be_bytes());
+			}
+			message.add_option(options::ORO, requested);
+		}
+		for (code, data) in self.identity_associations(lease) {
+			message.add_option(code, data);
+		}
+		message
+	}
+
+	/// Sends a message and waits up to `wait` for a reply to it
+	async fn exchange(&self, message: &Dhcp6Message, wait: Duration) -> Option<Dhcp6Message> {
+		if let Err(err) = self
+			.socket
+			.send_to(&message.encode(), self.destination)
+			.await
+		{
+			// Usually the link-local address is not ready yet
+			println!("[{}] Failed to send DHCPv6 message: {err}", self.ifname);
+			sleep(wait).await;
+			return None;
+		}
+		let deadline = Instant::now() + wait;
+		let mut buffer = [0u8; 1500];
+		loop {
+			let (len, _) = timeout_at(deadline, self.socket.recv_from(&mut buffer))
+				.await
+				.ok()?
+				.ok()?;
+			let Some(reply) = Dhcp6Message::decode(&buffer[..len]) else {
+				continue;
+			};
+			if reply.transaction_id == message.transaction_id
+				&& reply.option(options::CLIENTID) == Some(self.duid.as_slice())
+			{
+				return Some(reply);
+			}
+		}
+	}
+
+	/// Sends a message until a reply arrives or `until` passes, doubling the retransmission timeout up to `max_wait`
+	async fn transact(
+		&self,
+		message_type: MessageType,
+		lease: Option<&Dhcpv6Lease>,
+		until: Option<Instant>,
+		max_wait: Duration,
+	) -> Option<Reply> {
+		let started = Instant::now();
+		let mut wait = Duration::from_secs(1);
+		loop {
+			if let Some(until) = until {
+				let now = Instant::now();
+				if now >= until {
+					return None;
+				}
+				wait = wait.min(until - now);
+			}
+			let message = self.message(message_type, lease, started);
+			if let Some(reply) = self.exchange(&message, wait).await {
+				if message_type == MessageType::Solicit {
+					if reply.message_type == MessageType::Advertise {
+						return Dhcpv6Lease::from_reply(&reply).map(Reply::Lease);
+					}
+				} else if reply.message_type == MessageType::Reply {
+					if let Some((status, message)) = status_code(&reply.options) {
+						if status != STATUS_SUCCESS {
+							println!("[{}] DHCPv6 server refused: {message}", self.ifname);
+							return Some(Reply::Refused);
+						}
+					}
+					return Some(match Dhcpv6Lease::from_reply(&reply) {
+						Some(lease) => Reply::Lease(lease),
+						None => Reply::Refused,
+					});
+				}
+			}
+			wait = (wait * 2).min(max_wait);
+		}
+	}
+
+	/// SOLICIT, ADVERTISE, REQUEST, REPLY, retrying until a lease is obtained
+	async fn obtain(&self) -> Dhcpv6Lease {
+		loop {
+			let Some(Reply::Lease(advertised)) = self
+				.transact(MessageType::Solicit, None, None, Duration::from_secs(120))
+				.await
+			else {
+				continue;
+			};
+			println!(
+				"[{}] Received DHCPv6 advertisement from {}",
+				self.ifname, advertised.server_id
+			);
+			let until = Instant::now() + Duration::from_secs(60);
+			if let Some(Reply::Lease(lease)) = self
+				.transact(
+					MessageType::Request,
+					Some(&advertised),
+					Some(until),
+					Duration::from_secs(30),
+				)
+				.await
+			{
+				return lease;
+			}
+		}
+	}
+
+	async fn apply_lease(&self, lease: &Dhcpv6Lease, previous: Option<&Dhcpv6Lease>) {
+		let interface = Interface::get_from_name(&self.ifname);
+		if let Some(previous) = previous {
+			for address in &previous.addresses {
+				if !lease.addresses.iter().any(|a| a.address == address.address) {
+					interface
+						.delete_address(&address.address.to_string(), 128)
+						.await;
+				}
+			}
+			for prefix in &previous.prefixes {
+				if !lease.prefixes.iter().any(|p| p.prefix == prefix.prefix) {
+					routing::delete_unreachable_route(&format!(
+						"{}/{}",
+						prefix.prefix, prefix.length
+					))
+					.await;
+				}
+			}
+		}
+		for address in &lease.addresses {
+			if let Err(err) = interface
+				.replace_address_with_lifetime(
+					&address.address.to_string(),
+					128,
+					address.valid,
+					address.preferred,
+					true,
+				)
+				.await
+			{
+				println!(
+					"[{}] Could not assign {}: {err}",
+					self.ifname, address.address
+				);
+			}
+		}
+		// Parts of the prefix that are not assigned downstream must not be routed back upstream
+		for prefix in &lease.prefixes {
+			routing::replace_unreachable_route(&format!("{}/{}", prefix.prefix, prefix.length))
+				.await;
+		}
+		store_lease(&self.ifname, Some(lease));
+		renumber(&self.ifname).await;
+	}
+
+	async fn remove_lease(&self, lease: &Dhcpv6Lease) {
+		let interface = Interface::get_from_name(&self.ifname);
+		if interface.exists().await {
+			for address in &lease.addresses {
+				interface
+					.delete_address(&address.address.to_string(), 128)
+					.await;
+			}
+		}
+		for prefix in &lease.prefixes {
+			routing::delete_unreachable_route(&format!("{}/{}", prefix.prefix, prefix.length))
+				.await;
+		}
+		store_lease(&self.ifname, None);
+		renumber(&self.ifname).await;
+	}
+
+	async fn run(self) {
+		loop {
+			let mut lease = self.obtain().await;
+			println!("[{}] Got DHCPv6 lease", self.ifname);
+			self.apply_lease(&lease, None).await;
+			run_hook_with_env(
+				format!("dhcpv6-bound.{}", self.ifname),
+				&lease.hook_env(&self.ifname),
+			);
+
+			loop {
+				let bound = Instant::now();
+				let t1 = bound + Duration::from_secs(lease.renew_time as u64);
+				let t2 = bound + Duration::from_secs(lease.rebind_time as u64);
+				let expiry = bound + Duration::from_secs(lease.valid_time() as u64);
+				sleep_until(t1).await;
+
+				let mut reply = self
+					.transact(
+						MessageType::Renew,
+						Some(&lease),
+						Some(t2),
+						Duration::from_secs(600),
+					)
+					.await;
+				if reply.is_none() {
+					println!("[{}] DHCPv6 server did not answer, rebinding", self.ifname);
+					reply = self
+						.transact(
+							MessageType::Rebind,
+							Some(&lease),
+							Some(expiry),
+							Duration::from_secs(600),
+						)
+						.await;
+				}
+
+				match reply {
+					Some(Reply::Lease(renewed)) => {
+						println!("[{}] Renewed DHCPv6 lease", self.ifname);
+						self.apply_lease(&renewed, Some(&lease)).await;
+						lease = renewed;
+						run_hook_with_env(
+							format!("dhcpv6-renew.{}", self.ifname),
+							&lease.hook_env(&self.ifname),
+						);
+					}
+					Some(Reply::Refused) | None => {
+						println!("[{}] Lost DHCPv6 lease", self.ifname);
+						self.remove_lease(&lease).await;
+						run_hook_with_env(
+							format!("dhcpv6-expired.{}", self.ifname),
+							&lease.hook_env(&self.ifname),
+						);
+						break;
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Starts a DHCPv6 client in the background. Prefixes it obtains are handed to the downstream interfaces
+pub async fn start_client(interface: &Interface, config: &Dhcpv6ClientConfig) {
+	stop_client(interface).await;
+	let client = match Client::new(interface, config).await {
+		Ok(client) => client,
+		Err(err) => {
+			println!("[{}] Could not start DHCPv6 client: {err}", interface.name);
+			return;
+		}
+	};
+	let task = tokio::spawn(client.run());
+	CLIENTS.lock().unwrap().insert(interface.name.clone(), task);
+}
+
+/// Stops the DHCPv6 client running on the interface and releases its lease
+pub async fn stop_client(interface: &Interface) {
+	let task = CLIENTS.lock().unwrap().remove(&interface.name);
+	let Some(task) = task else {
+		return;
+	};
+	task.abort();
+	let Some(lease) = get_lease(&interface.name) else {
+		return;
+	};
+	println!("[{}] Releasing DHCPv6 lease", interface.name);
+	let config = Dhcpv6ClientConfig {
+		request_address: !lease.addresses.is_empty(),
+		request_prefix: !lease.prefixes.is_empty(),
+		prefix_length_hint: None,
+	};
+	match Client::new(interface, &config).await {
+		Ok(client) => {
+			let release = client.message(MessageType::Release, Some(&lease), Instant::now());
+			let _ = client
+				.socket
+				.send_to(&release.encode(), client.destination)
+				.await;
+			client.remove_lease(&lease).await;
+		}
+		Err(_) => {
+			store_lease(&interface.name, None);
+			renumber(&interface.name).await;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn iaaddr(address: &str, preferred: u32, valid: u32) -> (u16, Vec<u8>) {
+		let mut data = Ipv6Addr::from_str(address).unwrap().octets().to_vec();
+		data.extend_from_slice(&preferred.to_be_bytes());
+		data.extend_from_slice(&valid.to_be_bytes());
+		(options::IAADDR, data)
+	}
+
+	fn iaprefix(prefix: &str, length: u8, preferred: u32, valid: u32) -> (u16, Vec<u8>) {
+		let mut data = preferred.to_be_bytes().to_vec();
+		data.extend_from_slice(&valid.to_be_bytes());
+		data.push(length);
+		data.extend_from_slice(&Ipv6Addr::from_str(prefix).unwrap().octets());
+		(options::IAPREFIX, data)
+	}
+
+	fn reply(code: u16, nested: Vec<(u16, Vec<u8>)>) -> Dhcp6Message {
+		let mut reply = Dhcp6Message::new(MessageType::Reply, [1, 2, 3]);
+		reply.add_option(options::SERVERID, vec![0, 3, 0, 1, 2, 0, 0, 0, 0, 1]);
+		// IAID, T1 and T2
+		let mut ia = vec![0, 0, 0, 1, 0, 0, 0, 100, 0, 0, 0, 160];
+		ia.extend_from_slice(&encode_options(&nested));
+		reply.add_option(code, ia);
+		reply
+	}
+
+	#[test]
+	fn discards_invalid_lifetimes() {
+		let lease = Dhcpv6Lease::from_reply(&reply(
+			options::IA_NA,
+			vec![
+				iaaddr("2001:db8::1", 200, 300),
+				// Preferred longer than valid
+				iaaddr("2001:db8::2", 400, 300),
+				// Withdrawn
+				iaaddr("2001:db8::3", 0, 0),
+			],
+		))
+		.unwrap();
+		let addresses: Vec<_> = lease.addresses.iter().map(|a| a.address).collect();
+		assert_eq!(addresses, vec![Ipv6Addr::from_str("2001:db8::1").unwrap()]);
+		assert_eq!(lease.renew_time, 100);
+		assert_eq!(lease.rebind_time, 160);
+
+		let lease = Dhcpv6Lease::from_reply(&reply(
+			options::IA_PD,
+			vec![
+				iaprefix("2001:db8:1::", 56, 300, 200),
+				iaprefix("2001:db8:2::", 56, 200, 300),
+			],
+		))
+		.unwrap();
+		assert_eq!(lease.prefixes.len(), 1);
+		assert_eq!(
+			lease.prefixes[0].prefix,
+			Ipv6Addr::from_str("2001:db8:2::").unwrap()
+		);
+	}
+
+	#[test]
+	fn withdrawing_everything_leaves_no_lease() {
+		assert!(
+			Dhcpv6Lease::from_reply(&reply(options::IA_NA, vec![iaaddr("2001:db8::1", 0, 0)]))
+				.is_none()
+		);
+	}
+}
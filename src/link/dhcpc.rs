@@ -1,18 +1,746 @@
-use tokio::process::Command;
+use std::{
+	collections::BTreeMap,
+	io,
+	mem::{size_of, MaybeUninit},
+	net::{Ipv4Addr, SocketAddrV4},
+	str::FromStr,
+	sync::Mutex,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::link::interface::Interface;
+use pnet::util::MacAddr;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use tokio::{
+	io::unix::AsyncFd,
+	net::UdpSocket,
+	task::JoinHandle,
+	time::{sleep, sleep_until, timeout_at, Instant},
+};
 
-/// Runs a DHCP client on the specified interface
-/// Optionally, it can be told to not background itself after obtaining an IP address (or not)
+use crate::{
+	config::DhcpClientConfig,
+	hooks::run_hook_with_env,
+	link::{
+		dhcp::{
+			self, decode_udp_packet, encode_udp_packet, netmask_to_prefix, options, DhcpMessage,
+			MessageType, BOOTREPLY, BOOTREQUEST, FLAG_BROADCAST,
+		},
+		interface::Interface,
+		resolver, routing, uplink,
+	},
+	state,
+};
+
+/// The smallest MTU a DHCP server may hand out (RFC 2132 9.1)
+const MIN_MTU: u16 = 68;
+
+/// A lease obtained from a DHCP server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DhcpLease {
+	pub address: Ipv4Addr,
+	pub prefix: u8,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub router: Option<Ipv4Addr>,
+	#[serde(default)]
+	pub dns: Vec<Ipv4Addr>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub domain: Option<String>,
+	#[serde(default)]
+	pub ntp: Vec<Ipv4Addr>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub mtu: Option<u16>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	#[serde(default)]
+	pub classless_routes: Vec<ClasslessRoute>,
+	pub server_id: Ipv4Addr,
+	/// Lease, renewal (T1) and rebinding (T2) times in seconds
+	pub lease_time: u32,
+	pub renew_time: u32,
+	pub rebind_time: u32,
+	/// When the lease was last acquired or renewed, in seconds since the epoch
+	pub acquired: u64,
+}
+
+/// A route from option 121. A router of 0.0.0.0 means the destination is on-link
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClasslessRoute {
+	pub destination: Ipv4Addr,
+	pub prefix: u8,
+	pub router: Ipv4Addr,
+}
+
+impl ClasslessRoute {
+	async fn replace(&self, ifname: &str) -> Result<(), String> {
+		let route = format!("{}/{}", self.destination, self.prefix);
+		if self.router.is_unspecified() {
+			routing::replace_route_dev(&route, ifname).await
+		} else {
+			routing::replace_route_via(&self.router.to_string(), &route, ifname).await
+		}
+	}
+
+	async fn delete(&self, ifname: &str) {
+		let route = format!("{}/{}", self.destination, self.prefix);
+		if self.router.is_unspecified() {
+			routing::delete_route_dev(&route, ifname).await;
+		} else {
+			routing::delete_route_via(&self.router.to_string(), &route, ifname).await;
+		}
+	}
+}
+
+impl DhcpLease {
+	fn from_ack(ack: &DhcpMessage, server_id: Ipv4Addr) -> DhcpLease {
+		let address = ack.yiaddr;
+		let prefix = match ack.option_ipv4(options::SUBNET_MASK) {
+			Some(netmask) => netmask_to_prefix(netmask),
+			// Fall back to the classful netmask
+			None => match address.octets()[0] {
+				0..=127 => 8,
+				128..=191 => 16,
+				_ => 24,
+			},
+		};
+		let lease_time = ack.option_u32(options::LEASE_TIME).unwrap_or(3600);
+		DhcpLease {
+			address,
+			prefix,
+			router: ack.option_ipv4_list(options::ROUTER).first().copied(),
+			dns: ack.option_ipv4_list(options::DNS),
+			domain: ack.option_string(options::DOMAIN_NAME),
+			ntp: ack.option_ipv4_list(options::NTP),
+			mtu: ack.option_u16(options::MTU),
+			classless_routes: ack
+				.option_classless_routes()
+				.into_iter()
+				.map(|(destination, prefix, router)| ClasslessRoute {
+					destination,
+					prefix,
+					router,
+				})
+				.collect(),
+			server_id: ack.option_ipv4(options::SERVER_ID).unwrap_or(server_id),
+			lease_time,
+			renew_time: ack
+				.option_u32(options::RENEWAL_TIME)
+				.unwrap_or(lease_time / 2),
+			rebind_time: ack
+				.option_u32(options::REBINDING_TIME)
+				.unwrap_or(lease_time / 8 * 7),
+			acquired: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.unwrap_or_default()
+				.as_secs(),
+		}
+	}
+
+	/// The lease as environment variables for hooks
+	fn hook_env(&self, ifname: &str) -> Vec<(String, String)> {
+		let join = |addresses: &Vec<Ipv4Addr>| {
+			addresses
+				.iter()
+				.map(|address| address.to_string())
+				.collect::<Vec<_>>()
+				.join(" ")
+		};
+		vec![
+			("DHCP_INTERFACE".to_string(), ifname.to_string()),
+			("DHCP_ADDRESS".to_string(), self.address.to_string()),
+			("DHCP_PREFIX".to_string(), self.prefix.to_string()),
+			(
+				"DHCP_ROUTER".to_string(),
+				self.router
+					.map(|router| router.to_string())
+					.unwrap_or_default(),
+			),
+			("DHCP_DNS".to_string(), join(&self.dns)),
+			(
+				"DHCP_DOMAIN".to_string(),
+				self.domain.clone().unwrap_or_default(),
+			),
+			("DHCP_NTP".to_string(), join(&self.ntp)),
+			("DHCP_SERVER_ID".to_string(), self.server_id.to_string()),
+			("DHCP_LEASE_TIME".to_string(), self.lease_time.to_string()),
+			("DHCP_RENEW_TIME".to_string(), self.renew_time.to_string()),
+			("DHCP_REBIND_TIME".to_string(), self.rebind_time.to_string()),
+		]
+	}
+}
+
+/// Lease events, each runs the `dhcp-<event>.<ifname>` hook
+enum LeaseEvent {
+	Bound,
+	Renew,
+	Expired,
+}
+
+fn lease_event(ifname: &str, event: LeaseEvent, lease: &DhcpLease) {
+	let hook = match event {
+		LeaseEvent::Bound => "dhcp-bound",
+		LeaseEvent::Renew => "dhcp-renew",
+		LeaseEvent::Expired => "dhcp-expired",
+	};
+	run_hook_with_env(format!("{hook}.{ifname}"), &lease.hook_env(ifname));
+}
+
+enum Reply {
+	Ack(DhcpLease),
+	Nak,
+}
+
+/// Background renewal tasks and the configuration they were started with, by interface name
+static CLIENTS: Mutex<BTreeMap<String, (JoinHandle<()>, DhcpClientConfig)>> =
+	Mutex::new(BTreeMap::new());
+/// Currently bound leases, by interface name
+static LEASES: Mutex<BTreeMap<String, DhcpLease>> = Mutex::new(BTreeMap::new());
+
+/// Get the lease currently held on an interface
+pub fn get_lease(ifname: &str) -> Option<DhcpLease> {
+	LEASES.lock().unwrap().get(ifname).cloned()
+}
+
+/// Get the DNS servers of the leases on all interfaces
+pub fn get_dns_servers() -> Vec<Ipv4Addr> {
+	LEASES
+		.lock()
+		.unwrap()
+		.values()
+		.flat_map(|lease| lease.dns.clone())
+		.collect()
+}
+
+/// Remembers the lease, also on disk so the same address can be requested again after a restart
+fn store_lease(ifname: &str, lease: Option<&DhcpLease>) {
+	{
+		let mut leases = LEASES.lock().unwrap();
+		match lease {
+			Some(lease) => {
+				state::save(&format!("dhcpc/{ifname}"), lease);
+				leases.insert(ifname.to_string(), lease.clone());
+			}
+			None => {
+				state::remove(&format!("dhcpc/{ifname}"));
+				leases.remove(ifname);
+			}
+		};
+	}
+	resolver::update();
+}
+
+/// Forgets the lease of an interface whose address was taken away, like the one the failover probe
+/// obtained from the other router once netd takes over
+pub fn forget_lease(ifname: &str) {
+	if let Some(lease) = get_lease(ifname) {
+		store_lease(ifname, None);
+		lease_event(ifname, LeaseEvent::Expired, &lease);
+	}
+}
+
+/// Adds a random -1..1s offset to retransmission timeouts, as recommended by RFC 2131
+fn jitter(base: Duration) -> Duration {
+	let offset = thread_rng().gen_range(0..2000);
+	(base + Duration::from_millis(offset)).saturating_sub(Duration::from_secs(1))
+}
+
+fn open_socket(ifname: &str) -> io::Result<UdpSocket> {
+	let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+	socket.set_reuse_address(true)?;
+	socket.set_broadcast(true)?;
+	socket.bind_device(Some(ifname.as_bytes()))?;
+	socket.set_nonblocking(true)?;
+	socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, dhcp::CLIENT_PORT).into())?;
+	UdpSocket::from_std(socket.into())
+}
+
+/// The link layer broadcast address of the interface for IPv4 packets
+fn link_broadcast(ifindex: u32) -> io::Result<SockAddr> {
+	// SAFETY: the zeroed storage is large enough for the sockaddr_ll of the packet family
+	let ((), address) = unsafe {
+		SockAddr::try_init(|storage, len| {
+			let address = storage.cast::<libc::sockaddr_ll>();
+			(*address).sll_family = libc::AF_PACKET as u16;
+			(*address).sll_protocol = (libc::ETH_P_IP as u16).to_be();
+			(*address).sll_ifindex = ifindex as i32;
+			(*address).sll_halen = 6;
+			(*address).sll_addr = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0];
+			*len = size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+			Ok(())
+		})
+	}?;
+	Ok(address)
+}
+
+/// Opens an AF_PACKET socket for the IPv4 packets of the interface.
+/// Used while the interface has no address, so messages are sent from 0.0.0.0 (RFC 2131 4.1)
+/// and replies are received even though the kernel would not deliver them to a UDP socket
+fn open_packet_socket(ifindex: u32) -> io::Result<AsyncFd<Socket>> {
+	// Without a protocol nothing is received until the socket is bound to the interface
+	let socket = Socket::new(Domain::PACKET, Type::DGRAM, None)?;
+	socket.bind(&link_broadcast(ifindex)?)?;
+	socket.set_nonblocking(true)?;
+	AsyncFd::new(socket)
+}
+
+struct Client {
+	ifname: String,
+	ifindex: u32,
+	mac: [u8; 6],
+	/// Used once the interface has an address, for renewing, rebinding and releasing
+	socket: UdpSocket,
+	/// Used while the interface has no address, for discovering and requesting
+	packet_socket: AsyncFd<Socket>,
+	config: DhcpClientConfig,
+}
+
+impl Client {
+	async fn new(interface: &Interface, config: &DhcpClientConfig) -> Option<Client> {
+		let ifname = interface.name.clone();
+		let Ok(mac) = MacAddr::from_str(&interface.get_mac().await) else {
+			println!("[{ifname}] Failed to start DHCP client: no valid MAC address");
+			return None;
+		};
+		// An index of 0 would send and receive on every interface
+		let ifindex = interface.get_index();
+		if ifindex == 0 {
+			println!("[{ifname}] Failed to start DHCP client: no such interface");
+			return None;
+		}
+		let sockets =
+			open_socket(&ifname).and_then(|socket| Ok((socket, open_packet_socket(ifindex)?)));
+		let (socket, packet_socket) = match sockets {
+			Ok(sockets) => sockets,
+			Err(err) => {
+				println!("[{ifname}] Failed to open DHCP client socket: {err}");
+				return None;
+			}
+		};
+		Some(Client {
+			ifname,
+			ifindex,
+			mac: mac.octets(),
+			socket,
+			packet_socket,
+			config: config.clone(),
+		})
+	}
+
+	fn message(&self, message_type: MessageType, xid: u32) -> DhcpMessage {
+		let mut message = DhcpMessage::new(BOOTREQUEST, message_type, xid, self.mac);
+		let client_id = self.config.client_id_bytes().unwrap_or_else(|| {
+			let mut client_id = vec![1];
+			client_id.extend_from_slice(&self.mac);
+			client_id
+		});
+		message.set_option(options::CLIENT_ID, client_id);
+		if message_type == MessageType::Release {
+			return message;
+		}
+		if let Some(hostname) = &self.config.hostname {
+			message.set_option(options::HOSTNAME, hostname.as_bytes().to_vec());
+		}
+		if let Some(vendor_class) = &self.config.vendor_class {
+			message.set_option(options::VENDOR_CLASS, vendor_class.as_bytes().to_vec());
+		}
+		let mut parameters = vec![
+			options::SUBNET_MASK,
+			options::ROUTER,
+			options::DNS,
+			options::DOMAIN_NAME,
+			options::NTP,
+			options::LEASE_TIME,
+			options::RENEWAL_TIME,
+			options::REBINDING_TIME,
+		];
+		if self.config.use_mtu {
+			parameters.push(options::MTU);
+		}
+		if self.config.use_classless_routes {
+			parameters.push(options::CLASSLESS_ROUTES);
+		}
+		for option in &self.config.request_options {
+			if !parameters.contains(option) {
+				parameters.push(*option);
+			}
+		}
+		message.set_option(options::PARAMETER_REQUEST_LIST, parameters);
+		message
+	}
+
+	/// Broadcasts a message from 0.0.0.0 through the packet socket
+	async fn send_unconfigured(
+		&self,
+		message: &DhcpMessage,
+		destination: SocketAddrV4,
+	) -> io::Result<()> {
+		let source = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, dhcp::CLIENT_PORT);
+		let packet = encode_udp_packet(source, destination, &message.encode());
+		let broadcast = link_broadcast(self.ifindex)?;
+		loop {
+			let mut guard = self.packet_socket.writable().await?;
+			if let Ok(result) = guard.try_io(|socket| socket.get_ref().send_to(&packet, &broadcast))
+			{
+				return result.map(|_| ());
+			}
+		}
+	}
+
+	/// The next DHCP message received through the packet socket
+	async fn recv_unconfigured(&self) -> io::Result<DhcpMessage> {
+		let mut buffer = [MaybeUninit::<u8>::uninit(); 1500];
+		loop {
+			let mut guard = self.packet_socket.readable().await?;
+			let Ok(result) = guard.try_io(|socket| socket.get_ref().recv(&mut buffer)) else {
+				continue;
+			};
+			let len = result?;
+			// SAFETY: recv initialized the first len bytes
+			let packet: Vec<u8> = buffer[..len]
+				.iter()
+				.map(|byte| unsafe { byte.assume_init() })
+				.collect();
+			if let Some(message) =
+				decode_udp_packet(&packet, dhcp::CLIENT_PORT).and_then(DhcpMessage::decode)
+			{
+				return Ok(message);
+			}
+		}
+	}
+
+	/// The next DHCP message received through the UDP socket
+	async fn recv(&self) -> io::Result<DhcpMessage> {
+		let mut buffer = [0u8; 1500];
+		loop {
+			let (len, _) = self.socket.recv_from(&mut buffer).await?;
+			if let Some(message) = DhcpMessage::decode(&buffer[..len]) {
+				return Ok(message);
+			}
+		}
+	}
+
+	/// Sends a message and waits up to `wait` for a reply to it.
+	/// Messages without a client address are sent before the interface has one, through the packet socket
+	async fn exchange(
+		&self,
+		message: &DhcpMessage,
+		destination: SocketAddrV4,
+		wait: Duration,
+	) -> Option<DhcpMessage> {
+		let unconfigured = message.ciaddr.is_unspecified();
+		let sent = if unconfigured {
+			self.send_unconfigured(message, destination).await
+		} else {
+			self.socket
+				.send_to(&message.encode(), destination)
+				.await
+				.map(|_| ())
+		};
+		if let Err(err) = sent {
+			println!("[{}] Failed to send DHCP message: {err}", self.ifname);
+			sleep(wait).await;
+			return None;
+		}
+		let deadline = Instant::now() + wait;
+		loop {
+			let received = if unconfigured {
+				timeout_at(deadline, self.recv_unconfigured()).await
+			} else {
+				timeout_at(deadline, self.recv()).await
+			};
+			let reply = match received.ok()? {
+				Ok(reply) => reply,
+				Err(err) => {
+					println!("[{}] Failed to receive DHCP message: {err}", self.ifname);
+					sleep_until(deadline).await;
+					return None;
+				}
+			};
+			if reply.op == BOOTREPLY && reply.xid == message.xid && reply.chaddr == self.mac {
+				return Some(reply);
+			}
+		}
+	}
+
+	/// Runs DISCOVER, OFFER, REQUEST, ACK once, with retransmissions
+	async fn discover(&self) -> Option<DhcpLease> {
+		let xid = thread_rng().gen();
+		let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, dhcp::SERVER_PORT);
+		let mut discover = self.message(MessageType::Discover, xid);
+		discover.flags = FLAG_BROADCAST;
+		// Ask for the configured address, or the one we had before, servers usually hand it out again
+		let previous: Option<DhcpLease> = state::load(&format!("dhcpc/{}", self.ifname));
+		let requested = self
+			.config
+			.requested_address
+			.as_ref()
+			.and_then(|address| address.parse::<Ipv4Addr>().ok())
+			.or(previous.map(|previous| previous.address));
+		if let Some(requested) = requested {
+			discover.set_option(options::REQUESTED_ADDRESS, requested.octets().to_vec());
+		}
+
+		let mut offer = None;
+		for attempt in 0..4 {
+			let wait = jitter(Duration::from_secs(4 << attempt));
+			if let Some(reply) = self.exchange(&discover, broadcast, wait).await {
+				if reply.message_type() == Some(MessageType::Offer) {
+					offer = Some(reply);
+					break;
+				}
+			}
+		}
+		let offer = offer?;
+		let server_id = offer.option_ipv4(options::SERVER_ID)?;
+		println!(
+			"[{}] Received DHCP offer of {} from {server_id}",
+			self.ifname, offer.yiaddr
+		);
+
+		let mut request = self.message(MessageType::Request, xid);
+		request.flags = FLAG_BROADCAST;
+		request.set_option(options::SERVER_ID, server_id.octets().to_vec());
+		request.set_option(options::REQUESTED_ADDRESS, offer.yiaddr.octets().to_vec());
+		for attempt in 0..4 {
+			let wait = jitter(Duration::from_secs(4 << attempt));
+			match self.request(&request, broadcast, server_id, wait).await {
+				Some(Reply::Ack(lease)) => return Some(lease),
+				Some(Reply::Nak) => return None,
+				None => continue,
+			}
+		}
+		None
+	}
+
+	async fn request(
+		&self,
+		request: &DhcpMessage,
+		destination: SocketAddrV4,
+		server_id: Ipv4Addr,
+		wait: Duration,
+	) -> Option<Reply> {
+		let reply = self.exchange(request, destination, wait).await?;
+		match reply.message_type() {
+			Some(MessageType::Ack) => Some(Reply::Ack(DhcpLease::from_ack(&reply, server_id))),
+			Some(MessageType::Nak) => {
+				println!(
+					"[{}] DHCP server refused the request: {}",
+					self.ifname,
+					reply.option_string(options::MESSAGE).unwrap_or_default()
+				);
+				Some(Reply::Nak)
+			}
+			_ => None,
+		}
+	}
+
+	/// Renews the lease until `until`, sending to `destination` (the server when renewing, broadcast when rebinding).
+	/// Retransmits after half the remaining time, but at least every 60 seconds (RFC 2131 4.4.5)
+	async fn renew(
+		&self,
+		lease: &DhcpLease,
+		destination: SocketAddrV4,
+		until: Instant,
+	) -> Option<Reply> {
+		let mut request = self.message(MessageType::Request, thread_rng().gen());
+		request.ciaddr = lease.address;
+		while Instant::now() < until {
+			let remaining = until - Instant::now();
+			let wait = (remaining / 2).max(Duration::from_secs(60)).min(remaining);
+			if let Some(reply) = self
+				.request(&request, destination, lease.server_id, wait)
+				.await
+			{
+				return Some(reply);
+			}
+		}
+		None
+	}
+
+	/// Tries to get a lease until one is obtained, backing off between attempts
+	async fn obtain(&self) -> DhcpLease {
+		let mut backoff = Duration::from_secs(10);
+		loop {
+			if let Some(lease) = self.discover().await {
+				return lease;
+			}
+			println!(
+				"[{}] No DHCP lease obtained, retrying in {}s",
+				self.ifname,
+				backoff.as_secs()
+			);
+			sleep(jitter(backoff)).await;
+			backoff = (backoff * 2).min(Duration::from_secs(300));
+		}
+	}
+
+	/// Keeps the lease renewed, and starts over if it is lost
+	async fn maintain(self, mut lease: DhcpLease) {
+		loop {
+			let bound = Instant::now();
+			let t1 = bound + Duration::from_secs(lease.renew_time as u64);
+			let t2 = bound + Duration::from_secs(lease.rebind_time as u64);
+			let expiry = bound + Duration::from_secs(lease.lease_time as u64);
+			sleep_until(t1).await;
+
+			let server = SocketAddrV4::new(lease.server_id, dhcp::SERVER_PORT);
+			let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, dhcp::SERVER_PORT);
+			let mut reply = self.renew(&lease, server, t2).await;
+			if reply.is_none() {
+				println!("[{}] DHCP server did not answer, rebinding", self.ifname);
+				reply = self.renew(&lease, broadcast, expiry).await;
+			}
+
+			match reply {
+				Some(Reply::Ack(renewed)) => {
+					println!("[{}] Renewed DHCP lease", self.ifname);
+					self.apply_lease(&renewed, Some(&lease)).await;
+					lease = renewed;
+					store_lease(&self.ifname, Some(&lease));
+					lease_event(&self.ifname, LeaseEvent::Renew, &lease);
+				}
+				Some(Reply::Nak) | None => {
+					println!("[{}] Lost DHCP lease for {}", self.ifname, lease.address);
+					self.remove_lease(&lease).await;
+					store_lease(&self.ifname, None);
+					lease_event(&self.ifname, LeaseEvent::Expired, &lease);
+					lease = self.obtain().await;
+					self.apply_lease(&lease, None).await;
+					store_lease(&self.ifname, Some(&lease));
+					lease_event(&self.ifname, LeaseEvent::Bound, &lease);
+				}
+			}
+		}
+	}
+
+	/// Configures the interface with the lease
+	async fn apply_lease(&self, lease: &DhcpLease, previous: Option<&DhcpLease>) {
+		let interface = Interface::get_from_name(&self.ifname);
+		if let Some(previous) = previous {
+			// Only touch what changed, removing the address on every renewal would break connections
+			if previous.address != lease.address || previous.prefix != lease.prefix {
+				self.remove_lease(previous).await;
+			} else if previous.router != lease.router
+				|| previous.classless_routes != lease.classless_routes
+			{
+				self.remove_routes(previous).await;
+			}
+		}
+		interface
+			.replace_address(&lease.address.to_string(), lease.prefix)
+			.await;
+		let ifname = &self.ifname;
+		if let (true, Some(mtu)) = (self.config.use_mtu, lease.mtu) {
+			// The driver may not support as much as the server hands out
+			let max_mtu = interface.get_max_mtu().await.unwrap_or(u16::MAX);
+			if !(MIN_MTU..=max_mtu).contains(&mtu) {
+				println!("[{ifname}] Ignoring MTU {mtu} from the DHCP server");
+			} else if let Err(error) = interface.set_mtu(mtu).await {
+				println!("[{ifname}] Could not set MTU {mtu}: {error}");
+			}
+		}
+		// Option 121 replaces the router option if the server sends both (RFC 3442)
+		if self.config.use_classless_routes && !lease.classless_routes.is_empty() {
+			for route in &lease.classless_routes {
+				if let Err(error) = route.replace(ifname).await {
+					println!(
+						"[{ifname}] Could not add route to {}/{} via {}: {error}",
+						route.destination, route.prefix, route.router
+					);
+				}
+			}
+		} else if let (true, Some(router)) = (self.uses_router(), lease.router) {
+			if let Err(error) =
+				routing::replace_route_via(&router.to_string(), "default", ifname).await
+			{
+				println!("[{ifname}] Could not add default route via {router}: {error}");
+			}
+		}
+	}
+
+	/// Removes the address and routes of the lease from the interface
+	async fn remove_lease(&self, lease: &DhcpLease) {
+		self.remove_routes(lease).await;
+		Interface::get_from_name(&self.ifname)
+			.delete_address(&lease.address.to_string(), lease.prefix)
+			.await;
+	}
+
+	/// Whether the router option becomes the default route. The group of an uplink installs it instead
+	fn uses_router(&self) -> bool {
+		self.config.use_router && !uplink::is_member(&self.ifname)
+	}
+
+	async fn remove_routes(&self, lease: &DhcpLease) {
+		if self.config.use_classless_routes {
+			for route in &lease.classless_routes {
+				route.delete(&self.ifname).await;
+			}
+		}
+		if let (true, Some(router)) = (self.uses_router(), lease.router) {
+			routing::delete_route_via(&router.to_string(), "default", &self.ifname).await;
+		}
+	}
+}
+
+/// Runs a DHCP client on the specified interface and applies the lease
+/// Optionally, it can be told to give up if no lease is obtained and to not keep the lease renewed in the background
 /// That is useful for failover scenarios
-/// Note: Check the return value to see if the command was successful
-pub async fn dhcp_client(interface: &Interface, no_bg: bool) -> bool {
-	let mut cmd = Command::new("udhcpc");
-	cmd.arg("-i").arg(&interface.name);
-	if no_bg {
-		cmd.arg("-n");
-		cmd.arg("-q");
-	}
-	let output = cmd.output().await.expect("Failed to execute command");
-	output.status.success()
+pub async fn dhcp_client(
+	interface: &Interface,
+	no_bg: bool,
+	config: &DhcpClientConfig,
+) -> Option<DhcpLease> {
+	stop_client(interface).await;
+	let client = Client::new(interface, config).await?;
+	let lease = if no_bg {
+		client.discover().await?
+	} else {
+		client.obtain().await
+	};
+	client.apply_lease(&lease, None).await;
+	store_lease(&interface.name, Some(&lease));
+	lease_event(&interface.name, LeaseEvent::Bound, &lease);
+	if !no_bg {
+		let task = tokio::spawn(client.maintain(lease.clone()));
+		CLIENTS
+			.lock()
+			.unwrap()
+			.insert(interface.name.clone(), (task, config.clone()));
+	}
+	Some(lease)
+}
+
+/// Stops the DHCP client running on the interface and releases its lease
+pub async fn stop_client(interface: &Interface) {
+	let client = CLIENTS.lock().unwrap().remove(&interface.name);
+	let config = match client {
+		Some((task, config)) => {
+			task.abort();
+			config
+		}
+		None => DhcpClientConfig::default(),
+	};
+	let Some(lease) = get_lease(&interface.name) else {
+		return;
+	};
+	store_lease(&interface.name, None);
+	println!(
+		"[{}] Releasing DHCP lease for {}",
+		interface.name, lease.address
+	);
+	if interface.exists().await {
+		let Some(client) = Client::new(interface, &config).await else {
+			return;
+		};
+		let mut release = client.message(MessageType::Release, thread_rng().gen());
+		release.ciaddr = lease.address;
+		release.set_option(options::SERVER_ID, lease.server_id.octets().to_vec());
+		let server = SocketAddrV4::new(lease.server_id, dhcp::SERVER_PORT);
+		let _ = client.socket.send_to(&release.encode(), server).await;
+		client.remove_lease(&lease).await;
+	}
 }
@@ -1,71 +1,1586 @@
-use rand::{thread_rng, Rng};
-
-pub struct DHCPServer {
-	pub start: String,
-	pub end: String,
-	pub interface: String,
-	pub dns: String,
-	pub netmask: String,
-	pub router: String,
-	pub lease: u16,
-}
-
-pub fn generate_random_string(length: usize) -> String {
-	let charset: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
-	let mut rng = thread_rng();
-	let random_string: String = (0..length)
-		.map(|_| {
-			let idx = rng.gen_range(0..charset.len());
-			char::from(charset[idx])
+use std::{
+	collections::BTreeMap,
+	net::{Ipv4Addr, SocketAddrV4},
+	str::FromStr,
+	sync::Mutex,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+	net::UdpSocket,
+	sync::Notify,
+	task::JoinHandle,
+	time::{interval, timeout},
+};
+
+use crate::{
+	arp::ArpListener,
+	config::{
+		client_id_bytes, AddressList, DhcpBootRule, DhcpClientClass, DhcpOption, DhcpOptionType,
+		DhcpPoolConfig, DhcpReservation, DhcpRoute, InterfaceDhcpConfig,
+	},
+	link::{
+		dhcp::{
+			self, encode_classless_routes, options, DhcpMessage, MessageType, BOOTREPLY,
+			BOOTREQUEST,
+		},
+		dhcp6::{encode_domain_list, to_hex},
+		interface::Interface,
+		matching::glob_match,
+	},
+	state,
+};
+
+/// How long an offered address is held for the client before it can be offered to someone else
+const OFFER_TIME: u64 = 60;
+/// Addresses that were declined or answered an ARP probe are not handed out for this long
+const CONFLICT_TIME: u64 = 3600;
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+/// How many addresses of every pool are probed ahead, so DISCOVER is answered without waiting for probes
+const READY_ADDRESSES: usize = 2;
+/// How long an address that did not answer a probe is offered without probing it again
+const READY_TIME: u64 = 60;
+/// Options netd sets itself, either from the DHCP protocol or from their own setting
+const MANAGED_OPTIONS: [u8; 20] = [
+	options::PAD,
+	options::SUBNET_MASK,
+	options::ROUTER,
+	options::DNS,
+	options::HOSTNAME,
+	options::DOMAIN_NAME,
+	options::MTU,
+	options::NTP,
+	options::REQUESTED_ADDRESS,
+	options::LEASE_TIME,
+	options::MESSAGE_TYPE,
+	options::SERVER_ID,
+	options::PARAMETER_REQUEST_LIST,
+	options::MESSAGE,
+	options::RENEWAL_TIME,
+	options::REBINDING_TIME,
+	options::RELAY_AGENT_INFORMATION,
+	options::DOMAIN_SEARCH,
+	options::CLASSLESS_ROUTES,
+	options::END,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LeaseState {
+	Offered,
+	Bound,
+	Expired,
+	/// Something else is using the address
+	Conflict,
+}
+
+/// An address handed out by the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServerLease {
+	pub address: Ipv4Addr,
+	/// The client identifier, or the MAC address if the client did not send one, hex encoded
+	pub client: String,
+	pub mac: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub hostname: Option<String>,
+	pub state: LeaseState,
+	/// In seconds since the epoch
+	pub expires: u64,
+}
+
+impl ServerLease {
+	/// Whether the address can be given to a client other than the last holder
+	fn is_free(&self, now: u64) -> bool {
+		match self.state {
+			LeaseState::Expired => true,
+			_ => self.expires <= now,
+		}
+	}
+}
+
+/// The leases of a server as saved on disk
+#[derive(Serialize, Deserialize, Default)]
+struct LeaseDatabase {
+	#[serde(default)]
+	leases: Vec<ServerLease>,
+}
+
+/// Running servers by interface name
+static SERVERS: Mutex<BTreeMap<String, JoinHandle<()>>> = Mutex::new(BTreeMap::new());
+/// Leases of every server by interface name, then by address
+static LEASES: Mutex<BTreeMap<String, BTreeMap<Ipv4Addr, ServerLease>>> =
+	Mutex::new(BTreeMap::new());
+/// Held while copying and writing the leases of a server
+static SAVING: Mutex<()> = Mutex::new(());
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs()
+}
+
+fn state_name(ifname: &str) -> String {
+	format!("dhcpd/{ifname}")
+}
+
+/// Saves the leases of the interface to disk in the background, so the servers don't wait on the disk
+fn save_leases(ifname: &str) {
+	let ifname = ifname.to_string();
+	tokio::task::spawn_blocking(move || write_leases(&ifname));
+}
+
+/// Writes the current leases of the interface to disk.
+/// Offers and conflicts are short lived, so only bound and expired leases are written.
+/// The leases are copied while holding SAVING, so a write that was queued earlier can't overwrite newer leases
+fn write_leases(ifname: &str) {
+	let _saving = SAVING.lock().unwrap();
+	let Some(leases) = LEASES.lock().unwrap().get(ifname).map(|leases| {
+		leases
+			.values()
+			.filter(|lease| matches!(lease.state, LeaseState::Bound | LeaseState::Expired))
+			.cloned()
+			.collect()
+	}) else {
+		return;
+	};
+	state::save(&state_name(ifname), &LeaseDatabase { leases });
+}
+
+/// Get the leases of the DHCP server running on an interface
+pub fn get_leases(ifname: &str) -> Vec<ServerLease> {
+	LEASES
+		.lock()
+		.unwrap()
+		.get(ifname)
+		.map(|leases| leases.values().cloned().collect())
+		.unwrap_or_default()
+}
+
+/// Get the hostnames and addresses of the clients currently holding a lease, on all interfaces
+pub fn get_hostnames() -> Vec<(String, Ipv4Addr)> {
+	let now = now();
+	LEASES
+		.lock()
+		.unwrap()
+		.values()
+		.flat_map(|leases| leases.values())
+		.filter(|lease| lease.state == LeaseState::Bound && lease.expires > now)
+		.filter_map(|lease| Some((lease.hostname.clone()?, lease.address)))
+		.collect()
+}
+
+/// Forgets the lease of an address, so the client can't renew it and the address can be handed out again.
+/// Returns the interface the lease was on
+pub fn revoke_lease(address: Ipv4Addr) -> Option<String> {
+	let ifname = {
+		let mut servers = LEASES.lock().unwrap();
+		let (ifname, leases) = servers
+			.iter_mut()
+			.find(|(_, leases)| leases.contains_key(&address))?;
+		let lease = leases.remove(&address)?;
+		println!(
+			"[{ifname}] Revoked lease of {address} to {} ({})",
+			lease.mac,
+			lease.hostname.as_deref().unwrap_or("unknown")
+		);
+		ifname.clone()
+	};
+	save_leases(&ifname);
+	Some(ifname)
+}
+
+fn format_mac(mac: &[u8; 6]) -> String {
+	mac.iter()
+		.map(|byte| format!("{byte:02x}"))
+		.collect::<Vec<_>>()
+		.join(":")
+}
+
+fn open_socket(ifname: &str) -> std::io::Result<UdpSocket> {
+	let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+	// Every interface runs its own server on port 67, told apart by the bound device
+	socket.set_reuse_address(true)?;
+	socket.set_broadcast(true)?;
+	socket.bind_device(Some(ifname.as_bytes()))?;
+	socket.set_nonblocking(true)?;
+	socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, dhcp::SERVER_PORT).into())?;
+	UdpSocket::from_std(socket.into())
+}
+
+/// A validated reservation
+struct Reservation {
+	mac: Option<[u8; 6]>,
+	client_id: Option<Vec<u8>>,
+	address: Ipv4Addr,
+	hostname: Option<String>,
+	router: Option<Ipv4Addr>,
+	dns: Option<Vec<Ipv4Addr>>,
+	lease_time: Option<u32>,
+	routes: Option<Vec<(Ipv4Addr, u8, Ipv4Addr)>>,
+	/// Sent after the options of the pool, so they replace those with the same code
+	options: Vec<(u8, Vec<u8>)>,
+	next_server: Option<Ipv4Addr>,
+	boot_file: Option<String>,
+}
+
+impl Reservation {
+	fn matches(&self, request: &DhcpMessage) -> bool {
+		match (&self.client_id, request.option(options::CLIENT_ID)) {
+			(Some(reserved), Some(client_id)) => reserved.as_slice() == client_id,
+			_ => self.mac == Some(request.chaddr),
+		}
+	}
+}
+
+/// A validated boot rule
+struct BootRule {
+	architecture: Option<u16>,
+	vendor_class: Option<String>,
+	next_server: Option<Ipv4Addr>,
+	boot_file: String,
+}
+
+impl BootRule {
+	fn matches(&self, request: &DhcpMessage) -> bool {
+		let architectures: Vec<u16> = request
+			.option(options::CLIENT_ARCHITECTURE)
+			.map(|data| {
+				data.chunks_exact(2)
+					.map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
+					.collect()
+			})
+			.unwrap_or_default();
+		let vendor_class = request.option_string(options::VENDOR_CLASS);
+		self.architecture
+			.is_none_or(|architecture| architectures.contains(&architecture))
+			&& self.vendor_class.as_ref().is_none_or(|pattern| {
+				vendor_class
+					.as_ref()
+					.is_some_and(|vendor_class| glob_match(pattern, vendor_class))
+			})
+	}
+}
+
+/// A validated pool
+struct Pool {
+	start: u32,
+	end: u32,
+	netmask: Ipv4Addr,
+	/// The interface address in the subnet of the pool, or the main address for relayed subnets
+	server_address: Ipv4Addr,
+	/// The subnet is not on the interface, so it is only reached through relays
+	relayed: bool,
+	router: Option<Ipv4Addr>,
+	dns: Vec<Ipv4Addr>,
+	/// Classless static routes as destination, prefix length and router
+	routes: Vec<(Ipv4Addr, u8, Ipv4Addr)>,
+	/// Options that are the same for every client
+	options: Vec<(u8, Vec<u8>)>,
+	lease_time: u32,
+	client_class: Option<DhcpClientClass>,
+}
+
+impl Pool {
+	fn in_range(&self, address: Ipv4Addr) -> bool {
+		(self.start..=self.end).contains(&u32::from(address)) && address != self.server_address
+	}
+
+	fn in_subnet(&self, address: Ipv4Addr) -> bool {
+		let mask = u32::from(self.netmask);
+		u32::from(address) & mask == self.start & mask
+	}
+
+	fn serves_class(&self, request: &DhcpMessage) -> bool {
+		let Some(class) = &self.client_class else {
+			return true;
+		};
+		let matches = |pattern: &Option<String>, value: Option<String>| {
+			pattern.as_ref().is_none_or(|pattern| {
+				value
+					.as_ref()
+					.is_some_and(|value| glob_match(pattern, value))
+			})
+		};
+		matches(
+			&class.vendor_class,
+			request.option_string(options::VENDOR_CLASS),
+		) && matches(
+			&class.user_class,
+			request.option_string(options::USER_CLASS),
+		) && matches(&class.mac, Some(format_mac(&request.chaddr)))
+	}
+}
+
+struct Server {
+	ifname: String,
+	socket: UdpSocket,
+	/// The main address of the interface
+	server_address: Ipv4Addr,
+	pools: Vec<Pool>,
+	reservations: Vec<Reservation>,
+	next_server: Option<Ipv4Addr>,
+	boot_file: Option<String>,
+	boot_rules: Vec<BootRule>,
+	/// Addresses that did not answer an ARP probe, with when they were probed
+	ready: Mutex<BTreeMap<Ipv4Addr, u64>>,
+	/// Wakes up the probing when a ready address was handed out
+	refill: Notify,
+}
+
+impl Server {
+	fn ifname(&self) -> &str {
+		&self.ifname
+	}
+
+	/// The pools of the subnet the request came from, which is the relay's subnet for relayed requests
+	fn segment_pools(&self, request: &DhcpMessage) -> Vec<&Pool> {
+		self.pools
+			.iter()
+			.filter(|pool| {
+				if request.giaddr.is_unspecified() {
+					!pool.relayed
+				} else {
+					pool.in_subnet(request.giaddr)
+				}
+			})
+			.collect()
+	}
+
+	/// The pools addresses can be handed out to the client from, those for its client class first
+	fn candidate_pools<'a>(&self, segment: &[&'a Pool], request: &DhcpMessage) -> Vec<&'a Pool> {
+		let mut pools: Vec<&Pool> = segment
+			.iter()
+			.copied()
+			.filter(|pool| pool.serves_class(request))
+			.collect();
+		pools.sort_by_key(|pool| pool.client_class.is_none());
+		pools
+	}
+
+	/// The pool whose options are sent along with an address that was not allocated from a pool
+	fn pool_for<'a>(
+		&self,
+		candidates: &[&'a Pool],
+		segment: &[&'a Pool],
+		address: Ipv4Addr,
+	) -> Option<&'a Pool> {
+		candidates
+			.iter()
+			.find(|pool| pool.in_range(address))
+			.or_else(|| {
+				candidates
+					.iter()
+					.chain(segment)
+					.find(|pool| pool.in_subnet(address))
+			})
+			.copied()
+	}
+
+	fn is_own_address(&self, address: Ipv4Addr) -> bool {
+		address == self.server_address
+			|| self.pools.iter().any(|pool| pool.server_address == address)
+	}
+
+	fn reservation(&self, request: &DhcpMessage) -> Option<&Reservation> {
+		self.reservations
+			.iter()
+			.find(|reservation| reservation.matches(request))
+	}
+
+	fn with_leases<T>(&self, f: impl FnOnce(&mut BTreeMap<Ipv4Addr, ServerLease>) -> T) -> T {
+		let mut leases = LEASES.lock().unwrap();
+		f(leases.entry(self.ifname().to_string()).or_default())
+	}
+
+	/// Changes the leases and saves them to disk
+	fn update_leases<T>(&self, f: impl FnOnce(&mut BTreeMap<Ipv4Addr, ServerLease>) -> T) -> T {
+		let result = self.with_leases(f);
+		save_leases(self.ifname());
+		result
+	}
+
+	fn lease_of(&self, client: &str) -> Option<ServerLease> {
+		self.with_leases(|leases| {
+			leases
+				.values()
+				.find(|lease| lease.client == client && lease.state != LeaseState::Conflict)
+				.cloned()
+		})
+	}
+
+	/// Whether the address can be given to the client from the pool
+	fn is_available(&self, address: Ipv4Addr, client: &str, now: u64, pool: &Pool) -> bool {
+		pool.in_range(address)
+			&& self.with_leases(|leases| match leases.get(&address) {
+				Some(lease) if lease.state == LeaseState::Conflict => lease.expires <= now,
+				Some(lease) => lease.client == client || lease.is_free(now),
+				None => true,
+			})
+	}
+
+	/// Whether another client holds a bound lease on the address that did not run out yet
+	fn is_held_by_other(&self, address: Ipv4Addr, client: &str) -> bool {
+		let now = now();
+		self.with_leases(|leases| {
+			leases.get(&address).is_some_and(|lease| {
+				lease.client != client && lease.state == LeaseState::Bound && !lease.is_free(now)
+			})
+		})
+	}
+
+	fn mark_conflict(&self, address: Ipv4Addr) {
+		println!("[{}] {address} is already in use", self.ifname());
+		self.with_leases(|leases| {
+			leases.insert(
+				address,
+				ServerLease {
+					address,
+					client: String::new(),
+					mac: String::new(),
+					hostname: None,
+					state: LeaseState::Conflict,
+					expires: now() + CONFLICT_TIME,
+				},
+			)
+		});
+	}
+
+	/// Picks an address from the first pool that has one for the client: its current one,
+	/// or the one it asked for or another one if they were probed ahead
+	fn allocate<'a>(
+		&self,
+		client: &str,
+		requested: Option<Ipv4Addr>,
+		pools: &[&'a Pool],
+	) -> Option<(Ipv4Addr, &'a Pool)> {
+		let now = now();
+		let current = self.lease_of(client).map(|lease| lease.address);
+		let mut ready = self.ready.lock().unwrap();
+		ready.retain(|_, probed| *probed + READY_TIME > now);
+		let allocated = pools.iter().find_map(|pool| {
+			if let Some(current) =
+				current.filter(|current| self.is_available(*current, client, now, pool))
+			{
+				return Some((current, *pool));
+			}
+			let available = |address: &Ipv4Addr| self.is_available(*address, client, now, pool);
+			requested
+				.filter(|requested| ready.contains_key(requested))
+				.filter(available)
+				.or_else(|| ready.keys().copied().find(available))
+				.map(|address| (address, *pool))
+		});
+		if let Some((address, _)) = allocated {
+			ready.remove(&address);
+		}
+		self.refill.notify_one();
+		allocated
+	}
+
+	/// The next address of the pool to probe: one never handed out, searched from where the last search ended,
+	/// otherwise the one that expired first, so returning clients usually get their old address
+	fn next_candidate(&self, pool: &Pool, cursor: &mut u32) -> Option<Ipv4Addr> {
+		let now = now();
+		let ready = self.ready.lock().unwrap();
+		self.with_leases(|leases| {
+			let range = Ipv4Addr::from(pool.start)..=Ipv4Addr::from(pool.end);
+			let size = (pool.end - pool.start) as usize + 1;
+			let taken = leases.range(range.clone()).count()
+				+ ready.range(range.clone()).count()
+				+ usize::from(range.contains(&pool.server_address));
+			if taken < size {
+				loop {
+					let address = Ipv4Addr::from(*cursor);
+					*cursor = if *cursor >= pool.end {
+						pool.start
+					} else {
+						*cursor + 1
+					};
+					if address != pool.server_address
+						&& !leases.contains_key(&address)
+						&& !ready.contains_key(&address)
+					{
+						return Some(address);
+					}
+				}
+			}
+			leases
+				.range(range)
+				.map(|(_, lease)| lease)
+				.filter(|lease| lease.state != LeaseState::Conflict && lease.is_free(now))
+				.filter(|lease| !ready.contains_key(&lease.address))
+				.min_by_key(|lease| lease.expires)
+				.map(|lease| lease.address)
+		})
+	}
+
+	/// How many addresses of the pool are ready to be offered, forgetting the stale ones
+	fn ready_count(&self, pool: &Pool) -> usize {
+		let now = now();
+		let mut ready = self.ready.lock().unwrap();
+		ready.retain(|_, probed| *probed + READY_TIME > now);
+		ready
+			.range(Ipv4Addr::from(pool.start)..=Ipv4Addr::from(pool.end))
+			.count()
+	}
+
+	/// Keeps a few addresses of every pool probed with ARP, so they can be offered right away.
+	/// Addresses of relayed subnets are not on this link, so they can't be probed
+	async fn prepare_addresses(&self) {
+		let mut cursors: Vec<u32> = self.pools.iter().map(|pool| pool.start).collect();
+		loop {
+			let mut listener = None;
+			for (pool, cursor) in self.pools.iter().zip(&mut cursors) {
+				while self.ready_count(pool) < READY_ADDRESSES {
+					let Some(address) = self.next_candidate(pool, cursor) else {
+						break;
+					};
+					if !pool.relayed {
+						if listener.is_none() {
+							listener =
+								ArpListener::open(&Interface::get_from_name(&self.ifname)).await;
+						}
+						let Some(listener) = &mut listener else {
+							break;
+						};
+						listener.send_request(Ipv4Addr::UNSPECIFIED, address);
+						if listener
+							.wait_for_sender(address, PROBE_TIMEOUT)
+							.await
+							.is_some()
+						{
+							self.mark_conflict(address);
+							continue;
+						}
+					}
+					self.ready.lock().unwrap().insert(address, now());
+				}
+			}
+			drop(listener);
+			// Probed addresses go stale, so they are probed again after a while
+			let _ = timeout(Duration::from_secs(READY_TIME / 2), self.refill.notified()).await;
+		}
+	}
+
+	fn store(
+		&self,
+		address: Ipv4Addr,
+		request: &DhcpMessage,
+		client: &str,
+		state: LeaseState,
+		duration: u64,
+	) {
+		let lease = ServerLease {
+			address,
+			client: client.to_string(),
+			mac: format_mac(&request.chaddr),
+			hostname: request.option_string(options::HOSTNAME).or_else(|| {
+				self.reservation(request)
+					.and_then(|reservation| reservation.hostname.clone())
+			}),
+			state,
+			expires: now() + duration,
+		};
+		let update = |leases: &mut BTreeMap<Ipv4Addr, ServerLease>| {
+			// A client only holds one address
+			leases.retain(|other, lease| *other == address || lease.client != client);
+			leases.insert(address, lease);
+		};
+		// Offers aren't saved, the client gets a new one if the server restarts
+		if state == LeaseState::Offered {
+			self.with_leases(update);
+		} else {
+			self.update_leases(update);
+		}
+	}
+
+	fn reply(
+		&self,
+		request: &DhcpMessage,
+		message_type: MessageType,
+		server_address: Ipv4Addr,
+	) -> DhcpMessage {
+		let mut reply = DhcpMessage::new(BOOTREPLY, message_type, request.xid, request.chaddr);
+		reply.flags = request.flags;
+		reply.giaddr = request.giaddr;
+		reply.set_option(options::SERVER_ID, server_address.octets().to_vec());
+		reply
+	}
+
+	/// Adds the configuration options of the pool to an OFFER or ACK, the reservation overrides them
+	fn add_options(
+		&self,
+		reply: &mut DhcpMessage,
+		pool: &Pool,
+		with_lease: bool,
+		reservation: Option<&Reservation>,
+	) {
+		if with_lease {
+			let lease_time = lease_time(pool, reservation);
+			reply.set_option(options::LEASE_TIME, lease_time.to_be_bytes().to_vec());
+			reply.set_option(
+				options::RENEWAL_TIME,
+				(lease_time / 2).to_be_bytes().to_vec(),
+			);
+			reply.set_option(
+				options::REBINDING_TIME,
+				(lease_time / 8 * 7).to_be_bytes().to_vec(),
+			);
+		}
+		reply.set_option(options::SUBNET_MASK, pool.netmask.octets().to_vec());
+		let router = reservation
+			.and_then(|reservation| reservation.router)
+			.or(pool.router);
+		if let Some(router) = router {
+			reply.set_option(options::ROUTER, router.octets().to_vec());
+		}
+		let dns = reservation
+			.and_then(|reservation| reservation.dns.as_ref())
+			.unwrap_or(&pool.dns);
+		if !dns.is_empty() {
+			reply.set_option(
+				options::DNS,
+				dns.iter().flat_map(|dns| dns.octets()).collect(),
+			);
+		}
+		let reservation_options = reservation
+			.map(|reservation| reservation.options.as_slice())
+			.unwrap_or_default();
+		for (code, data) in pool.options.iter().chain(reservation_options) {
+			reply.set_option(*code, data.clone());
+		}
+		let routes = reservation
+			.and_then(|reservation| reservation.routes.as_ref())
+			.unwrap_or(&pool.routes);
+		if !routes.is_empty() {
+			// Clients that understand option 121 ignore the router option, so the default route is included (RFC 3442)
+			let mut routes = routes.clone();
+			if let Some(router) = router {
+				routes.push((Ipv4Addr::UNSPECIFIED, 0, router));
+			}
+			reply.set_option(options::CLASSLESS_ROUTES, encode_classless_routes(&routes));
+		}
+		if let Some(hostname) = reservation.and_then(|reservation| reservation.hostname.as_ref()) {
+			reply.set_option(options::HOSTNAME, hostname.as_bytes().to_vec());
+		}
+	}
+
+	/// Tells network booting clients where to load their boot file from.
+	/// The next server is sent even without a boot file, some clients ask it for their configuration
+	fn add_boot(
+		&self,
+		request: &DhcpMessage,
+		reply: &mut DhcpMessage,
+		reservation: Option<&Reservation>,
+	) {
+		let (next_server, boot_file) =
+			match self.boot_rules.iter().find(|rule| rule.matches(request)) {
+				Some(rule) => (rule.next_server.or(self.next_server), Some(&rule.boot_file)),
+				None => (self.next_server, self.boot_file.as_ref()),
+			};
+		let next_server = reservation
+			.and_then(|reservation| reservation.next_server)
+			.or(next_server);
+		let boot_file = reservation
+			.and_then(|reservation| reservation.boot_file.as_ref())
+			.or(boot_file);
+		reply.siaddr = next_server.unwrap_or(Ipv4Addr::UNSPECIFIED);
+		if let Some(boot_file) = boot_file {
+			reply.file = boot_file.clone();
+		}
+	}
+
+	async fn send(&self, request: &DhcpMessage, reply: &DhcpMessage) {
+		let mut reply = reply.clone();
+		// Relays expect their information back, as the last option (RFC 3046 2.2)
+		if let Some(agent_information) = request.option(options::RELAY_AGENT_INFORMATION) {
+			reply.set_option(options::RELAY_AGENT_INFORMATION, agent_information.to_vec());
+		}
+		let destination = if !request.giaddr.is_unspecified() {
+			SocketAddrV4::new(request.giaddr, dhcp::SERVER_PORT)
+		} else if !request.ciaddr.is_unspecified() && reply.message_type() != Some(MessageType::Nak)
+		{
+			SocketAddrV4::new(request.ciaddr, dhcp::CLIENT_PORT)
+		} else {
+			// The client has no address yet, so it can't be reached without its MAC in the ARP table
+			SocketAddrV4::new(Ipv4Addr::BROADCAST, dhcp::CLIENT_PORT)
+		};
+		if let Err(err) = self.socket.send_to(&reply.encode(), destination).await {
+			println!("[{}] Failed to send DHCP reply: {err}", self.ifname());
+		}
+	}
+
+	async fn handle(&self, request: DhcpMessage) {
+		if request.op != BOOTREQUEST {
+			return;
+		}
+		let Some(message_type) = request.message_type() else {
+			return;
+		};
+		let client = to_hex(
+			request
+				.option(options::CLIENT_ID)
+				.unwrap_or(&request.chaddr),
+		);
+		let mac = format_mac(&request.chaddr);
+		let requested = request.option_ipv4(options::REQUESTED_ADDRESS);
+		let segment = self.segment_pools(&request);
+		let candidates = self.candidate_pools(&segment, &request);
+		// Reservations only apply on the subnet they are in
+		let reservation = self.reservation(&request).filter(|reservation| {
+			segment
+				.iter()
+				.any(|pool| pool.in_subnet(reservation.address))
+		});
+
+		match message_type {
+			MessageType::Discover => {
+				let allocated = match reservation {
+					// The client waits until the address was released or the lease ran out
+					Some(reservation) if self.is_held_by_other(reservation.address, &client) => {
+						println!(
+							"[{}] {} is reserved for {mac}, but still leased to another client",
+							self.ifname(),
+							reservation.address
+						);
+						return;
+					}
+					Some(reservation) => self
+						.pool_for(&candidates, &segment, reservation.address)
+						.map(|pool| (reservation.address, pool)),
+					None => self.allocate(&client, requested, &candidates),
+				};
+				let Some((address, pool)) = allocated else {
+					println!("[{}] No free address for {mac}", self.ifname());
+					return;
+				};
+				// Keep a bound lease bound, the client may just be rebooting
+				if self
+					.lease_of(&client)
+					.filter(|lease| lease.address == address && lease.state == LeaseState::Bound)
+					.is_none()
+				{
+					self.store(address, &request, &client, LeaseState::Offered, OFFER_TIME);
+				}
+				println!("[{}] Offering {address} to {mac}", self.ifname());
+				let mut reply = self.reply(&request, MessageType::Offer, pool.server_address);
+				reply.yiaddr = address;
+				self.add_options(&mut reply, pool, true, reservation);
+				self.add_boot(&request, &mut reply, reservation);
+				self.send(&request, &reply).await;
+			}
+			MessageType::Request => {
+				let server_id = request.option_ipv4(options::SERVER_ID);
+				if server_id.is_some_and(|id| !self.is_own_address(id)) {
+					// The client accepted another server's offer
+					self.with_leases(|leases| {
+						leases.retain(|_, lease| {
+							lease.client != client || lease.state != LeaseState::Offered
+						})
+					});
+					return;
+				}
+				let address = requested.unwrap_or(request.ciaddr);
+				if address.is_unspecified() {
+					return;
+				}
+				let now = now();
+				let pool = match reservation {
+					// A client with a reservation is moved to its reserved address
+					Some(reservation) => self
+						.pool_for(&candidates, &segment, address)
+						.filter(|_| address == reservation.address)
+						.filter(|_| !self.is_held_by_other(address, &client)),
+					// A renewing client must still hold the lease, it may have been revoked
+					None if requested.is_none() => {
+						self.pool_for(&candidates, &segment, address).filter(|_| {
+							self.lease_of(&client)
+								.is_some_and(|lease| lease.address == address)
+						})
+					}
+					None => candidates
+						.iter()
+						.find(|pool| self.is_available(address, &client, now, pool))
+						.copied(),
+				};
+				let Some(pool) = pool else {
+					println!("[{}] Refusing {address} to {mac}", self.ifname());
+					let server_address = segment
+						.first()
+						.map_or(self.server_address, |pool| pool.server_address);
+					let reply = self.reply(&request, MessageType::Nak, server_address);
+					self.send(&request, &reply).await;
+					return;
+				};
+				self.store(
+					address,
+					&request,
+					&client,
+					LeaseState::Bound,
+					lease_time(pool, reservation) as u64,
+				);
+				println!("[{}] Leased {address} to {mac}", self.ifname());
+				let mut reply = self.reply(&request, MessageType::Ack, pool.server_address);
+				reply.yiaddr = address;
+				reply.ciaddr = request.ciaddr;
+				self.add_options(&mut reply, pool, true, reservation);
+				self.add_boot(&request, &mut reply, reservation);
+				self.send(&request, &reply).await;
+			}
+			MessageType::Decline => {
+				if let Some(address) = requested {
+					println!("[{}] {mac} declined {address}", self.ifname());
+					self.mark_conflict(address);
+				}
+			}
+			MessageType::Release => {
+				println!("[{}] {mac} released {}", self.ifname(), request.ciaddr);
+				self.update_leases(|leases| {
+					if let Some(lease) = leases.get_mut(&request.ciaddr) {
+						if lease.client == client {
+							lease.state = LeaseState::Expired;
+							lease.expires = now();
+						}
+					}
+				});
+			}
+			MessageType::Inform => {
+				// The client configured its address itself and only wants the other options
+				let Some(pool) = self.pool_for(&candidates, &segment, request.ciaddr) else {
+					return;
+				};
+				let mut reply = self.reply(&request, MessageType::Ack, pool.server_address);
+				reply.ciaddr = request.ciaddr;
+				self.add_options(&mut reply, pool, false, reservation);
+				self.send(&request, &reply).await;
+			}
+			_ => {}
+		}
+	}
+
+	/// Marks bound leases whose time ran out as expired and forgets stale offers and conflicts
+	fn expire(&self) {
+		let now = now();
+		let ifname = self.ifname().to_string();
+		let changed = self.with_leases(|leases| {
+			let mut changed = false;
+			leases.retain(|address, lease| match lease.state {
+				LeaseState::Bound if lease.expires <= now => {
+					println!(
+						"[{ifname}] Lease of {address} to {} ({}) expired",
+						lease.mac,
+						lease.hostname.as_deref().unwrap_or("unknown")
+					);
+					lease.state = LeaseState::Expired;
+					changed = true;
+					true
+				}
+				LeaseState::Offered | LeaseState::Conflict if lease.expires <= now => false,
+				_ => true,
+			});
+			changed
+		});
+		if changed {
+			save_leases(&ifname);
+		}
+	}
+
+	async fn run(self) {
+		tokio::select! {
+			_ = self.serve() => {}
+			_ = self.prepare_addresses() => {}
+		}
+	}
+
+	async fn serve(&self) {
+		let mut buffer = [0u8; 1500];
+		let mut expiry = interval(Duration::from_secs(60));
+		loop {
+			tokio::select! {
+				_ = expiry.tick() => self.expire(),
+				result = self.socket.recv_from(&mut buffer) => {
+					let len = match result {
+						Ok((len, _)) => len,
+						Err(err) => {
+							println!("[{}] DHCP server stopped: {err}", self.ifname());
+							return;
+						}
+					};
+					if let Some(request) = DhcpMessage::decode(&buffer[..len]) {
+						self.handle(request).await;
+					}
+				}
+			}
+		}
+	}
+}
+
+fn parse_address(ifname: &str, name: &str, value: &str) -> Option<Ipv4Addr> {
+	if value.is_empty() {
+		return None;
+	}
+	let address = Ipv4Addr::from_str(value).ok();
+	if address.is_none() {
+		println!("[{ifname}] Invalid DHCP {name}: {value}");
+	}
+	address
+}
+
+fn parse_addresses(ifname: &str, name: &str, values: &AddressList) -> Vec<Ipv4Addr> {
+	values
+		.addresses()
+		.iter()
+		.filter_map(|value| parse_address(ifname, name, value))
+		.collect()
+}
+
+/// Parses a route destination like `10.20.0.0/16`
+fn parse_destination(destination: &str) -> Option<(Ipv4Addr, u8)> {
+	let (address, prefix) = destination.split_once('/')?;
+	let prefix: u8 = prefix.parse().ok().filter(|prefix| *prefix <= 32)?;
+	let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+	Some((
+		Ipv4Addr::from(u32::from(Ipv4Addr::from_str(address).ok()?) & mask),
+		prefix,
+	))
+}
+
+/// Encodes the value of a raw option according to its type, lists encode every element one after another
+fn encode_option(option: &DhcpOption) -> Result<Vec<u8>, String> {
+	let values = match &option.value {
+		toml::Value::Array(values) => values.clone(),
+		value => vec![value.clone()],
+	};
+	let mut data = vec![];
+	for value in &values {
+		let integer = |max: u32| {
+			value
+				.as_integer()
+				.filter(|value| (0..=max as i64).contains(value))
+				.map(|value| value as u32)
+				.ok_or(format!("{value} is not a number from 0 to {max}"))
+		};
+		match option.option_type {
+			DhcpOptionType::Address => {
+				let address = value
+					.as_str()
+					.and_then(|value| Ipv4Addr::from_str(value).ok())
+					.ok_or(format!("{value} is not an IPv4 address"))?;
+				data.extend_from_slice(&address.octets());
+			}
+			DhcpOptionType::U8 => data.push(integer(u8::MAX as u32)? as u8),
+			DhcpOptionType::U16 => {
+				data.extend_from_slice(&(integer(u16::MAX as u32)? as u16).to_be_bytes())
+			}
+			DhcpOptionType::U32 => data.extend_from_slice(&integer(u32::MAX)?.to_be_bytes()),
+			DhcpOptionType::Bool => {
+				let value = value
+					.as_bool()
+					.ok_or(format!("{value} is not true or false"))?;
+				data.push(value as u8);
+			}
+			DhcpOptionType::String => {
+				let value = value.as_str().ok_or(format!("{value} is not a string"))?;
+				data.extend_from_slice(value.as_bytes());
+			}
+			DhcpOptionType::Hex => {
+				let bytes: Option<Vec<u8>> = value.as_str().and_then(|value| {
+					value
+						.split(':')
+						.map(|byte| u8::from_str_radix(byte, 16).ok())
+						.collect()
+				});
+				data.extend(bytes.ok_or(format!("{value} is not colon separated hex bytes"))?);
+			}
+		}
+	}
+	if data.is_empty() {
+		return Err("the value is empty".to_string());
+	}
+	Ok(data)
+}
+
+/// Encodes the options that are the same for every client, skipping invalid ones
+/// The lease time of a client, the reservation overrides the one of the pool
+fn lease_time(pool: &Pool, reservation: Option<&Reservation>) -> u32 {
+	reservation
+		.and_then(|reservation| reservation.lease_time)
+		.unwrap_or(pool.lease_time)
+}
+
+/// The options of a pool or reservation that are the same for every lease
+fn static_options(
+	ifname: &str,
+	ntp: &[String],
+	domain: Option<&String>,
+	search: &[String],
+	mtu: Option<u16>,
+	raw_options: &[DhcpOption],
+) -> Vec<(u8, Vec<u8>)> {
+	let mut static_options = vec![];
+	let ntp: Vec<u8> = ntp
+		.iter()
+		.filter_map(|ntp| parse_address(ifname, "ntp", ntp))
+		.flat_map(|ntp| ntp.octets())
+		.collect();
+	if !ntp.is_empty() {
+		static_options.push((options::NTP, ntp));
+	}
+	if let Some(domain) = domain {
+		static_options.push((options::DOMAIN_NAME, domain.as_bytes().to_vec()));
+	}
+	if !search.is_empty() {
+		if search.iter().any(|domain| {
+			domain
+				.split('.')
+				.any(|label| label.len() > 63 || label.contains(' '))
+		}) {
+			println!("[{ifname}] Invalid DHCP search list: {search:?}");
+		} else {
+			static_options.push((options::DOMAIN_SEARCH, encode_domain_list(search)));
+		}
+	}
+	match mtu {
+		// The smallest MTU allowed (RFC 2132 5.1)
+		Some(mtu) if mtu < 68 => println!("[{ifname}] Invalid DHCP MTU: {mtu}"),
+		Some(mtu) => static_options.push((options::MTU, mtu.to_be_bytes().to_vec())),
+		None => {}
+	}
+	for option in raw_options {
+		if MANAGED_OPTIONS.contains(&option.code) {
+			println!(
+				"[{ifname}] DHCP option {} can't be set as a raw option",
+				option.code
+			);
+			continue;
+		}
+		match encode_option(option) {
+			Ok(data) => static_options.push((option.code, data)),
+			Err(err) => println!("[{ifname}] Invalid DHCP option {}: {err}", option.code),
+		}
+	}
+	static_options
+}
+
+fn validate_boot_file(ifname: &str, boot_file: &str) -> bool {
+	// The file field of the header only has room for 127 characters and the terminating zero
+	let valid = !boot_file.is_empty() && boot_file.len() <= 127;
+	if !valid {
+		println!("[{ifname}] Invalid DHCP boot file: {boot_file:?}");
+	}
+	valid
+}
+
+fn validate_boot_rule(ifname: &str, rule: &DhcpBootRule) -> Option<BootRule> {
+	if !validate_boot_file(ifname, &rule.boot_file) {
+		return None;
+	}
+	let next_server = match &rule.next_server {
+		Some(next_server) => Some(parse_address(ifname, "next server", next_server)?),
+		None => None,
+	};
+	Some(BootRule {
+		architecture: rule.architecture,
+		vendor_class: rule.vendor_class.clone(),
+		next_server,
+		boot_file: rule.boot_file.clone(),
+	})
+}
+
+/// Classless static routes as destination, prefix length and router
+fn parse_routes(ifname: &str, routes: &[DhcpRoute]) -> Vec<(Ipv4Addr, u8, Ipv4Addr)> {
+	routes
+		.iter()
+		.filter_map(|route| {
+			let parsed =
+				parse_destination(&route.destination).zip(Ipv4Addr::from_str(&route.router).ok());
+			if parsed.is_none() {
+				println!(
+					"[{ifname}] Invalid DHCP route: {} via {}",
+					route.destination, route.router
+				);
+			}
+			parsed.map(|((destination, prefix), router)| (destination, prefix, router))
 		})
+		.collect()
+}
+
+fn validate_lease_time(ifname: &str, lease_time: u32) -> u32 {
+	if lease_time < 60 {
+		println!("[{ifname}] DHCP lease time {lease_time} is too short, using 60 seconds");
+	}
+	lease_time.max(60)
+}
+
+/// Checks a reservation against the subnets of the pools and the other reservations
+fn validate_reservation(
+	ifname: &str,
+	reservation: &DhcpReservation,
+	pools: &[Pool],
+	valid: &[Reservation],
+) -> Option<Reservation> {
+	let address = parse_address(ifname, "reservation address", &reservation.address)?;
+	let mac = match &reservation.mac {
+		Some(mac) => match MacAddr::from_str(mac) {
+			Ok(mac) => Some(mac.octets()),
+			Err(_) => {
+				println!("[{ifname}] Invalid MAC address in DHCP reservation: {mac}");
+				return None;
+			}
+		},
+		None => None,
+	};
+	let client_id = reservation.client_id.as_deref().map(client_id_bytes);
+	if mac.is_none() && client_id.is_none() {
+		println!("[{ifname}] DHCP reservation for {address} needs a MAC address or client id");
+		return None;
+	}
+	if !pools
+		.iter()
+		.any(|pool| pool.in_subnet(address) && address != pool.server_address)
+	{
+		println!(
+			"[{ifname}] DHCP reservation {address} is not a usable address in any pool's subnet"
+		);
+		return None;
+	}
+	if let Some(other) = valid.iter().find(|other| {
+		other.address == address
+			|| (mac.is_some() && other.mac == mac)
+			|| (client_id.is_some() && other.client_id == client_id)
+	}) {
+		println!(
+			"[{ifname}] DHCP reservation {address} conflicts with the reservation {}",
+			other.address
+		);
+		return None;
+	}
+	if pools.iter().any(|pool| pool.in_range(address)) {
+		println!("[{ifname}] DHCP reservation {address} must be outside the range of the pools");
+		return None;
+	}
+	Some(Reservation {
+		mac,
+		client_id,
+		address,
+		hostname: reservation.hostname.clone(),
+		router: reservation
+			.router
+			.as_deref()
+			.and_then(|router| parse_address(ifname, "reservation router", router)),
+		dns: reservation
+			.dns
+			.as_ref()
+			.map(|dns| parse_addresses(ifname, "reservation dns", dns)),
+		lease_time: reservation
+			.lease_time
+			.map(|lease_time| validate_lease_time(ifname, lease_time)),
+		routes: reservation
+			.routes
+			.as_ref()
+			.map(|routes| parse_routes(ifname, routes)),
+		options: static_options(
+			ifname,
+			reservation.ntp.as_deref().unwrap_or_default(),
+			reservation.domain.as_ref(),
+			reservation.search.as_deref().unwrap_or_default(),
+			reservation.mtu,
+			&reservation.options,
+		),
+		next_server: reservation
+			.next_server
+			.as_deref()
+			.and_then(|next_server| parse_address(ifname, "reservation next server", next_server)),
+		boot_file: reservation
+			.boot_file
+			.clone()
+			.filter(|boot_file| validate_boot_file(ifname, boot_file)),
+	})
+}
+
+/// Checks a pool and finds the interface address in its subnet
+fn validate_pool(
+	ifname: &str,
+	config: &DhcpPoolConfig,
+	server_address: Ipv4Addr,
+	addresses: &[Ipv4Addr],
+) -> Option<Pool> {
+	let (Some(start), Some(end), Some(netmask)) = (
+		parse_address(ifname, "start", &config.start),
+		parse_address(ifname, "end", &config.end),
+		parse_address(ifname, "netmask", &config.netmask),
+	) else {
+		println!("[{ifname}] DHCP pool requires start, end and netmask");
+		return None;
+	};
+	let mask = u32::from(netmask);
+	if start > end || u32::from(start) & mask != u32::from(end) & mask {
+		println!("[{ifname}] DHCP pool {start} - {end} is not a range in one subnet");
+		return None;
+	}
+	let in_subnet = |address: &Ipv4Addr| u32::from(*address) & mask == u32::from(start) & mask;
+	let local_address = [server_address]
+		.iter()
+		.chain(addresses)
+		.find(|address| in_subnet(address))
+		.copied();
+	let routes = parse_routes(ifname, &config.routes);
+	match local_address {
+		Some(_) => println!("[{ifname}] DHCP server handing out {start} - {end}"),
+		None => println!("[{ifname}] DHCP server handing out {start} - {end} to relayed clients"),
+	}
+	Some(Pool {
+		start: u32::from(start),
+		end: u32::from(end),
+		netmask,
+		server_address: local_address.unwrap_or(server_address),
+		relayed: local_address.is_none(),
+		router: parse_address(ifname, "router", &config.router),
+		dns: parse_addresses(ifname, "dns", &config.dns),
+		routes,
+		options: static_options(
+			ifname,
+			&config.ntp,
+			config.domain.as_ref(),
+			&config.search,
+			config.mtu,
+			&config.options,
+		),
+		lease_time: validate_lease_time(ifname, config.lease_time),
+		client_class: config.client_class.clone(),
+	})
+}
+
+/// Starts a DHCP server on the interface, answering from `server_address` or the interface address in the subnet of the pool
+pub async fn start_server(
+	interface: &Interface,
+	server_address: Ipv4Addr,
+	config: &InterfaceDhcpConfig,
+) {
+	stop_server(interface).await;
+	let ifname = interface.name.clone();
+	let addresses: Vec<Ipv4Addr> = interface
+		.get_ipv4_addresses()
+		.await
+		.into_iter()
+		.map(|(address, _)| address)
+		.collect();
+	// The main pool is optional when there are other pools
+	let pools: Vec<Pool> = Some(&config.pool)
+		.filter(|pool| !pool.start.is_empty() || config.pools.is_empty())
+		.into_iter()
+		.chain(&config.pools)
+		.filter_map(|pool| validate_pool(&ifname, pool, server_address, &addresses))
 		.collect();
-	random_string
-}
-
-impl DHCPServer {
-	pub fn new(
-		start: String,
-		end: String,
-		interface: String,
-		dns: String,
-		netmask: String,
-		router: String,
-		lease: u16,
-	) -> DHCPServer {
-		DHCPServer {
-			start,
-			end,
-			interface,
-			dns,
-			netmask,
-			router,
-			lease,
-		}
-	}
-	pub fn start(&self) {
-		let _ = std::fs::create_dir("/tmp/dhcp");
-		let name = generate_random_string(5);
-		let _ = std::fs::write(
-			format!("/tmp/dhcp/{name}.conf"),
-			format!(
-				"start {start}
-end {end}
-interface {interface}
-option dns {dns}
-option subnet {netmask}
-option router {router}
-option lease {lease}",
-				start = self.start,
-				end = self.end,
-				interface = self.interface,
-				dns = self.dns,
-				netmask = self.netmask,
-				router = self.router,
-				lease = self.lease
+	if pools.is_empty() {
+		println!("[{ifname}] DHCP server has no valid pool");
+		return;
+	}
+	let mut reservations = vec![];
+	for reservation in &config.reservations {
+		if let Some(reservation) = validate_reservation(&ifname, reservation, &pools, &reservations)
+		{
+			reservations.push(reservation);
+		}
+	}
+	let socket = match open_socket(&ifname) {
+		Ok(socket) => socket,
+		Err(err) => {
+			println!("[{ifname}] Could not start DHCP server: {err}");
+			return;
+		}
+	};
+	let server = Server {
+		ifname: ifname.clone(),
+		socket,
+		server_address,
+		pools,
+		reservations,
+		next_server: config
+			.next_server
+			.as_deref()
+			.and_then(|next_server| parse_address(&ifname, "next server", next_server)),
+		boot_file: config
+			.boot_file
+			.clone()
+			.filter(|boot_file| validate_boot_file(&ifname, boot_file)),
+		boot_rules: config
+			.boot_rules
+			.iter()
+			.filter_map(|rule| validate_boot_rule(&ifname, rule))
+			.collect(),
+		ready: Mutex::new(BTreeMap::new()),
+		refill: Notify::new(),
+	};
+	// Leases survive restarts and reloads, so clients keep their addresses
+	let saved: LeaseDatabase = state::load(&state_name(&ifname)).unwrap_or_default();
+	LEASES.lock().unwrap().insert(
+		ifname.clone(),
+		saved
+			.leases
+			.into_iter()
+			.map(|lease| (lease.address, lease))
+			.collect(),
+	);
+	let task = tokio::spawn(server.run());
+	SERVERS.lock().unwrap().insert(ifname, task);
+}
+
+/// Stops the DHCP server running on the interface
+pub async fn stop_server(interface: &Interface) {
+	let task = SERVERS.lock().unwrap().remove(&interface.name);
+	if let Some(task) = task {
+		println!("[{}] Stopping DHCP server", interface.name);
+		task.abort();
+	}
+	// Writes that are still queued are skipped once the leases are gone, so they are written one last time
+	let ifname = interface.name.clone();
+	let _ = tokio::task::spawn_blocking(move || write_leases(&ifname)).await;
+	// The saved leases are kept for when the server starts again
+	LEASES.lock().unwrap().remove(&interface.name);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+
+	fn pool(config: &str) -> Pool {
+		let config: DhcpPoolConfig = toml::from_str(config).unwrap();
+		validate_pool("test", &config, Ipv4Addr::new(10, 0, 0, 1), &[]).unwrap()
+	}
+
+	fn reservation(config: &str, pools: &[Pool], valid: &[Reservation]) -> Option<Reservation> {
+		let config: DhcpReservation = toml::from_str(config).unwrap();
+		validate_reservation("test", &config, pools, valid)
+	}
+
+	fn server(ifname: &str, pools: Vec<Pool>, reservations: Vec<Reservation>) -> Server {
+		let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.set_nonblocking(true).unwrap();
+		Server {
+			ifname: ifname.to_string(),
+			socket: UdpSocket::from_std(socket).unwrap(),
+			server_address: Ipv4Addr::new(10, 0, 0, 1),
+			pools,
+			reservations,
+			next_server: None,
+			boot_file: None,
+			boot_rules: vec![],
+			ready: Mutex::new(BTreeMap::new()),
+			refill: Notify::new(),
+		}
+	}
+
+	fn request(chaddr: [u8; 6]) -> DhcpMessage {
+		DhcpMessage::new(BOOTREQUEST, MessageType::Discover, 1, chaddr)
+	}
+
+	#[test]
+	fn validates_reservations() {
+		let pools = [pool(
+			r#"start = "10.0.0.100"
+			end = "10.0.0.200"
+			netmask = "255.255.255.0""#,
+		)];
+		let valid = reservation(
+			r#"mac = "02:00:00:00:00:01"
+			address = "10.0.0.10""#,
+			&pools,
+			&[],
+		)
+		.unwrap();
+		// In the range, outside every subnet, the server address, without a client, and taken
+		for config in [
+			r#"mac = "02:00:00:00:00:02"
+			address = "10.0.0.150""#,
+			r#"mac = "02:00:00:00:00:02"
+			address = "10.0.1.10""#,
+			r#"mac = "02:00:00:00:00:02"
+			address = "10.0.0.1""#,
+			r#"address = "10.0.0.11""#,
+			r#"mac = "02:00:00:00:00:02"
+			address = "10.0.0.10""#,
+			r#"mac = "02:00:00:00:00:01"
+			address = "10.0.0.11""#,
+		] {
+			assert!(reservation(config, &pools, std::slice::from_ref(&valid)).is_none());
+		}
+	}
+
+	#[test]
+	fn client_id_takes_precedence_over_mac() {
+		let pools = [pool(
+			r#"start = "10.0.0.100"
+			end = "10.0.0.200"
+			netmask = "255.255.255.0""#,
+		)];
+		let reservation = reservation(
+			r#"mac = "02:00:00:00:00:01"
+			client_id = "host"
+			address = "10.0.0.10""#,
+			&pools,
+			&[],
+		)
+		.unwrap();
+		let mut request = request(MAC);
+		assert!(reservation.matches(&request));
+		request.set_option(options::CLIENT_ID, client_id_bytes("host"));
+		assert!(reservation.matches(&request));
+		// The same MAC with another client id is another client
+		request.set_option(options::CLIENT_ID, client_id_bytes("other"));
+		assert!(!reservation.matches(&request));
+		let mut request = super::tests::request([2, 0, 0, 0, 0, 2]);
+		request.set_option(options::CLIENT_ID, client_id_bytes("host"));
+		assert!(reservation.matches(&request));
+	}
+
+	#[tokio::test]
+	async fn reservation_overrides_pool_options() {
+		let pools = vec![pool(
+			r#"start = "10.0.0.100"
+			end = "10.0.0.200"
+			netmask = "255.255.255.0"
+			router = "10.0.0.1"
+			lease_time = 600
+			ntp = ["10.0.0.1"]
+			domain = "lan"
+			routes = [{ destination = "10.20.0.0/16", router = "10.0.0.254" }]
+			options = [{ code = 66, type = "string", value = "tftp.lan" }]"#,
+		)];
+		let reserved = reservation(
+			r#"mac = "02:00:00:00:00:01"
+			address = "10.0.0.10"
+			lease_time = 86400
+			ntp = ["10.0.0.2"]
+			routes = []
+			options = [{ code = 66, type = "string", value = "other.lan" }]
+			boot_file = "host.efi""#,
+			&pools,
+			&[],
+		)
+		.unwrap();
+		let server = server("reservation-options", pools, vec![]);
+		let pool = &server.pools[0];
+
+		let mut reply = request(MAC);
+		server.add_options(&mut reply, pool, true, Some(&reserved));
+		server.add_boot(&request(MAC), &mut reply, Some(&reserved));
+		assert_eq!(reply.option_u32(options::LEASE_TIME), Some(86400));
+		assert_eq!(
+			reply.option_ipv4_list(options::NTP),
+			[Ipv4Addr::new(10, 0, 0, 2)]
+		);
+		assert_eq!(
+			reply.option_string(options::DOMAIN_NAME).as_deref(),
+			Some("lan")
+		);
+		assert_eq!(reply.option_string(66).as_deref(), Some("other.lan"));
+		assert!(reply.option(options::CLASSLESS_ROUTES).is_none());
+		assert_eq!(reply.file, "host.efi");
+
+		let mut reply = request(MAC);
+		server.add_options(&mut reply, pool, true, None);
+		assert_eq!(reply.option_u32(options::LEASE_TIME), Some(600));
+		assert_eq!(reply.option_string(66).as_deref(), Some("tftp.lan"));
+		assert!(reply.option(options::CLASSLESS_ROUTES).is_some());
+	}
+
+	/// A plain pool and a class pool on the link, and a relayed pool
+	fn selection_server(ifname: &str) -> Server {
+		let pools = vec![
+			pool(
+				r#"start = "10.0.0.100"
+				end = "10.0.0.200"
+				netmask = "255.255.255.0""#,
+			),
+			pool(
+				r#"start = "10.0.0.50"
+				end = "10.0.0.99"
+				netmask = "255.255.255.0"
+				client_class = { vendor_class = "PXEClient*" }"#,
+			),
+			pool(
+				r#"start = "10.1.0.100"
+				end = "10.1.0.200"
+				netmask = "255.255.255.0""#,
 			),
+		];
+		server(ifname, pools, vec![])
+	}
+
+	fn starts(pools: &[&Pool]) -> Vec<Ipv4Addr> {
+		pools
+			.iter()
+			.map(|pool| Ipv4Addr::from(pool.start))
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn segment_of_local_and_relayed_requests() {
+		let server = selection_server("segment-pools");
+		assert!(server.pools[2].relayed);
+		assert!(!server.pools[0].relayed);
+
+		let local = request(MAC);
+		assert_eq!(
+			starts(&server.segment_pools(&local)),
+			[Ipv4Addr::new(10, 0, 0, 100), Ipv4Addr::new(10, 0, 0, 50)]
+		);
+		let mut relayed = request(MAC);
+		relayed.giaddr = Ipv4Addr::new(10, 1, 0, 1);
+		assert_eq!(
+			starts(&server.segment_pools(&relayed)),
+			[Ipv4Addr::new(10, 1, 0, 100)]
+		);
+		// A relay in a subnet without a pool gets nothing
+		relayed.giaddr = Ipv4Addr::new(10, 2, 0, 1);
+		assert!(server.segment_pools(&relayed).is_empty());
+	}
+
+	#[tokio::test]
+	async fn class_pools_come_first() {
+		let server = selection_server("candidate-pools");
+		let mut request = request(MAC);
+		let segment = server.segment_pools(&request);
+		// Clients outside the class only get the plain pool
+		assert_eq!(
+			starts(&server.candidate_pools(&segment, &request)),
+			[Ipv4Addr::new(10, 0, 0, 100)]
+		);
+		request.set_option(options::VENDOR_CLASS, b"PXEClient:Arch:00007".to_vec());
+		assert_eq!(
+			starts(&server.candidate_pools(&segment, &request)),
+			[Ipv4Addr::new(10, 0, 0, 50), Ipv4Addr::new(10, 0, 0, 100)]
+		);
+	}
+
+	#[tokio::test]
+	async fn pool_for_address() {
+		let server = selection_server("pool-for");
+		let request = request(MAC);
+		let segment = server.segment_pools(&request);
+		let candidates = server.candidate_pools(&segment, &request);
+		let pool_for = |address| {
+			server
+				.pool_for(&candidates, &segment, address)
+				.map(|pool| Ipv4Addr::from(pool.start))
+		};
+		assert_eq!(
+			pool_for(Ipv4Addr::new(10, 0, 0, 150)),
+			Some(Ipv4Addr::new(10, 0, 0, 100))
+		);
+		// Outside every range, the subnet decides
+		assert_eq!(
+			pool_for(Ipv4Addr::new(10, 0, 0, 20)),
+			Some(Ipv4Addr::new(10, 0, 0, 100))
 		);
-		let _ = std::process::Command::new("udhcpd")
-			.arg(format!("/tmp/dhcp/{name}.conf"))
-			.spawn();
+		assert_eq!(pool_for(Ipv4Addr::new(10, 1, 0, 150)), None);
 	}
 }
@@ -0,0 +1,226 @@
+use std::{
+	collections::BTreeMap,
+	net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+	str::FromStr,
+	sync::Mutex,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{net::UdpSocket, task::JoinHandle};
+
+use crate::{
+	config::DhcpRelayConfig,
+	link::{
+		dhcp::{self, options, DhcpMessage, BOOTREPLY, BOOTREQUEST, FLAG_BROADCAST},
+		interface::Interface,
+	},
+};
+
+/// Requests that went through more relays than this are dropped (RFC 1542 4.1.1)
+const MAX_HOPS: u8 = 16;
+
+/// Sub-options of the relay agent information option (RFC 3046)
+const CIRCUIT_ID: u8 = 1;
+const REMOTE_ID: u8 = 2;
+
+/// Running relays by interface name
+static RELAYS: Mutex<BTreeMap<String, JoinHandle<()>>> = Mutex::new(BTreeMap::new());
+
+fn open_socket(device: Option<&str>, address: Ipv4Addr) -> std::io::Result<UdpSocket> {
+	let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+	// Shares port 67 with the DHCP servers and relays on other interfaces
+	socket.set_reuse_address(true)?;
+	socket.set_broadcast(true)?;
+	if let Some(device) = device {
+		socket.bind_device(Some(device.as_bytes()))?;
+	}
+	socket.set_nonblocking(true)?;
+	socket.bind(&SocketAddrV4::new(address, dhcp::SERVER_PORT).into())?;
+	UdpSocket::from_std(socket.into())
+}
+
+struct Relay {
+	ifname: String,
+	/// Receives requests from clients and sends replies to them
+	downstream: UdpSocket,
+	/// Sends requests to the servers and receives their replies
+	upstream: UdpSocket,
+	/// The interface address, sent as giaddr so the servers know which subnet the client is on
+	address: Ipv4Addr,
+	servers: Vec<Ipv4Addr>,
+	/// Option 82 to insert into requests
+	agent_information: Option<Vec<u8>>,
+}
+
+impl Relay {
+	async fn forward_request(&self, mut request: DhcpMessage) {
+		if request.hops >= MAX_HOPS {
+			return;
+		}
+		request.hops += 1;
+		if request.giaddr.is_unspecified() {
+			// Clients don't send option 82, so it was forged (RFC 3046 2.1)
+			if request.option(options::RELAY_AGENT_INFORMATION).is_some() {
+				println!(
+					"[{}] Dropping DHCP request with relay agent information from a client",
+					self.ifname
+				);
+				return;
+			}
+			request.giaddr = self.address;
+			if let Some(agent_information) = &self.agent_information {
+				request.set_option(options::RELAY_AGENT_INFORMATION, agent_information.clone());
+			}
+		}
+		let data = request.encode();
+		for server in &self.servers {
+			let destination = SocketAddrV4::new(*server, dhcp::SERVER_PORT);
+			if let Err(err) = self.upstream.send_to(&data, destination).await {
+				println!(
+					"[{}] Failed to relay DHCP request to {server}: {err}",
+					self.ifname
+				);
+			}
+		}
+	}
+
+	async fn forward_reply(&self, mut reply: DhcpMessage, source: SocketAddr) {
+		if reply.giaddr != self.address {
+			return;
+		}
+		let SocketAddr::V4(source) = source else {
+			return;
+		};
+		if !self.servers.contains(source.ip()) {
+			println!(
+				"[{}] Ignoring DHCP reply from unknown server {}",
+				self.ifname,
+				source.ip()
+			);
+			return;
+		}
+		// The option is only meant for the relay (RFC 3046 2.2)
+		if self.agent_information.is_some() {
+			reply.remove_option(options::RELAY_AGENT_INFORMATION);
+		}
+		let destination = if !reply.ciaddr.is_unspecified() && reply.flags & FLAG_BROADCAST == 0 {
+			SocketAddrV4::new(reply.ciaddr, dhcp::CLIENT_PORT)
+		} else {
+			// The client has no address yet, so it can't be reached without its MAC in the ARP table
+			SocketAddrV4::new(Ipv4Addr::BROADCAST, dhcp::CLIENT_PORT)
+		};
+		if let Err(err) = self.downstream.send_to(&reply.encode(), destination).await {
+			println!("[{}] Failed to relay DHCP reply: {err}", self.ifname);
+		}
+	}
+
+	async fn run(self) {
+		let mut downstream_buffer = [0u8; 1500];
+		let mut upstream_buffer = [0u8; 1500];
+		loop {
+			tokio::select! {
+				result = self.downstream.recv_from(&mut downstream_buffer) => {
+					let len = match result {
+						Ok((len, _)) => len,
+						Err(err) => {
+							println!("[{}] DHCP relay stopped: {err}", self.ifname);
+							return;
+						}
+					};
+					if let Some(request) = DhcpMessage::decode(&downstream_buffer[..len]) {
+						if request.op == BOOTREQUEST {
+							self.forward_request(request).await;
+						}
+					}
+				}
+				result = self.upstream.recv_from(&mut upstream_buffer) => {
+					let (len, source) = match result {
+						Ok(received) => received,
+						Err(err) => {
+							println!("[{}] DHCP relay stopped: {err}", self.ifname);
+							return;
+						}
+					};
+					if let Some(reply) = DhcpMessage::decode(&upstream_buffer[..len]) {
+						if reply.op == BOOTREPLY {
+							self.forward_reply(reply, source).await;
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Encodes the relay agent information option from its sub-options
+fn agent_information(ifname: &str, config: &DhcpRelayConfig) -> Option<Vec<u8>> {
+	if !config.agent_information {
+		return None;
+	}
+	let circuit_id = config.circuit_id.as_deref().unwrap_or(ifname);
+	let mut data = vec![];
+	for (code, value) in [
+		(CIRCUIT_ID, Some(circuit_id)),
+		(REMOTE_ID, config.remote_id.as_deref()),
+	] {
+		let Some(value) = value else {
+			continue;
+		};
+		// Each sub-option is at most 255 bytes long
+		let value = &value.as_bytes()[..value.len().min(255)];
+		data.push(code);
+		data.push(value.len() as u8);
+		data.extend_from_slice(value);
+	}
+	Some(data)
+}
+
+/// Relays DHCP between clients on the interface and the configured servers, answering from `address`
+pub async fn start_relay(interface: &Interface, address: Ipv4Addr, config: &DhcpRelayConfig) {
+	stop_relay(interface).await;
+	let ifname = interface.name.clone();
+	let servers: Vec<Ipv4Addr> = config
+		.servers
+		.iter()
+		.filter_map(|server| {
+			let parsed = Ipv4Addr::from_str(server).ok();
+			if parsed.is_none() {
+				println!("[{ifname}] Invalid DHCP relay server: {server}");
+			}
+			parsed
+		})
+		.collect();
+	if servers.is_empty() {
+		println!("[{ifname}] DHCP relay requires at least one server");
+		return;
+	}
+	let sockets = open_socket(Some(&ifname), Ipv4Addr::UNSPECIFIED)
+		.and_then(|downstream| Ok((downstream, open_socket(None, address)?)));
+	let (downstream, upstream) = match sockets {
+		Ok(sockets) => sockets,
+		Err(err) => {
+			println!("[{ifname}] Could not start DHCP relay: {err}");
+			return;
+		}
+	};
+	let relay = Relay {
+		ifname: ifname.clone(),
+		downstream,
+		upstream,
+		address,
+		servers,
+		agent_information: agent_information(&ifname, config),
+	};
+	println!("[{ifname}] Relaying DHCP to {}", config.servers.join(", "));
+	let task = tokio::spawn(relay.run());
+	RELAYS.lock().unwrap().insert(ifname, task);
+}
+
+/// Stops the DHCP relay running on the interface
+pub async fn stop_relay(interface: &Interface) {
+	let task = RELAYS.lock().unwrap().remove(&interface.name);
+	if let Some(task) = task {
+		println!("[{}] Stopping DHCP relay", interface.name);
+		task.abort();
+	}
+}
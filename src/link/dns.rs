@@ -0,0 +1,222 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::link::dhcp6::encode_domain_list;
+
+pub const PORT: u16 = 53;
+
+/// Record types (RFC 1035 3.2.2, RFC 3596)
+pub mod types {
+	pub const A: u16 = 1;
+	pub const PTR: u16 = 12;
+	pub const AAAA: u16 = 28;
+	pub const ANY: u16 = 255;
+}
+
+pub const CLASS_IN: u16 = 1;
+
+/// Response codes (RFC 1035 4.1.1)
+pub const NOERROR: u8 = 0;
+pub const SERVFAIL: u8 = 2;
+pub const NXDOMAIN: u8 = 3;
+
+const HEADER_LEN: usize = 12;
+
+/// The longest name allowed in wire format (RFC 1035 2.3.4)
+const MAX_NAME_LEN: usize = 255;
+
+/// An answer to a local name
+pub enum Record {
+	A(Ipv4Addr),
+	Aaaa(Ipv6Addr),
+	Ptr(String),
+}
+
+impl Record {
+	fn record_type(&self) -> u16 {
+		match self {
+			Record::A(_) => types::A,
+			Record::Aaaa(_) => types::AAAA,
+			Record::Ptr(_) => types::PTR,
+		}
+	}
+
+	fn data(&self) -> Vec<u8> {
+		match self {
+			Record::A(address) => address.octets().to_vec(),
+			Record::Aaaa(address) => address.octets().to_vec(),
+			Record::Ptr(name) => encode_domain_list(std::slice::from_ref(name)),
+		}
+	}
+}
+
+pub fn id(data: &[u8]) -> Option<u16> {
+	Some(u16::from_be_bytes([*data.first()?, *data.get(1)?]))
+}
+
+pub fn set_id(data: &mut [u8], id: u16) {
+	data[..2].copy_from_slice(&id.to_be_bytes());
+}
+
+pub fn is_response(data: &[u8]) -> bool {
+	data.get(2).is_some_and(|flags| flags & 0x80 != 0)
+}
+
+/// Decodes the single question of a message, returning the lowercase name without the trailing dot,
+/// the type, the class and where the question ends.
+/// Compressed names are rejected, they never appear in the question of a query
+fn decode_question(data: &[u8]) -> Option<(String, u16, u16, usize)> {
+	if data.len() < HEADER_LEN || u16::from_be_bytes([data[4], data[5]]) != 1 {
+		return None;
+	}
+	let mut labels = vec![];
+	let mut i = HEADER_LEN;
+	loop {
+		let len = *data.get(i)? as usize;
+		if len == 0 {
+			i += 1;
+			break;
+		}
+		if len & 0xc0 != 0 || i + 1 + len - HEADER_LEN > MAX_NAME_LEN {
+			return None;
+		}
+		let label = data.get(i + 1..i + 1 + len)?;
+		labels.push(String::from_utf8_lossy(label).to_lowercase());
+		i += 1 + len;
+	}
+	let qtype = u16::from_be_bytes([*data.get(i)?, *data.get(i + 1)?]);
+	let qclass = u16::from_be_bytes([*data.get(i + 2)?, *data.get(i + 3)?]);
+	Some((labels.join("."), qtype, qclass, i + 4))
+}
+
+/// Encodes a recursive query with a single question
+pub fn query(id: u16, name: &str, query_type: u16) -> Vec<u8> {
+	let mut data = id.to_be_bytes().to_vec();
+	data.extend_from_slice(&[0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+	data.extend_from_slice(&encode_domain_list(&[name.to_string()]));
+	data.extend_from_slice(&query_type.to_be_bytes());
+	data.extend_from_slice(&CLASS_IN.to_be_bytes());
+	data
+}
+
+/// The question section of a message, to match replies to the queries they answer
+pub fn question_section(data: &[u8]) -> Option<&[u8]> {
+	let (_, _, _, end) = decode_question(data)?;
+	Some(&data[HEADER_LEN..end])
+}
+
+/// A standard query with a single question
+pub struct Query<'a> {
+	pub data: &'a [u8],
+	/// Lowercase, without the trailing dot
+	pub name: String,
+	pub query_type: u16,
+	pub class: u16,
+	question_end: usize,
+}
+
+impl Query<'_> {
+	pub fn decode(data: &[u8]) -> Option<Query<'_>> {
+		// Only standard queries (opcode 0) are answered
+		if is_response(data) || data.get(2)? & 0x78 != 0 {
+			return None;
+		}
+		let (name, query_type, class, question_end) = decode_question(data)?;
+		Some(Query {
+			data,
+			name,
+			query_type,
+			class,
+			question_end,
+		})
+	}
+
+	/// The question as it appears in the message
+	pub fn question(&self) -> &[u8] {
+		&self.data[HEADER_LEN..self.question_end]
+	}
+
+	/// Encodes an authoritative reply, the answers all own the queried name
+	pub fn reply(&self, rcode: u8, answers: &[Record], ttl: u32) -> Vec<u8> {
+		// Recursion desired is copied from the query, recursion is available
+		let mut data = vec![
+			self.data[0],
+			self.data[1],
+			0x84 | (self.data[2] & 0x01),
+			0x80 | rcode,
+			0,
+			1,
+		];
+		data.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+		data.extend_from_slice(&[0, 0, 0, 0]);
+		data.extend_from_slice(self.question());
+		for answer in answers {
+			// A pointer to the name in the question
+			data.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+			data.extend_from_slice(&answer.record_type().to_be_bytes());
+			data.extend_from_slice(&CLASS_IN.to_be_bytes());
+			data.extend_from_slice(&ttl.to_be_bytes());
+			let rdata = answer.data();
+			data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+			data.extend_from_slice(&rdata);
+		}
+		data
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn decodes_queries() {
+		let data = query(0x1234, "Router.LAN", types::AAAA);
+		assert_eq!(id(&data), Some(0x1234));
+		assert!(!is_response(&data));
+		let query = Query::decode(&data).unwrap();
+		assert_eq!(query.name, "router.lan");
+		assert_eq!(query.query_type, types::AAAA);
+		assert_eq!(query.class, CLASS_IN);
+		assert_eq!(question_section(&data), Some(&data[HEADER_LEN..]));
+	}
+
+	#[test]
+	fn rejects_malformed_queries() {
+		let data = query(1, "router.lan", types::A);
+		// Truncated in the name and in the type
+		assert!(Query::decode(&data[..HEADER_LEN + 4]).is_none());
+		assert!(Query::decode(&data[..data.len() - 1]).is_none());
+		assert!(Query::decode(&data[..HEADER_LEN - 1]).is_none());
+
+		let mut multiple = data.clone();
+		multiple[5] = 2;
+		assert!(Query::decode(&multiple).is_none());
+
+		let mut compressed = data[..HEADER_LEN].to_vec();
+		compressed.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+		assert!(Query::decode(&compressed).is_none());
+
+		let mut long = data[..HEADER_LEN].to_vec();
+		for _ in 0..5 {
+			long.push(63);
+			long.extend_from_slice(&[b'a'; 63]);
+		}
+		long.extend_from_slice(&[0, 0, 1, 0, 1]);
+		assert!(Query::decode(&long).is_none());
+
+		// Not a standard query
+		let mut notify = data.clone();
+		notify[2] |= 4 << 3;
+		assert!(Query::decode(&notify).is_none());
+	}
+
+	#[test]
+	fn reply() {
+		let data = query(7, "router.lan", types::A);
+		let query = Query::decode(&data).unwrap();
+		let reply = query.reply(NOERROR, &[Record::A(Ipv4Addr::new(192, 168, 1, 1))], 60);
+		assert!(is_response(&reply));
+		assert_eq!(id(&reply), Some(7));
+		assert_eq!(question_section(&reply), Some(query.question()));
+		assert_eq!(&reply[reply.len() - 4..], &[192, 168, 1, 1]);
+	}
+}
@@ -0,0 +1,735 @@
+use std::{
+	collections::HashMap,
+	io::{self, IoSlice},
+	mem::MaybeUninit,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	os::fd::AsRawFd,
+	str::FromStr,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use rand::{thread_rng, Rng};
+use socket2::{Domain, MaybeUninitSlice, MsgHdr, MsgHdrMut, Protocol, SockAddr, Socket, Type};
+use tokio::{
+	io::{unix::AsyncFd, AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream, UdpSocket},
+	task::{JoinHandle, JoinSet},
+	time::Instant,
+};
+
+use crate::{
+	config::DnsConfig,
+	link::{
+		dhcp6c, dhcpc, dhcpd,
+		dns::{self, types, Query, Record, CLASS_IN, NOERROR, NXDOMAIN, SERVFAIL},
+	},
+};
+
+/// Forwarded queries are forgotten when no upstream server answered in this time
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// New queries are dropped while this many are waiting for an upstream answer
+const MAX_PENDING: usize = 1024;
+/// TCP connections of clients are closed after being idle for this long
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often to try listening again on an interface that does not exist yet
+const LISTEN_RETRY: Duration = Duration::from_secs(1);
+
+/// Tasks of the running forwarder
+static FORWARDER: Mutex<Vec<JoinHandle<()>>> = Mutex::new(vec![]);
+
+/// The local address a query was sent to and the interface it arrived on
+#[derive(Clone, Copy)]
+struct PacketInfo {
+	local: IpAddr,
+	ifindex: u32,
+}
+
+impl PacketInfo {
+	/// Finds the packet info among the control messages of a received query
+	fn parse(control: &[u8]) -> Option<PacketInfo> {
+		// SAFETY: CMSG_LEN only computes a length
+		let header_len = unsafe { libc::CMSG_LEN(0) } as usize;
+		let mut offset = 0;
+		while offset + header_len <= control.len() {
+			// SAFETY: the header lies within the buffer, which needs not be aligned for it
+			let header: libc::cmsghdr =
+				unsafe { std::ptr::read_unaligned(control[offset..].as_ptr().cast()) };
+			let len = header.cmsg_len as usize;
+			if len < header_len || offset + len > control.len() {
+				return None;
+			}
+			let data = &control[offset + header_len..offset + len];
+			match (header.cmsg_level, header.cmsg_type) {
+				// in_pktinfo is the interface index, the local address and the destination address.
+				// The local address is the one to answer from, even for queries sent to a broadcast address
+				(libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+					let ifindex = i32::from_ne_bytes(data.get(0..4)?.try_into().ok()?);
+					let local: [u8; 4] = data.get(4..8)?.try_into().ok()?;
+					return Some(PacketInfo {
+						local: IpAddr::from(local),
+						ifindex: ifindex as u32,
+					});
+				}
+				// in6_pktinfo is the destination address and the interface index
+				(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+					let local: [u8; 16] = data.get(0..16)?.try_into().ok()?;
+					let ifindex = u32::from_ne_bytes(data.get(16..20)?.try_into().ok()?);
+					return Some(PacketInfo {
+						local: IpAddr::from(local),
+						ifindex,
+					});
+				}
+				_ => {}
+			}
+			offset += len.next_multiple_of(size_of::<usize>());
+		}
+		None
+	}
+
+	/// The control message that makes an answer leave from the address the query was sent to
+	fn encode(&self) -> Vec<u8> {
+		let (level, kind, data) = match self.local {
+			IpAddr::V4(local) => {
+				let mut data = vec![0; size_of::<libc::in_pktinfo>()];
+				data[0..4].copy_from_slice(&(self.ifindex as i32).to_ne_bytes());
+				data[4..8].copy_from_slice(&local.octets());
+				(libc::IPPROTO_IP, libc::IP_PKTINFO, data)
+			}
+			IpAddr::V6(local) => {
+				let mut data = vec![0; size_of::<libc::in6_pktinfo>()];
+				data[0..16].copy_from_slice(&local.octets());
+				data[16..20].copy_from_slice(&self.ifindex.to_ne_bytes());
+				(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, data)
+			}
+		};
+		// SAFETY: CMSG_SPACE and CMSG_LEN only compute lengths, a zeroed cmsghdr is valid
+		let (space, len, header_len, mut header) = unsafe {
+			(
+				libc::CMSG_SPACE(data.len() as u32) as usize,
+				libc::CMSG_LEN(data.len() as u32) as usize,
+				libc::CMSG_LEN(0) as usize,
+				std::mem::zeroed::<libc::cmsghdr>(),
+			)
+		};
+		let mut control = vec![0u8; space];
+		header.cmsg_len = len as _;
+		header.cmsg_level = level;
+		header.cmsg_type = kind;
+		// SAFETY: the buffer is large enough for the header
+		unsafe { std::ptr::write_unaligned(control.as_mut_ptr().cast(), header) };
+		control[header_len..header_len + data.len()].copy_from_slice(&data);
+		control
+	}
+}
+
+/// A socket listening on the wildcard address of an interface.
+/// Answers are sent from the address the query was sent to, as clients drop answers from other addresses
+struct DownstreamSocket {
+	socket: AsyncFd<Socket>,
+	ipv6: bool,
+}
+
+impl DownstreamSocket {
+	async fn recv(&self) -> io::Result<(Vec<u8>, SocketAddr, Option<PacketInfo>)> {
+		let mut buffer = [MaybeUninit::<u8>::uninit(); 4096];
+		let mut control = [MaybeUninit::<u8>::uninit(); 64];
+		loop {
+			let mut guard = self.socket.readable().await?;
+			let mut client = SockAddr::from(match self.ipv6 {
+				false => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+				true => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+			});
+			let Ok(result) = guard.try_io(|socket| {
+				let mut buffers = [MaybeUninitSlice::new(&mut buffer)];
+				let mut message = MsgHdrMut::new()
+					.with_addr(&mut client)
+					.with_buffers(&mut buffers)
+					.with_control(&mut control);
+				let len = socket.get_ref().recvmsg(&mut message, 0)?;
+				Ok((len, message.control_len()))
+			}) else {
+				continue;
+			};
+			let (len, control_len) = result?;
+			// SAFETY: recvmsg initialized the first len bytes and control_len bytes of control
+			let data: Vec<u8> = buffer[..len]
+				.iter()
+				.map(|byte| unsafe { byte.assume_init() })
+				.collect();
+			let control: Vec<u8> = control[..control_len]
+				.iter()
+				.map(|byte| unsafe { byte.assume_init() })
+				.collect();
+			let Some(client) = client.as_socket() else {
+				continue;
+			};
+			return Ok((data, client, PacketInfo::parse(&control)));
+		}
+	}
+
+	async fn send_to(
+		&self,
+		data: &[u8],
+		client: SocketAddr,
+		info: Option<PacketInfo>,
+	) -> io::Result<usize> {
+		let destination = SockAddr::from(client);
+		let control = info.map(|info| info.encode()).unwrap_or_default();
+		loop {
+			let mut guard = self.socket.writable().await?;
+			if let Ok(result) = guard.try_io(|socket| {
+				let buffers = [IoSlice::new(data)];
+				let message = MsgHdr::new()
+					.with_addr(&destination)
+					.with_buffers(&buffers)
+					.with_control(&control);
+				socket.get_ref().sendmsg(&message, 0)
+			}) {
+				return result;
+			}
+		}
+	}
+}
+
+/// Has the kernel report the address and interface of received queries
+fn set_recv_packet_info(socket: &Socket, ipv6: bool) -> io::Result<()> {
+	let (level, option) = match ipv6 {
+		false => (libc::IPPROTO_IP, libc::IP_PKTINFO),
+		true => (libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO),
+	};
+	let enabled: libc::c_int = 1;
+	// SAFETY: the option value is a c_int living through the call
+	let result = unsafe {
+		libc::setsockopt(
+			socket.as_raw_fd(),
+			level,
+			option,
+			(&enabled as *const libc::c_int).cast(),
+			size_of::<libc::c_int>() as libc::socklen_t,
+		)
+	};
+	if result != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+fn open_socket(ifname: &str, address: IpAddr) -> io::Result<DownstreamSocket> {
+	let domain = match address {
+		IpAddr::V4(_) => Domain::IPV4,
+		IpAddr::V6(_) => Domain::IPV6,
+	};
+	let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+	if address.is_ipv6() {
+		socket.set_only_v6(true)?;
+	}
+	// Shares port 53 with the sockets on other interfaces
+	socket.set_reuse_address(true)?;
+	socket.bind_device(Some(ifname.as_bytes()))?;
+	socket.set_nonblocking(true)?;
+	set_recv_packet_info(&socket, address.is_ipv6())?;
+	socket.bind(&SocketAddr::new(address, dns::PORT).into())?;
+	Ok(DownstreamSocket {
+		socket: AsyncFd::new(socket)?,
+		ipv6: address.is_ipv6(),
+	})
+}
+
+/// Listens for clients retrying over TCP, after an answer was too large for UDP
+fn open_listener(ifname: &str, address: IpAddr) -> std::io::Result<TcpListener> {
+	let domain = match address {
+		IpAddr::V4(_) => Domain::IPV4,
+		IpAddr::V6(_) => Domain::IPV6,
+	};
+	let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+	if address.is_ipv6() {
+		socket.set_only_v6(true)?;
+	}
+	socket.set_reuse_address(true)?;
+	socket.bind_device(Some(ifname.as_bytes()))?;
+	socket.set_nonblocking(true)?;
+	socket.bind(&SocketAddr::new(address, dns::PORT).into())?;
+	socket.listen(128)?;
+	TcpListener::from_std(socket.into())
+}
+
+/// Reads a message prefixed with its length, as DNS is sent over TCP (RFC 1035 4.2.2)
+async fn read_tcp_message(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+	let len = stream.read_u16().await?;
+	let mut data = vec![0; len as usize];
+	stream.read_exact(&mut data).await?;
+	Ok(data)
+}
+
+async fn write_tcp_message(stream: &mut TcpStream, data: &[u8]) -> std::io::Result<()> {
+	let mut message = (data.len() as u16).to_be_bytes().to_vec();
+	message.extend_from_slice(data);
+	stream.write_all(&message).await
+}
+
+/// Makes a hostname sent by a DHCP client usable as a local name, if anything is left of it
+fn sanitize_hostname(hostname: &str) -> Option<String> {
+	// Some clients send their fully qualified name
+	let label: String = hostname
+		.split('.')
+		.next()?
+		.to_lowercase()
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+		.collect();
+	let label = label.trim_matches('-');
+	(!label.is_empty() && label.len() <= 63).then(|| label.to_string())
+}
+
+/// The address a reverse lookup in `in-addr.arpa` is for
+fn reverse_address(name: &str) -> Option<Ipv4Addr> {
+	let octets: Vec<u8> = name
+		.strip_suffix(".in-addr.arpa")?
+		.split('.')
+		.rev()
+		.map(|octet| octet.parse().ok())
+		.collect::<Option<_>>()?;
+	Some(Ipv4Addr::from(<[u8; 4]>::try_from(octets).ok()?))
+}
+
+/// A query forwarded upstream, waiting for an answer
+struct PendingQuery {
+	/// The socket the query was received on
+	downstream: Arc<DownstreamSocket>,
+	client: SocketAddr,
+	info: Option<PacketInfo>,
+	/// The id the client used, it is replaced by a random one upstream
+	id: u16,
+	question: Vec<u8>,
+	servers: Vec<SocketAddr>,
+	sent: Instant,
+}
+
+struct Forwarder {
+	/// Upstream servers from the configuration
+	servers: Vec<SocketAddr>,
+	domain: Option<String>,
+	/// Static host entries, by local name
+	hosts: Vec<(String, IpAddr)>,
+	dhcp_hostnames: bool,
+	local_ttl: u32,
+	upstream_v4: UdpSocket,
+	upstream_v6: Option<UdpSocket>,
+	/// Forwarded queries by upstream id
+	pending: Mutex<HashMap<u16, PendingQuery>>,
+}
+
+impl Forwarder {
+	/// The name without the local domain
+	fn local_name<'a>(&self, name: &'a str) -> &'a str {
+		self.domain
+			.as_deref()
+			.and_then(|domain| name.strip_suffix(domain)?.strip_suffix('.'))
+			.unwrap_or(name)
+	}
+
+	/// Single label names and names in the local domain are never forwarded
+	fn is_local(&self, name: &str) -> bool {
+		(!name.is_empty() && !name.contains('.'))
+			|| self
+				.domain
+				.as_deref()
+				.is_some_and(|domain| name == domain || name.ends_with(&format!(".{domain}")))
+	}
+
+	/// The static host entries and the hostnames of DHCP clients.
+	/// Clients can't take over a name that has a static entry
+	fn local_hosts(&self) -> Vec<(String, IpAddr)> {
+		let mut hosts = self.hosts.clone();
+		if self.dhcp_hostnames {
+			for (hostname, address) in dhcpd::get_hostnames() {
+				let Some(name) = sanitize_hostname(&hostname) else {
+					continue;
+				};
+				if !self.hosts.iter().any(|(host, _)| *host == name) {
+					hosts.push((name, address.into()));
+				}
+			}
+		}
+		hosts
+	}
+
+	fn upstream_servers(&self) -> Vec<SocketAddr> {
+		if !self.servers.is_empty() {
+			return self.servers.clone();
+		}
+		let mut servers: Vec<SocketAddr> = dhcpc::get_dns_servers()
+			.into_iter()
+			.map(IpAddr::from)
+			.chain(dhcp6c::get_dns_servers().into_iter().map(IpAddr::from))
+			.map(|address| SocketAddr::new(address, dns::PORT))
+			.collect();
+		servers.sort();
+		servers.dedup();
+		servers
+	}
+
+	/// Answers queries for local names, returns None if the query has to be forwarded
+	fn answer_locally(&self, query: &Query) -> Option<Vec<u8>> {
+		if query.class != CLASS_IN {
+			return None;
+		}
+		let hosts = self.local_hosts();
+		if query.query_type == types::PTR {
+			let address = IpAddr::V4(reverse_address(&query.name)?);
+			let (name, _) = hosts.iter().find(|(_, host)| *host == address)?;
+			let name = match &self.domain {
+				Some(domain) if !name.contains('.') => format!("{name}.{domain}"),
+				_ => name.clone(),
+			};
+			return Some(query.reply(NOERROR, &[Record::Ptr(name)], self.local_ttl));
+		}
+		let name = self.local_name(&query.name);
+		let addresses: Vec<IpAddr> = hosts
+			.iter()
+			.filter(|(host, _)| host == name)
+			.map(|(_, address)| *address)
+			.collect();
+		if addresses.is_empty() {
+			return self
+				.is_local(&query.name)
+				.then(|| query.reply(NXDOMAIN, &[], self.local_ttl));
+		}
+		let answers: Vec<Record> = addresses
+			.into_iter()
+			.filter_map(|address| match (address, query.query_type) {
+				(IpAddr::V4(address), types::A | types::ANY) => Some(Record::A(address)),
+				(IpAddr::V6(address), types::AAAA | types::ANY) => Some(Record::Aaaa(address)),
+				_ => None,
+			})
+			.collect();
+		// No answers of the queried type still means the name exists
+		Some(query.reply(NOERROR, &answers, self.local_ttl))
+	}
+
+	async fn forward(
+		&self,
+		query: &Query<'_>,
+		downstream: &Arc<DownstreamSocket>,
+		client: SocketAddr,
+		info: Option<PacketInfo>,
+	) {
+		let servers = self.upstream_servers();
+		if servers.is_empty() {
+			let _ = downstream
+				.send_to(&query.reply(SERVFAIL, &[], 0), client, info)
+				.await;
+			return;
+		}
+		let id = {
+			let mut pending = self.pending.lock().unwrap();
+			let now = Instant::now();
+			pending.retain(|_, query| now - query.sent < QUERY_TIMEOUT);
+			if pending.len() >= MAX_PENDING {
+				return;
+			}
+			// A random id makes forged answers harder to get accepted
+			let mut id = thread_rng().gen();
+			while pending.contains_key(&id) {
+				id = thread_rng().gen();
+			}
+			pending.insert(
+				id,
+				PendingQuery {
+					downstream: Arc::clone(downstream),
+					client,
+					info,
+					id: dns::id(query.data).unwrap_or_default(),
+					question: query.question().to_vec(),
+					servers: servers.clone(),
+					sent: now,
+				},
+			);
+			id
+		};
+		let mut data = query.data.to_vec();
+		dns::set_id(&mut data, id);
+		// Every server is asked, the first answer wins
+		for server in servers {
+			let socket = match server {
+				SocketAddr::V4(_) => &self.upstream_v4,
+				SocketAddr::V6(_) => match &self.upstream_v6 {
+					Some(socket) => socket,
+					None => continue,
+				},
+			};
+			if let Err(err) = socket.send_to(&data, server).await {
+				println!("Failed to forward DNS query to {server}: {err}");
+			}
+		}
+	}
+
+	/// Answers queries received on one of the interfaces
+	async fn serve(self: Arc<Self>, ifname: String, socket: Arc<DownstreamSocket>) {
+		loop {
+			let (data, client, info) = match socket.recv().await {
+				Ok(received) => received,
+				Err(err) => {
+					println!("[{ifname}] DNS forwarder stopped: {err}");
+					return;
+				}
+			};
+			let Some(query) = Query::decode(&data) else {
+				continue;
+			};
+			match self.answer_locally(&query) {
+				Some(reply) => {
+					let _ = socket.send_to(&reply, client, info).await;
+				}
+				None => self.forward(&query, &socket, client, info).await,
+			}
+		}
+	}
+
+	/// Asks the upstream servers over TCP one after the other, returning the first answer
+	async fn forward_tcp(&self, query: &Query<'_>) -> Option<Vec<u8>> {
+		for server in self.upstream_servers() {
+			let exchange = async {
+				let mut stream = TcpStream::connect(server).await?;
+				write_tcp_message(&mut stream, query.data).await?;
+				read_tcp_message(&mut stream).await
+			};
+			match tokio::time::timeout(QUERY_TIMEOUT, exchange).await {
+				Ok(Ok(answer))
+					if dns::is_response(&answer)
+						&& dns::id(&answer) == dns::id(query.data)
+						&& dns::question_section(&answer) == Some(query.question()) =>
+				{
+					return Some(answer);
+				}
+				Ok(Err(err)) => println!("Failed to forward DNS query to {server} over TCP: {err}"),
+				_ => {}
+			}
+		}
+		None
+	}
+
+	/// Answers the queries of a client connected over TCP until it goes quiet
+	async fn serve_connection(self: Arc<Self>, mut stream: TcpStream) {
+		while let Ok(Ok(data)) =
+			tokio::time::timeout(TCP_IDLE_TIMEOUT, read_tcp_message(&mut stream)).await
+		{
+			let Some(query) = Query::decode(&data) else {
+				return;
+			};
+			let reply = match self.answer_locally(&query) {
+				Some(reply) => reply,
+				None => match self.forward_tcp(&query).await {
+					Some(answer) => answer,
+					None => query.reply(SERVFAIL, &[], 0),
+				},
+			};
+			if write_tcp_message(&mut stream, &reply).await.is_err() {
+				return;
+			}
+		}
+	}
+
+	async fn serve_tcp(self: Arc<Self>, ifname: String, listener: TcpListener) {
+		// Dropped with the listener when the forwarder stops, which closes the connections too
+		let mut connections = JoinSet::new();
+		loop {
+			tokio::select! {
+				accepted = listener.accept() => match accepted {
+					Ok((stream, _)) => {
+						connections.spawn(Arc::clone(&self).serve_connection(stream));
+					}
+					Err(err) => {
+						println!("[{ifname}] DNS forwarder stopped accepting connections: {err}");
+						return;
+					}
+				},
+				// Finished connections are reaped so they don't pile up
+				Some(_) = connections.join_next() => {}
+			}
+		}
+	}
+
+	/// Answers queries on the interface over UDP and TCP.
+	/// Waits for the interface to appear, as bridges and VLANs are only created while configuring
+	async fn listen(self: Arc<Self>, ifname: String, address: IpAddr) {
+		let mut failed = false;
+		let (socket, listener) = loop {
+			match open_socket(&ifname, address)
+				.and_then(|socket| Ok((socket, open_listener(&ifname, address)?)))
+			{
+				Ok(sockets) => break sockets,
+				Err(err) => {
+					if !failed {
+						println!("[{ifname}] Could not listen for DNS queries yet: {err}");
+						failed = true;
+					}
+					tokio::time::sleep(LISTEN_RETRY).await;
+				}
+			}
+		};
+		if failed {
+			println!("[{ifname}] Listening for DNS queries");
+		}
+		tokio::join!(
+			Arc::clone(&self).serve(ifname.clone(), Arc::new(socket)),
+			self.serve_tcp(ifname, listener)
+		);
+	}
+
+	/// Passes answers from the upstream servers on to the clients that asked
+	async fn receive_answers(self: Arc<Self>, ipv6: bool) {
+		let socket = match (ipv6, &self.upstream_v6) {
+			(false, _) => &self.upstream_v4,
+			(true, Some(socket)) => socket,
+			(true, None) => return,
+		};
+		// Answers may be as large as the client's EDNS buffer
+		let mut buffer = vec![0u8; 65535];
+		loop {
+			let (len, source) = match socket.recv_from(&mut buffer).await {
+				Ok(received) => received,
+				Err(err) => {
+					println!("DNS forwarder stopped receiving answers: {err}");
+					return;
+				}
+			};
+			let data = &mut buffer[..len];
+			let Some(id) = dns::id(data).filter(|_| dns::is_response(data)) else {
+				continue;
+			};
+			// Only answers from the servers that were asked, to the question that was asked, are accepted
+			let pending = {
+				let mut pending = self.pending.lock().unwrap();
+				match pending.get(&id) {
+					Some(query)
+						if query.servers.contains(&source)
+							&& dns::question_section(data) == Some(&query.question) =>
+					{
+						pending.remove(&id)
+					}
+					_ => None,
+				}
+			};
+			let Some(pending) = pending else {
+				continue;
+			};
+			dns::set_id(data, pending.id);
+			let _ = pending
+				.downstream
+				.send_to(data, pending.client, pending.info)
+				.await;
+		}
+	}
+}
+
+/// Starts answering DNS queries on the configured interfaces
+pub async fn start(config: &DnsConfig) {
+	stop().await;
+	let parse = |address: &str| match IpAddr::from_str(address) {
+		Ok(address) => Some(address),
+		Err(_) => {
+			println!("Invalid address in the DNS configuration: {address}");
+			None
+		}
+	};
+	let servers = config
+		.servers
+		.iter()
+		.filter_map(|server| parse(server))
+		.map(|server| SocketAddr::new(server, dns::PORT))
+		.collect();
+	let domain = config
+		.domain
+		.as_deref()
+		.map(|domain| domain.trim_matches('.').to_lowercase());
+	let mut hosts = vec![];
+	for (name, addresses) in &config.hosts {
+		let name = name.trim_end_matches('.').to_lowercase();
+		// Static entries may be written with the local domain
+		let name = match &domain {
+			Some(domain) => name
+				.strip_suffix(&format!(".{domain}"))
+				.map(str::to_string)
+				.unwrap_or(name),
+			None => name,
+		};
+		for address in addresses.addresses() {
+			if let Some(address) = parse(&address) {
+				hosts.push((name.clone(), address));
+			}
+		}
+	}
+	let upstream_v4 = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await {
+		Ok(socket) => socket,
+		Err(err) => {
+			println!("Could not start DNS forwarder: {err}");
+			return;
+		}
+	};
+	let forwarder = Arc::new(Forwarder {
+		servers,
+		hosts,
+		domain,
+		dhcp_hostnames: config.dhcp_hostnames,
+		local_ttl: config.local_ttl,
+		upstream_v4,
+		upstream_v6: UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await.ok(),
+		pending: Mutex::new(HashMap::new()),
+	});
+
+	let mut tasks = vec![];
+	for ifname in &config.interfaces {
+		for address in [
+			IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+			IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+		] {
+			tasks.push(tokio::spawn(
+				Arc::clone(&forwarder).listen(ifname.clone(), address),
+			));
+		}
+	}
+	if tasks.is_empty() {
+		return;
+	}
+	println!(
+		"DNS forwarder listening on {}",
+		config.interfaces.join(", ")
+	);
+	tasks.push(tokio::spawn(Arc::clone(&forwarder).receive_answers(false)));
+	tasks.push(tokio::spawn(forwarder.receive_answers(true)));
+	*FORWARDER.lock().unwrap() = tasks;
+}
+
+/// Stops the DNS forwarder
+pub async fn stop() {
+	let tasks = std::mem::take(&mut *FORWARDER.lock().unwrap());
+	if tasks.is_empty() {
+		return;
+	}
+	println!("Stopping DNS forwarder");
+	for task in tasks {
+		task.abort();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn packet_info_round_trip() {
+		for local in [
+			IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+			IpAddr::V6("fe80::1".parse().unwrap()),
+		] {
+			let control = PacketInfo { local, ifindex: 3 }.encode();
+			let info = PacketInfo::parse(&control).unwrap();
+			assert_eq!(info.local, local);
+			assert_eq!(info.ifindex, 3);
+			assert!(PacketInfo::parse(&control[..control.len() - 8]).is_none());
+		}
+		assert!(PacketInfo::parse(&[]).is_none());
+	}
+}
@@ -0,0 +1,484 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use futures::TryStreamExt;
+use netlink_packet_route::link::LinkAttribute;
+use rtnetlink::new_connection;
+use serde::Serialize;
+
+use crate::{
+	config::{Duplex, EthtoolConfig},
+	link::{
+		genl::{
+			get_attribute, get_string, get_u32, get_u8, parse_attributes, Attribute, GenlSocket,
+		},
+		interface::Interface,
+	},
+};
+
+const ETHTOOL_GENL_NAME: &str = "ethtool";
+const ETHTOOL_GENL_VERSION: u8 = 1;
+
+/// Message types of the ethtool netlink family
+mod messages {
+	pub const LINKMODES_GET: u8 = 4;
+	pub const LINKMODES_SET: u8 = 5;
+	pub const LINKSTATE_GET: u8 = 6;
+	pub const FEATURES_GET: u8 = 11;
+	pub const FEATURES_SET: u8 = 12;
+	pub const RINGS_GET: u8 = 15;
+	pub const RINGS_SET: u8 = 16;
+	pub const PAUSE_GET: u8 = 21;
+	pub const PAUSE_SET: u8 = 22;
+
+	pub fn name(message: u8) -> &'static str {
+		match message {
+			LINKMODES_GET => "LINKMODES_GET",
+			LINKMODES_SET => "LINKMODES_SET",
+			LINKSTATE_GET => "LINKSTATE_GET",
+			FEATURES_GET => "FEATURES_GET",
+			FEATURES_SET => "FEATURES_SET",
+			RINGS_GET => "RINGS_GET",
+			RINGS_SET => "RINGS_SET",
+			PAUSE_GET => "PAUSE_GET",
+			PAUSE_SET => "PAUSE_SET",
+			_ => "unknown",
+		}
+	}
+}
+
+/// The request header, attribute 1 of every message
+const HEADER: u16 = 1;
+const HEADER_DEV_NAME: u16 = 2;
+
+const BITSET_NOMASK: u16 = 1;
+const BITSET_BITS: u16 = 3;
+const BITSET_BITS_BIT: u16 = 1;
+const BITSET_BIT_NAME: u16 = 2;
+const BITSET_BIT_VALUE: u16 = 3;
+
+const LINKMODES_AUTONEG: u16 = 2;
+const LINKMODES_SPEED: u16 = 5;
+const LINKMODES_DUPLEX: u16 = 6;
+const DUPLEX_HALF: u8 = 0;
+const DUPLEX_FULL: u8 = 1;
+const SPEED_UNKNOWN: u32 = u32::MAX;
+
+const LINKSTATE_LINK: u16 = 2;
+
+const FEATURES_HW: u16 = 2;
+const FEATURES_WANTED: u16 = 3;
+const FEATURES_ACTIVE: u16 = 4;
+
+const RINGS_RX: u16 = 6;
+const RINGS_RX_MINI: u16 = 7;
+const RINGS_RX_JUMBO: u16 = 8;
+const RINGS_TX: u16 = 9;
+
+const PAUSE_AUTONEG: u16 = 2;
+const PAUSE_RX: u16 = 3;
+const PAUSE_TX: u16 = 4;
+
+/// Link settings as currently reported by the driver
+#[derive(Serialize, Default)]
+pub struct EthtoolStatus {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub speed: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub duplex: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub autoneg: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub link_detected: Option<String>,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	pub offloads: BTreeMap<String, String>,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	pub ring: BTreeMap<String, String>,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	pub pause: BTreeMap<String, String>,
+}
+
+fn on_off(value: bool) -> &'static str {
+	if value {
+		"on"
+	} else {
+		"off"
+	}
+}
+
+fn header(ifname: &str) -> Attribute {
+	Attribute::Nested(
+		HEADER,
+		vec![Attribute::String(HEADER_DEV_NAME, ifname.to_string())],
+	)
+}
+
+/// Opens the ethtool netlink family, logging why if the kernel does not support it
+async fn open(ifname: &str) -> Option<GenlSocket> {
+	match GenlSocket::open(ETHTOOL_GENL_NAME, ETHTOOL_GENL_VERSION).await {
+		Ok(socket) => Some(socket),
+		Err(err) => {
+			println!("[{ifname}] Could not open ethtool netlink socket: {err}");
+			None
+		}
+	}
+}
+
+/// Sends a request for the interface and returns the attributes of the reply.
+/// Drivers reject settings they do not support, so failures are logged instead of aborting the configuration
+async fn request(
+	socket: &mut GenlSocket,
+	ifname: &str,
+	command: u8,
+	mut attributes: Vec<Attribute>,
+) -> Option<Vec<u8>> {
+	attributes.insert(0, header(ifname));
+	match socket.request(command, attributes).await {
+		Ok(reply) => Some(reply),
+		Err(err) => {
+			println!(
+				"[{ifname}] ethtool {} failed: {err}",
+				messages::name(command)
+			);
+			None
+		}
+	}
+}
+
+/// Returns the names of the bits that are set in a verbose bitset
+fn parse_bitset(data: &[u8]) -> BTreeSet<String> {
+	let attributes = parse_attributes(data);
+	// Without a mask only the bits that are set are listed
+	let nomask = get_attribute(&attributes, BITSET_NOMASK).is_some();
+	let Some(bits) = get_attribute(&attributes, BITSET_BITS) else {
+		return BTreeSet::new();
+	};
+	parse_attributes(bits)
+		.into_iter()
+		.filter(|(kind, _)| *kind == BITSET_BITS_BIT)
+		.map(|(_, bit)| parse_attributes(bit))
+		.filter(|bit| nomask || get_attribute(bit, BITSET_BIT_VALUE).is_some())
+		.filter_map(|bit| get_string(&bit, BITSET_BIT_NAME))
+		.collect()
+}
+
+/// Kernel features behind the names the ethtool command accepts.
+/// Returns None for names that are already kernel feature names
+fn legacy_features(feature: &str) -> Option<&'static [&'static str]> {
+	Some(match feature {
+		"rx" | "rx-checksumming" => &["rx-checksum"],
+		"tx" | "tx-checksumming" => &[
+			"tx-checksum-ipv4",
+			"tx-checksum-ip-generic",
+			"tx-checksum-ipv6",
+			"tx-checksum-fcoe-crc",
+			"tx-checksum-sctp",
+		],
+		"sg" | "scatter-gather" => &["tx-scatter-gather", "tx-scatter-gather-fraglist"],
+		"tso" | "tcp-segmentation-offload" => &[
+			"tx-tcp-segmentation",
+			"tx-tcp-ecn-segmentation",
+			"tx-tcp-mangleid-segmentation",
+			"tx-tcp6-segmentation",
+		],
+		"ufo" | "udp-fragmentation-offload" => &["tx-udp-fragmentation"],
+		"gso" | "generic-segmentation-offload" => &["tx-generic-segmentation"],
+		"gro" | "generic-receive-offload" => &["rx-gro"],
+		"lro" | "large-receive-offload" => &["rx-lro"],
+		"rxvlan" | "rx-vlan-offload" => &["rx-vlan-hw-parse"],
+		"txvlan" | "tx-vlan-offload" => &["tx-vlan-hw-insert"],
+		"ntuple" | "ntuple-filters" => &["rx-ntuple-filter"],
+		"rxhash" | "receive-hashing" => &["rx-hashing"],
+		_ => return None,
+	})
+}
+
+/// Kernel features the configured name refers to, limited to the ones in `available`
+fn kernel_features(feature: &str, available: &BTreeSet<String>) -> Vec<String> {
+	match legacy_features(feature) {
+		Some(features) => features
+			.iter()
+			.filter(|name| available.contains(**name))
+			.map(|name| name.to_string())
+			.collect(),
+		None => [feature.to_string()]
+			.into_iter()
+			.filter(|name| available.contains(name))
+			.collect(),
+	}
+}
+
+async fn apply_link_modes(socket: &mut GenlSocket, ifname: &str, config: &EthtoolConfig) {
+	let mut attributes = vec![];
+	let mut link = vec![];
+	if let Some(speed) = config.speed {
+		attributes.push(Attribute::U32(LINKMODES_SPEED, speed));
+		link.push(format!("speed {speed}"));
+	}
+	if let Some(duplex) = config.duplex {
+		let (value, name) = match duplex {
+			Duplex::Half => (DUPLEX_HALF, "half"),
+			Duplex::Full => (DUPLEX_FULL, "full"),
+		};
+		attributes.push(Attribute::U8(LINKMODES_DUPLEX, value));
+		link.push(format!("duplex {name}"));
+	}
+	if let Some(autoneg) = config.autoneg {
+		attributes.push(Attribute::U8(LINKMODES_AUTONEG, autoneg as u8));
+		link.push(format!("autoneg {}", on_off(autoneg)));
+	}
+	if !attributes.is_empty() {
+		println!("[{ifname}] Setting link parameters: {}", link.join(" "));
+		request(socket, ifname, messages::LINKMODES_SET, attributes).await;
+	}
+}
+
+async fn apply_offloads(socket: &mut GenlSocket, ifname: &str, offloads: &BTreeMap<String, bool>) {
+	if offloads.is_empty() {
+		return;
+	}
+	let Some(reply) = request(socket, ifname, messages::FEATURES_GET, vec![]).await else {
+		return;
+	};
+	// Only features in the hw set can be changed
+	let changeable = get_attribute(&parse_attributes(&reply), FEATURES_HW)
+		.map(parse_bitset)
+		.unwrap_or_default();
+
+	let mut bits = vec![];
+	let mut changes = vec![];
+	for (feature, enabled) in offloads {
+		let features = kernel_features(feature, &changeable);
+		if features.is_empty() {
+			println!("[{ifname}] Offload {feature} can not be changed");
+			continue;
+		}
+		changes.push(format!("{feature} {}", on_off(*enabled)));
+		for name in features {
+			let mut bit = vec![Attribute::String(BITSET_BIT_NAME, name)];
+			if *enabled {
+				bit.push(Attribute::Flag(BITSET_BIT_VALUE));
+			}
+			bits.push(Attribute::Nested(BITSET_BITS_BIT, bit));
+		}
+	}
+	if bits.is_empty() {
+		return;
+	}
+	println!("[{ifname}] Setting offloads: {}", changes.join(" "));
+	// Without the nomask flag the listed bits form the mask, so other features are left alone
+	let wanted = Attribute::Nested(FEATURES_WANTED, vec![Attribute::Nested(BITSET_BITS, bits)]);
+	request(socket, ifname, messages::FEATURES_SET, vec![wanted]).await;
+}
+
+/// Apply the ethtool settings to the interface
+pub async fn apply(interface: &Interface, config: &EthtoolConfig) {
+	let ifname = interface.name.clone();
+	let Some(mut socket) = open(&ifname).await else {
+		return;
+	};
+
+	apply_link_modes(&mut socket, &ifname, config).await;
+	apply_offloads(&mut socket, &ifname, &config.offloads).await;
+
+	if let Some(ring) = &config.ring {
+		let mut attributes = vec![];
+		let mut sizes = vec![];
+		for (name, kind, value) in [
+			("rx", RINGS_RX, ring.rx),
+			("rx-mini", RINGS_RX_MINI, ring.rx_mini),
+			("rx-jumbo", RINGS_RX_JUMBO, ring.rx_jumbo),
+			("tx", RINGS_TX, ring.tx),
+		] {
+			if let Some(value) = value {
+				attributes.push(Attribute::U32(kind, value));
+				sizes.push(format!("{name} {value}"));
+			}
+		}
+		if !attributes.is_empty() {
+			println!("[{ifname}] Setting ring sizes: {}", sizes.join(" "));
+			request(&mut socket, &ifname, messages::RINGS_SET, attributes).await;
+		}
+	}
+
+	if let Some(pause) = &config.pause {
+		let mut attributes = vec![];
+		let mut frames = vec![];
+		for (name, kind, value) in [
+			("autoneg", PAUSE_AUTONEG, pause.autoneg),
+			("rx", PAUSE_RX, pause.rx),
+			("tx", PAUSE_TX, pause.tx),
+		] {
+			if let Some(value) = value {
+				attributes.push(Attribute::U8(kind, value as u8));
+				frames.push(format!("{name} {}", on_off(value)));
+			}
+		}
+		if !attributes.is_empty() {
+			println!("[{ifname}] Setting pause frames: {}", frames.join(" "));
+			request(&mut socket, &ifname, messages::PAUSE_SET, attributes).await;
+		}
+	}
+}
+
+/// Read back the current link settings.
+/// Only the offloads that are configured are reported, the full feature list is very long
+pub async fn get_status(interface: &Interface, config: &EthtoolConfig) -> EthtoolStatus {
+	let ifname = interface.name.clone();
+	let mut status = EthtoolStatus::default();
+	let Some(mut socket) = open(&ifname).await else {
+		return status;
+	};
+
+	if let Some(reply) = request(&mut socket, &ifname, messages::LINKMODES_GET, vec![]).await {
+		let attributes = parse_attributes(&reply);
+		status.speed = get_u32(&attributes, LINKMODES_SPEED).map(|speed| match speed {
+			SPEED_UNKNOWN => "unknown".to_string(),
+			speed => format!("{speed}Mb/s"),
+		});
+		status.duplex = get_u8(&attributes, LINKMODES_DUPLEX).map(|duplex| {
+			match duplex {
+				DUPLEX_HALF => "half",
+				DUPLEX_FULL => "full",
+				_ => "unknown",
+			}
+			.to_string()
+		});
+		status.autoneg =
+			get_u8(&attributes, LINKMODES_AUTONEG).map(|autoneg| on_off(autoneg != 0).to_string());
+	}
+
+	if let Some(reply) = request(&mut socket, &ifname, messages::LINKSTATE_GET, vec![]).await {
+		status.link_detected = get_u8(&parse_attributes(&reply), LINKSTATE_LINK)
+			.map(|link| if link != 0 { "yes" } else { "no" }.to_string());
+	}
+
+	if !config.offloads.is_empty() {
+		if let Some(reply) = request(&mut socket, &ifname, messages::FEATURES_GET, vec![]).await {
+			let attributes = parse_attributes(&reply);
+			let active = get_attribute(&attributes, FEATURES_ACTIVE)
+				.map(parse_bitset)
+				.unwrap_or_default();
+			let hw = get_attribute(&attributes, FEATURES_HW)
+				.map(parse_bitset)
+				.unwrap_or_default();
+			let known: BTreeSet<String> = active.union(&hw).cloned().collect();
+			for feature in config.offloads.keys() {
+				let features = kernel_features(feature, &known);
+				// A legacy name counts as on when any of its features is, like the ethtool command shows it
+				let value = if features.iter().any(|name| active.contains(name)) {
+					"on"
+				} else if legacy_features(feature).is_some() || !features.is_empty() {
+					"off"
+				} else {
+					"unknown"
+				};
+				status.offloads.insert(feature.clone(), value.to_string());
+			}
+		}
+	}
+
+	if config.ring.is_some() {
+		if let Some(reply) = request(&mut socket, &ifname, messages::RINGS_GET, vec![]).await {
+			let attributes = parse_attributes(&reply);
+			for (name, kind) in [
+				("rx", RINGS_RX),
+				("rx-mini", RINGS_RX_MINI),
+				("rx-jumbo", RINGS_RX_JUMBO),
+				("tx", RINGS_TX),
+			] {
+				if let Some(value) = get_u32(&attributes, kind) {
+					status.ring.insert(name.to_string(), value.to_string());
+				}
+			}
+		}
+	}
+
+	if config.pause.is_some() {
+		if let Some(reply) = request(&mut socket, &ifname, messages::PAUSE_GET, vec![]).await {
+			let attributes = parse_attributes(&reply);
+			for (name, kind) in [
+				("autoneg", PAUSE_AUTONEG),
+				("rx", PAUSE_RX),
+				("tx", PAUSE_TX),
+			] {
+				if let Some(value) = get_u8(&attributes, kind) {
+					status
+						.pause
+						.insert(name.to_string(), on_off(value != 0).to_string());
+				}
+			}
+		}
+	}
+
+	status
+}
+
+/// Get the permanent (burnt in) MAC address of the interface
+pub async fn get_permanent_mac(interface: &Interface) -> Option<String> {
+	let (connection, handle, _) = new_connection().ok()?;
+	tokio::spawn(connection);
+	let link = handle
+		.link()
+		.get()
+		.match_name(interface.name.clone())
+		.execute()
+		.try_next()
+		.await
+		.ok()??;
+	link.attributes
+		.into_iter()
+		.find_map(|attribute| match attribute {
+			LinkAttribute::PermAddress(mac) => Some(
+				mac.iter()
+					.map(|byte| format!("{byte:02x}"))
+					.collect::<Vec<_>>()
+					.join(":"),
+			),
+			_ => None,
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn legacy_feature_names() {
+		let available: BTreeSet<String> = ["rx-gro", "tx-tcp-segmentation", "tx-tcp6-segmentation"]
+			.into_iter()
+			.map(String::from)
+			.collect();
+		assert_eq!(kernel_features("gro", &available), vec!["rx-gro"]);
+		assert_eq!(
+			kernel_features("tcp-segmentation-offload", &available),
+			vec!["tx-tcp-segmentation", "tx-tcp6-segmentation"]
+		);
+		assert_eq!(kernel_features("rx-gro", &available), vec!["rx-gro"]);
+		assert!(kernel_features("lro", &available).is_empty());
+	}
+
+	#[test]
+	fn bitset_values() {
+		let bit = |name: &str, value: bool| {
+			let mut bit = vec![Attribute::String(BITSET_BIT_NAME, name.to_string())];
+			if value {
+				bit.push(Attribute::Flag(BITSET_BIT_VALUE));
+			}
+			Attribute::Nested(BITSET_BITS_BIT, bit)
+		};
+		let mut data = vec![];
+		Attribute::Nested(BITSET_BITS, vec![bit("rx-gro", true), bit("rx-lro", false)])
+			.encode(&mut data);
+		assert_eq!(parse_bitset(&data), BTreeSet::from(["rx-gro".to_string()]));
+
+		// In a list every listed bit is set
+		let mut data = vec![];
+		Attribute::Flag(BITSET_NOMASK).encode(&mut data);
+		Attribute::Nested(
+			BITSET_BITS,
+			vec![bit("rx-gro", false), bit("rx-lro", false)],
+		)
+		.encode(&mut data);
+		assert_eq!(parse_bitset(&data).len(), 2);
+	}
+}
@@ -0,0 +1,308 @@
+use std::{io, mem::MaybeUninit};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use tokio::io::unix::AsyncFd;
+
+const NLMSG_HEADER_LEN: usize = 16;
+const GENL_HEADER_LEN: usize = 4;
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+/// The error message carries extended ack attributes
+const NLM_F_ACK_TLVS: u16 = 0x200;
+const NLMSGERR_ATTR_MSG: u16 = 1;
+
+const NLA_F_NESTED: u16 = 0x8000;
+const NLA_F_NET_BYTEORDER: u16 = 0x4000;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+/// A netlink attribute to send
+pub enum Attribute {
+	U8(u16, u8),
+	U32(u16, u32),
+	String(u16, String),
+	Flag(u16),
+	Nested(u16, Vec<Attribute>),
+}
+
+impl Attribute {
+	pub fn encode(&self, data: &mut Vec<u8>) {
+		let start = data.len();
+		// The length is filled in once the payload is known
+		data.extend_from_slice(&[0, 0]);
+		match self {
+			Attribute::U8(kind, value) => {
+				data.extend_from_slice(&kind.to_ne_bytes());
+				data.push(*value);
+			}
+			Attribute::U32(kind, value) => {
+				data.extend_from_slice(&kind.to_ne_bytes());
+				data.extend_from_slice(&value.to_ne_bytes());
+			}
+			Attribute::String(kind, value) => {
+				data.extend_from_slice(&kind.to_ne_bytes());
+				data.extend_from_slice(value.as_bytes());
+				data.push(0);
+			}
+			Attribute::Flag(kind) => data.extend_from_slice(&kind.to_ne_bytes()),
+			Attribute::Nested(kind, attributes) => {
+				data.extend_from_slice(&(kind | NLA_F_NESTED).to_ne_bytes());
+				for attribute in attributes {
+					attribute.encode(data);
+				}
+			}
+		}
+		let len = (data.len() - start) as u16;
+		data[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+		data.resize(data.len().next_multiple_of(4), 0);
+	}
+}
+
+/// Splits netlink attributes into their type, without the flags, and payload
+pub fn parse_attributes(data: &[u8]) -> Vec<(u16, &[u8])> {
+	let mut attributes = vec![];
+	let mut i = 0;
+	while i + 4 <= data.len() {
+		let len = u16::from_ne_bytes([data[i], data[i + 1]]) as usize;
+		let kind = u16::from_ne_bytes([data[i + 2], data[i + 3]]);
+		// The length includes the header, anything shorter would loop forever
+		let Some(payload) = data.get(i + 4..i + len).filter(|_| len >= 4) else {
+			break;
+		};
+		attributes.push((kind & !(NLA_F_NESTED | NLA_F_NET_BYTEORDER), payload));
+		i += len.next_multiple_of(4);
+	}
+	attributes
+}
+
+pub fn get_attribute<'a>(attributes: &[(u16, &'a [u8])], kind: u16) -> Option<&'a [u8]> {
+	attributes
+		.iter()
+		.find(|(k, _)| *k == kind)
+		.map(|(_, payload)| *payload)
+}
+
+pub fn get_u8(attributes: &[(u16, &[u8])], kind: u16) -> Option<u8> {
+	get_attribute(attributes, kind)?.first().copied()
+}
+
+pub fn get_u16(attributes: &[(u16, &[u8])], kind: u16) -> Option<u16> {
+	let payload = get_attribute(attributes, kind)?;
+	Some(u16::from_ne_bytes(payload.get(..2)?.try_into().ok()?))
+}
+
+pub fn get_u32(attributes: &[(u16, &[u8])], kind: u16) -> Option<u32> {
+	let payload = get_attribute(attributes, kind)?;
+	Some(u32::from_ne_bytes(payload.get(..4)?.try_into().ok()?))
+}
+
+pub fn get_string(attributes: &[(u16, &[u8])], kind: u16) -> Option<String> {
+	let payload = get_attribute(attributes, kind)?;
+	let end = payload
+		.iter()
+		.position(|b| *b == 0)
+		.unwrap_or(payload.len());
+	Some(String::from_utf8_lossy(&payload[..end]).to_string())
+}
+
+/// Turns a netlink error message into an io::Error, including the kernel's explanation if there is one
+fn parse_error(flags: u16, payload: &[u8]) -> Option<io::Error> {
+	let error = i32::from_ne_bytes(payload.get(..4)?.try_into().ok()?);
+	if error == 0 {
+		return None;
+	}
+	let error = io::Error::from_raw_os_error(-error);
+	// With NETLINK_CAP_ACK only the header of the request is echoed back
+	let message = payload
+		.get(4 + NLMSG_HEADER_LEN..)
+		.filter(|_| flags & NLM_F_ACK_TLVS != 0)
+		.and_then(|tlvs| get_string(&parse_attributes(tlvs), NLMSGERR_ATTR_MSG));
+	Some(match message {
+		Some(message) => io::Error::new(error.kind(), format!("{error}: {message}")),
+		None => error,
+	})
+}
+
+/// A generic netlink socket talking to a single family
+pub struct GenlSocket {
+	socket: AsyncFd<Socket>,
+	family: u16,
+	version: u8,
+	sequence: u32,
+}
+
+impl GenlSocket {
+	/// Opens a socket and resolves the id of the family by its name
+	pub async fn open(family: &str, version: u8) -> io::Result<GenlSocket> {
+		let socket = Socket::new(
+			Domain::from(libc::AF_NETLINK),
+			Type::RAW,
+			Some(Protocol::from(libc::NETLINK_GENERIC)),
+		)?;
+		for option in [libc::NETLINK_CAP_ACK, libc::NETLINK_EXT_ACK] {
+			let enable: libc::c_int = 1;
+			// SAFETY: the option value is a valid c_int for the duration of the call
+			let result = unsafe {
+				libc::setsockopt(
+					std::os::fd::AsRawFd::as_raw_fd(&socket),
+					libc::SOL_NETLINK,
+					option,
+					&enable as *const _ as *const libc::c_void,
+					size_of::<libc::c_int>() as libc::socklen_t,
+				)
+			};
+			if result < 0 {
+				return Err(io::Error::last_os_error());
+			}
+		}
+		socket.set_nonblocking(true)?;
+		let mut socket = GenlSocket {
+			socket: AsyncFd::new(socket)?,
+			family: GENL_ID_CTRL,
+			version: 1,
+			sequence: 0,
+		};
+		let reply = socket
+			.request(
+				CTRL_CMD_GETFAMILY,
+				vec![Attribute::String(CTRL_ATTR_FAMILY_NAME, family.to_string())],
+			)
+			.await?;
+		socket.family =
+			get_u16(&parse_attributes(&reply), CTRL_ATTR_FAMILY_ID).ok_or_else(|| {
+				io::Error::new(
+					io::ErrorKind::NotFound,
+					format!("No {family} netlink family"),
+				)
+			})?;
+		socket.version = version;
+		Ok(socket)
+	}
+
+	async fn send(&self, message: &[u8]) -> io::Result<()> {
+		// SAFETY: sockaddr_nl is plain old data and pid 0 addresses the kernel
+		let (_, kernel) = unsafe {
+			SockAddr::try_init(|storage, len| {
+				let address = &mut *(storage as *mut libc::sockaddr_nl);
+				address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+				*len = size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+				Ok(())
+			})?
+		};
+		loop {
+			let mut guard = self.socket.writable().await?;
+			match guard.try_io(|socket| socket.get_ref().send_to(message, &kernel)) {
+				Ok(result) => return result.map(|_| ()),
+				Err(_would_block) => continue,
+			}
+		}
+	}
+
+	async fn recv(&self, buffer: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+		loop {
+			let mut guard = self.socket.readable().await?;
+			match guard.try_io(|socket| socket.get_ref().recv(buffer)) {
+				Ok(result) => return result,
+				Err(_would_block) => continue,
+			}
+		}
+	}
+
+	/// Sends a command and waits for the kernel to acknowledge it.
+	/// Returns the attributes of the reply, empty if the command has no reply
+	pub async fn request(
+		&mut self,
+		command: u8,
+		attributes: Vec<Attribute>,
+	) -> io::Result<Vec<u8>> {
+		self.sequence = self.sequence.wrapping_add(1);
+		let mut message = vec![0; NLMSG_HEADER_LEN];
+		message.extend_from_slice(&[command, self.version, 0, 0]);
+		for attribute in &attributes {
+			attribute.encode(&mut message);
+		}
+		let len = message.len() as u32;
+		message[0..4].copy_from_slice(&len.to_ne_bytes());
+		message[4..6].copy_from_slice(&self.family.to_ne_bytes());
+		message[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+		message[8..12].copy_from_slice(&self.sequence.to_ne_bytes());
+		self.send(&message).await?;
+
+		let mut reply = vec![];
+		let mut buffer = vec![MaybeUninit::<u8>::uninit(); 65536];
+		loop {
+			let len = self.recv(&mut buffer).await?;
+			// SAFETY: recv initialized the first len bytes
+			let data = unsafe { &*(&buffer[..len] as *const [MaybeUninit<u8>] as *const [u8]) };
+			let mut i = 0;
+			while i + NLMSG_HEADER_LEN <= data.len() {
+				let header = &data[i..i + NLMSG_HEADER_LEN];
+				let len = u32::from_ne_bytes(header[0..4].try_into().unwrap()) as usize;
+				let kind = u16::from_ne_bytes([header[4], header[5]]);
+				let flags = u16::from_ne_bytes([header[6], header[7]]);
+				let sequence = u32::from_ne_bytes(header[8..12].try_into().unwrap());
+				let Some(payload) = data
+					.get(i + NLMSG_HEADER_LEN..i + len)
+					.filter(|_| len >= NLMSG_HEADER_LEN)
+				else {
+					break;
+				};
+				i += len.next_multiple_of(4);
+				// Replies to an earlier request that was given up on
+				if sequence != self.sequence {
+					continue;
+				}
+				if kind == NLMSG_ERROR {
+					return match parse_error(flags, payload) {
+						Some(err) => Err(err),
+						None => Ok(reply),
+					};
+				}
+				if let Some(attributes) = payload.get(GENL_HEADER_LEN..) {
+					reply = attributes.to_vec();
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn attribute_round_trip() {
+		let mut data = vec![];
+		for attribute in [
+			Attribute::U8(1, 7),
+			Attribute::Nested(
+				2,
+				vec![Attribute::String(3, "eth0".to_string()), Attribute::Flag(4)],
+			),
+			Attribute::U32(5, 1000),
+		] {
+			attribute.encode(&mut data);
+		}
+		assert_eq!(data.len() % 4, 0);
+
+		let attributes = parse_attributes(&data);
+		assert_eq!(attributes.len(), 3);
+		assert_eq!(get_u8(&attributes, 1), Some(7));
+		assert_eq!(get_u32(&attributes, 5), Some(1000));
+		let nested = parse_attributes(get_attribute(&attributes, 2).unwrap());
+		assert_eq!(get_string(&nested, 3).as_deref(), Some("eth0"));
+		assert_eq!(get_attribute(&nested, 4), Some(&[][..]));
+	}
+
+	#[test]
+	fn stops_at_truncated_attributes() {
+		// The second attribute claims more data than there is
+		let data = [5, 0, 1, 0, 9, 0, 0, 0, 12, 0, 2, 0, 1, 2];
+		let attributes = parse_attributes(&data);
+		assert_eq!(attributes, vec![(1, &[9][..])]);
+	}
+}
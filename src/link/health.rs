@@ -0,0 +1,258 @@
+use std::{
+	net::{IpAddr, SocketAddr},
+	str::FromStr,
+	time::Duration,
+};
+
+use futures::future::join_all;
+use rand::{thread_rng, Rng};
+use socket2::{Domain, Protocol, Socket, Type};
+use surge_ping::{Client, PingIdentifier, PingSequence, ICMP};
+use tokio::net::{TcpSocket, UdpSocket};
+
+use crate::{
+	arp,
+	config::{HealthCheckConfig, HealthProbe},
+	link::{dns, interface::Interface},
+};
+
+/// Probes the targets of a health check through one interface and tracks whether they count as up
+pub struct HealthCheck {
+	interface: Interface,
+	config: HealthCheckConfig,
+	probe: HealthProbe,
+	targets: Vec<SocketAddr>,
+	up: bool,
+	/// Rounds in a row whose result disagrees with `up`
+	streak: u32,
+}
+
+impl HealthCheck {
+	/// `default_targets` are probed when the configuration lists none, `up` is the state to start in
+	pub fn new(
+		interface: &Interface,
+		config: &HealthCheckConfig,
+		default_targets: &[IpAddr],
+		up: bool,
+	) -> HealthCheck {
+		let ifname = &interface.name;
+		let probe = config.probe.unwrap_or(HealthProbe::Icmp);
+		let default_port = match probe {
+			HealthProbe::Dns => dns::PORT,
+			_ => 0,
+		};
+		let mut targets: Vec<SocketAddr> = config
+			.targets
+			.iter()
+			.filter_map(|target| {
+				let parsed = SocketAddr::from_str(target)
+					.ok()
+					.or_else(|| {
+						IpAddr::from_str(target)
+							.ok()
+							.map(|address| (address, default_port).into())
+					})
+					.filter(|address| probe != HealthProbe::Tcp || address.port() != 0)
+					.filter(|address| probe != HealthProbe::Arp || address.is_ipv4());
+				if parsed.is_none() {
+					println!("[{ifname}] Invalid health check target: {target}");
+				}
+				parsed
+			})
+			.collect();
+		if config.targets.is_empty() {
+			targets = default_targets
+				.iter()
+				.map(|address| (*address, default_port).into())
+				.filter(|address: &SocketAddr| probe != HealthProbe::Arp || address.is_ipv4())
+				.collect();
+		}
+		HealthCheck {
+			interface: Interface::get_from_name(ifname),
+			config: config.clone(),
+			probe,
+			targets,
+			up,
+			streak: 0,
+		}
+	}
+
+	pub fn is_up(&self) -> bool {
+		self.up
+	}
+
+	/// Runs one round of probes and returns whether the state changed
+	pub async fn check(&mut self) -> bool {
+		let success = self.round().await;
+		self.record(success)
+	}
+
+	/// Counts the result of a round towards the rise or fall threshold and returns whether the state changed
+	fn record(&mut self, success: bool) -> bool {
+		if success == self.up {
+			self.streak = 0;
+			return false;
+		}
+		self.streak += 1;
+		let threshold = if self.up {
+			self.config.fall
+		} else {
+			self.config.rise
+		};
+		if self.streak < threshold.max(1) {
+			println!(
+				"[{}] Health check {} ({}/{})",
+				self.interface.name,
+				if success { "succeeded" } else { "failed" },
+				self.streak,
+				threshold
+			);
+			return false;
+		}
+		self.up = success;
+		self.streak = 0;
+		true
+	}
+
+	/// Probes every interval until the targets are in the given state
+	pub async fn wait_for(&mut self, up: bool) {
+		loop {
+			self.check().await;
+			if self.up == up {
+				return;
+			}
+			tokio::time::sleep(self.interval()).await;
+		}
+	}
+
+	/// The time between rounds
+	pub fn interval(&self) -> Duration {
+		Duration::from_secs(self.config.interval.max(1))
+	}
+
+	/// Probes all targets at once, the round succeeds when enough of them answered
+	async fn round(&self) -> bool {
+		let results = join_all(self.targets.iter().map(|target| self.probe(*target))).await;
+		let answered = results.into_iter().filter(|answered| *answered).count();
+		!self.targets.is_empty() && answered >= self.config.min_targets.clamp(1, self.targets.len())
+	}
+
+	async fn probe(&self, target: SocketAddr) -> bool {
+		let timeout = Duration::from_millis(self.config.timeout);
+		match (self.probe, target.ip()) {
+			(HealthProbe::Arp, IpAddr::V4(address)) => {
+				arp::probe(&self.interface, address, timeout).await
+			}
+			(HealthProbe::Arp, IpAddr::V6(_)) => false,
+			(HealthProbe::Icmp, address) => self.ping(address, timeout).await.is_ok(),
+			(HealthProbe::Tcp, _) => tokio::time::timeout(timeout, self.connect(target))
+				.await
+				.is_ok_and(|result| result.is_ok()),
+			(HealthProbe::Dns, _) => tokio::time::timeout(timeout, self.query(target))
+				.await
+				.is_ok_and(|result| result.is_ok()),
+		}
+	}
+
+	async fn ping(&self, address: IpAddr, timeout: Duration) -> Result<(), surge_ping::SurgeError> {
+		let kind = match address {
+			IpAddr::V4(_) => ICMP::V4,
+			IpAddr::V6(_) => ICMP::V6,
+		};
+		let config = surge_ping::Config::builder()
+			.kind(kind)
+			.interface(&self.interface.name)
+			.build();
+		let client = Client::new(&config)?;
+		let identifier = PingIdentifier(thread_rng().gen());
+		let mut pinger = client.pinger(address, identifier).await;
+		pinger.timeout(timeout);
+		pinger.ping(PingSequence(0), &[0; 8]).await?;
+		Ok(())
+	}
+
+	async fn connect(&self, target: SocketAddr) -> std::io::Result<()> {
+		let socket = match target {
+			SocketAddr::V4(_) => TcpSocket::new_v4()?,
+			SocketAddr::V6(_) => TcpSocket::new_v6()?,
+		};
+		socket.bind_device(Some(self.interface.name.as_bytes()))?;
+		socket.connect(target).await?;
+		Ok(())
+	}
+
+	/// Sends a query and waits for the answer to it, whatever the response code
+	async fn query(&self, target: SocketAddr) -> std::io::Result<()> {
+		let domain = match target {
+			SocketAddr::V4(_) => Domain::IPV4,
+			SocketAddr::V6(_) => Domain::IPV6,
+		};
+		let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+		socket.bind_device(Some(self.interface.name.as_bytes()))?;
+		socket.set_nonblocking(true)?;
+		let socket = UdpSocket::from_std(socket.into())?;
+		socket.connect(target).await?;
+		let id = thread_rng().gen();
+		socket
+			.send(&dns::query(id, &self.config.query, dns::types::A))
+			.await?;
+		let mut buffer = [0; 1500];
+		loop {
+			let len = socket.recv(&mut buffer).await?;
+			let data = &buffer[..len];
+			if dns::is_response(data) && dns::id(data) == Some(id) {
+				return Ok(());
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn health_check(config: &str, up: bool) -> HealthCheck {
+		let config: HealthCheckConfig = toml::from_str(config).unwrap();
+		HealthCheck::new(&Interface::get_from_name("test"), &config, &[], up)
+	}
+
+	#[test]
+	fn falls_after_threshold() {
+		let mut check = health_check("fall = 3", true);
+		assert!(!check.record(false));
+		assert!(!check.record(false));
+		assert!(check.is_up());
+		assert!(check.record(false));
+		assert!(!check.is_up());
+		// Staying down is no change
+		assert!(!check.record(false));
+	}
+
+	#[test]
+	fn rises_after_threshold() {
+		let mut check = health_check("rise = 2", false);
+		assert!(!check.record(true));
+		assert!(check.record(true));
+		assert!(check.is_up());
+	}
+
+	#[test]
+	fn streak_resets_on_agreeing_round() {
+		let mut check = health_check("fall = 3", true);
+		for success in [false, false, true, false, false, true] {
+			assert!(!check.record(success));
+		}
+		assert!(check.is_up());
+		assert!(!check.record(false));
+		assert!(!check.record(false));
+		assert!(check.record(false));
+	}
+
+	#[test]
+	fn zero_threshold_changes_at_once() {
+		let mut check = health_check("rise = 0\nfall = 0", true);
+		assert!(check.record(false));
+		assert!(check.record(true));
+		assert!(check.is_up());
+	}
+}
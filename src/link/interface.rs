@@ -1,5 +1,10 @@
 // use std::process::Command;
 
+use std::{
+	net::Ipv4Addr,
+	time::{Duration, Instant},
+};
+
 use tokio::process::Command;
 
 pub struct Interface {
@@ -29,6 +34,32 @@ impl Interface {
 		}
 	}
 
+	/// Create a macvlan interface on top of `parent` with its own MAC address
+	pub async fn create_macvlan(name: &str, parent: &str, mac: &str) -> Result<Interface, String> {
+		let output = Command::new("ip")
+			.arg("link")
+			.arg("add")
+			.arg("link")
+			.arg(parent)
+			.arg("name")
+			.arg(name)
+			.arg("address")
+			.arg(mac)
+			.arg("type")
+			.arg("macvlan")
+			.arg("mode")
+			.arg("bridge")
+			.output()
+			.await
+			.expect("Failed to execute command");
+		if !output.status.success() {
+			return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+		}
+		Ok(Interface {
+			name: name.to_string(),
+		})
+	}
+
 	/// Check if the interface exists
 	pub async fn exists(&self) -> bool {
 		let output = Command::new("ip")
@@ -41,9 +72,25 @@ impl Interface {
 		output.status.success()
 	}
 
+	/// Wait for the interface to exist, checking once per second.
+	/// Returns false if it still does not exist after the timeout
+	pub async fn wait_until_exists(&self, timeout: Duration) -> bool {
+		let deadline = Instant::now() + timeout;
+		loop {
+			if self.exists().await {
+				return true;
+			}
+			if Instant::now() >= deadline {
+				return false;
+			}
+			println!("[{}] Waiting for interface to appear", self.name);
+			tokio::time::sleep(Duration::from_secs(1)).await;
+		}
+	}
+
 	/// Rename the interface.
 	/// Must be done before bringing the interface up, otherwise it will fail
-	pub async fn rename(&mut self, new_name: &str) {
+	pub async fn rename(&mut self, new_name: &str) -> Result<(), String> {
 		let output = Command::new("ip")
 			.arg("link")
 			.arg("set")
@@ -53,8 +100,11 @@ impl Interface {
 			.output()
 			.await
 			.expect("Failed to execute command");
-		assert!(output.status.success());
+		if !output.status.success() {
+			return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+		}
 		self.name = new_name.to_string();
+		Ok(())
 	}
 
 	/// Bring the interface up
@@ -83,6 +133,43 @@ impl Interface {
 		assert!(output.status.success());
 	}
 
+	/// Set the MTU of the interface. Returns the error of `ip`, as the MTU usually comes from the network
+	pub async fn set_mtu(&self, mtu: u16) -> Result<(), String> {
+		let output = Command::new("ip")
+			.arg("link")
+			.arg("set")
+			.arg("dev")
+			.arg(&self.name)
+			.arg("mtu")
+			.arg(mtu.to_string())
+			.output()
+			.await
+			.expect("Failed to execute command");
+		if !output.status.success() {
+			return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+		}
+		Ok(())
+	}
+
+	/// Get the largest MTU the driver of the interface supports
+	pub async fn get_max_mtu(&self) -> Option<u16> {
+		let output = Command::new("ip")
+			.arg("-d")
+			.arg("link")
+			.arg("show")
+			.arg("dev")
+			.arg(&self.name)
+			.output()
+			.await
+			.expect("Failed to execute command");
+		let output = String::from_utf8(output.stdout).expect("Invalid UTF-8");
+		let parts: Vec<&str> = output.split_whitespace().collect();
+		parts
+			.get(parts.iter().position(|&x| x == "maxmtu")? + 1)?
+			.parse()
+			.ok()
+	}
+
 	/// Check if the interface is up
 	pub async fn is_up(&self) -> bool {
 		let output = Command::new("ip")
@@ -110,12 +197,12 @@ impl Interface {
 		assert!(output.status.success());
 	}
 
-	/// Flush all addresses from the interface.
-	/// Removes all IP addresses from the interface
-	pub async fn flush_addresses(&self) {
+	/// Add an IP address to the interface, or update it if it already exists
+	pub async fn replace_address(&self, address: &String, mask: u8) {
 		let output = Command::new("ip")
 			.arg("addr")
-			.arg("flush")
+			.arg("replace")
+			.arg(format!("{}/{}", address, mask))
 			.arg("dev")
 			.arg(&self.name)
 			.output()
@@ -124,25 +211,62 @@ impl Interface {
 		assert!(output.status.success());
 	}
 
-	/// Get the gateway for the interface.
-	/// This checks the routing table for this interface and extracts the default route
-	pub async fn get_gateway(&self) -> String {
-		let output = Command::new("ip")
-			.arg("route")
-			.arg("show")
+	/// Add or update an address that the kernel removes once its valid lifetime (in seconds) runs out.
+	/// Without `prefix_route`, the rest of the prefix is not treated as on-link
+	pub async fn replace_address_with_lifetime(
+		&self,
+		address: &String,
+		mask: u8,
+		valid: u32,
+		preferred: u32,
+		prefix_route: bool,
+	) -> Result<(), String> {
+		let mut command = Command::new("ip");
+		command
+			.arg("addr")
+			.arg("replace")
+			.arg(format!("{}/{}", address, mask))
+			.arg("dev")
+			.arg(&self.name)
+			.arg("valid_lft")
+			.arg(valid.to_string())
+			.arg("preferred_lft")
+			.arg(preferred.to_string());
+		if !prefix_route {
+			command.arg("noprefixroute");
+		}
+		let output = command.output().await.expect("Failed to execute command");
+		if !output.status.success() {
+			return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+		}
+		Ok(())
+	}
+
+	/// Remove an IP address from the interface, if it is assigned
+	pub async fn delete_address(&self, address: &String, mask: u8) {
+		let _ = Command::new("ip")
+			.arg("addr")
+			.arg("delete")
+			.arg(format!("{}/{}", address, mask))
 			.arg("dev")
 			.arg(&self.name)
 			.output()
 			.await
 			.expect("Failed to execute command");
+	}
 
-		// STDOUT looks like this:
-		// default via <gateway> dev <name> ......
-		// We want to extract the <gateway> part
-		let output = String::from_utf8(output.stdout).expect("Invalid UTF-8");
-		let parts: Vec<&str> = output.split_whitespace().collect();
-		let gateway = parts[2];
-		gateway.to_string()
+	/// Flush all addresses from the interface.
+	/// Removes all IP addresses from the interface
+	pub async fn flush_addresses(&self) {
+		let output = Command::new("ip")
+			.arg("addr")
+			.arg("flush")
+			.arg("dev")
+			.arg(&self.name)
+			.output()
+			.await
+			.expect("Failed to execute command");
+		assert!(output.status.success());
 	}
 
 	/// Set the interface description
@@ -196,6 +320,63 @@ impl Interface {
 		mac.to_string()
 	}
 
+	/// Get the IPv4 addresses of the interface with their prefix length
+	pub async fn get_ipv4_addresses(&self) -> Vec<(Ipv4Addr, u8)> {
+		let output = Command::new("ip")
+			.arg("-4")
+			.arg("-o")
+			.arg("addr")
+			.arg("show")
+			.arg("dev")
+			.arg(&self.name)
+			.output()
+			.await
+			.expect("Failed to execute command");
+		let output = String::from_utf8(output.stdout).expect("Invalid UTF-8");
+		output
+			.lines()
+			.filter_map(|line| {
+				let parts: Vec<&str> = line.split_whitespace().collect();
+				let (address, prefix) = parts
+					.get(parts.iter().position(|&x| x == "inet")? + 1)?
+					.split_once('/')?;
+				Some((address.parse().ok()?, prefix.parse().ok()?))
+			})
+			.collect()
+	}
+
+	/// Get the kernel index of the interface
+	pub fn get_index(&self) -> u32 {
+		std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", self.name))
+			.ok()
+			.and_then(|index| index.trim().parse().ok())
+			.unwrap_or_default()
+	}
+
+	/// Set a per interface IPv6 sysctl, e.g. accept_ra
+	pub fn set_ipv6_sysctl(&self, key: &str, value: &str) {
+		let path = format!("/proc/sys/net/ipv6/conf/{}/{key}", self.name);
+		if let Err(err) = std::fs::write(&path, value) {
+			println!("[{}] Failed to set {path}: {err}", self.name);
+		}
+	}
+
+	/// Get a per interface IPv4 sysctl
+	pub fn get_ipv4_sysctl(&self, key: &str) -> Option<String> {
+		let path = format!("/proc/sys/net/ipv4/conf/{}/{key}", self.name);
+		std::fs::read_to_string(path)
+			.ok()
+			.map(|value| value.trim().to_string())
+	}
+
+	/// Set a per interface IPv4 sysctl, e.g. arp_ignore
+	pub fn set_ipv4_sysctl(&self, key: &str, value: &str) {
+		let path = format!("/proc/sys/net/ipv4/conf/{}/{key}", self.name);
+		if let Err(err) = std::fs::write(&path, value) {
+			println!("[{}] Failed to set {path}: {err}", self.name);
+		}
+	}
+
 	/// Delete the interface
 	pub async fn delete(&self) {
 		let output = Command::new("ip")
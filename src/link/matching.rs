@@ -0,0 +1,193 @@
+use std::{fmt, fs, path::Path};
+
+use crate::{
+	config::LinkMatch,
+	link::{ethtool, interface::Interface},
+};
+
+/// Properties of a link as found in sysfs
+pub struct LinkInfo {
+	pub index: u32,
+	pub name: String,
+	pub mac: Option<String>,
+	pub driver: Option<String>,
+	pub path: Option<String>,
+}
+
+pub enum MatchError {
+	NoCriteria,
+	NotFound,
+	Ambiguous(Vec<String>),
+}
+
+impl fmt::Display for MatchError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MatchError::NoCriteria => write!(f, "rule does not specify any match criteria"),
+			MatchError::NotFound => write!(f, "no link matches this rule"),
+			MatchError::Ambiguous(names) => {
+				write!(f, "rule is ambiguous, it matches {}", names.join(", "))
+			}
+		}
+	}
+}
+
+/// Matches `text` against a pattern where `*` matches any sequence and `?` any single character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let text: Vec<char> = text.chars().collect();
+	let (mut p, mut t) = (0, 0);
+	// Position of the last `*` and the text position it was tried at
+	let mut backtrack: Option<(usize, usize)> = None;
+	while t < text.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+			p += 1;
+			t += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			backtrack = Some((p, t));
+			p += 1;
+		} else if let Some((star, star_t)) = backtrack {
+			p = star + 1;
+			t = star_t + 1;
+			backtrack = Some((star, star_t + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+	fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Lists all links known to the kernel, except loopback
+pub fn list_links() -> Vec<LinkInfo> {
+	let Ok(entries) = fs::read_dir("/sys/class/net") else {
+		return vec![];
+	};
+	let mut links: Vec<LinkInfo> = entries
+		.filter_map(|entry| entry.ok())
+		.map(|entry| {
+			let base = entry.path();
+			let device = base.join("device");
+			LinkInfo {
+				index: read_trimmed(&base.join("ifindex"))
+					.and_then(|index| index.parse().ok())
+					.unwrap_or_default(),
+				name: entry.file_name().to_string_lossy().to_string(),
+				mac: read_trimmed(&base.join("address")).map(|mac| mac.to_lowercase()),
+				driver: fs::read_link(device.join("driver"))
+					.ok()
+					.and_then(|driver| {
+						driver
+							.file_name()
+							.map(|name| name.to_string_lossy().to_string())
+					}),
+				path: fs::canonicalize(&device)
+					.ok()
+					.map(|path| path.to_string_lossy().to_string()),
+			}
+		})
+		.filter(|link| link.name != "lo")
+		.collect();
+	links.sort_by(|a, b| a.name.cmp(&b.name));
+	links
+}
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+	// Allow matching either the whole sysfs path or a single component like the PCI slot
+	glob_match(pattern, path) || path.split('/').any(|part| glob_match(pattern, part))
+}
+
+async fn link_matches(rule: &LinkMatch, link: &LinkInfo) -> bool {
+	if let Some(name) = &rule.name {
+		if !glob_match(name, &link.name) {
+			return false;
+		}
+	}
+	if let Some(mac) = &rule.mac {
+		if link.mac.as_deref() != Some(mac.to_lowercase().as_str()) {
+			return false;
+		}
+	}
+	if let Some(driver) = &rule.driver {
+		if !link
+			.driver
+			.as_ref()
+			.is_some_and(|actual| glob_match(driver, actual))
+		{
+			return false;
+		}
+	}
+	if let Some(path) = &rule.path {
+		if !link
+			.path
+			.as_ref()
+			.is_some_and(|actual| path_matches(path, actual))
+		{
+			return false;
+		}
+	}
+	// Checked last, as it is the only criterion that needs a netlink request
+	if let Some(permanent_mac) = &rule.permanent_mac {
+		let actual = ethtool::get_permanent_mac(&Interface::get_from_name(&link.name)).await;
+		if actual.as_deref() != Some(permanent_mac.to_lowercase().as_str()) {
+			return false;
+		}
+	}
+	true
+}
+
+/// Finds the single link selected by the rule
+pub async fn find_link(rule: &LinkMatch) -> Result<String, MatchError> {
+	if rule.is_empty() {
+		return Err(MatchError::NoCriteria);
+	}
+	let mut matches = vec![];
+	for link in list_links() {
+		if link_matches(rule, &link).await {
+			matches.push(link.name);
+		}
+	}
+	match matches.len() {
+		0 => Err(MatchError::NotFound),
+		1 => Ok(matches.remove(0)),
+		_ => Err(MatchError::Ambiguous(matches)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn glob_wildcards() {
+		assert!(glob_match("eth*", "eth0"));
+		assert!(glob_match("eth*", "eth"));
+		assert!(glob_match("*", ""));
+		assert!(glob_match("e*h*1", "enp3s0eth1"));
+		assert!(glob_match("eth?", "eth1"));
+		assert!(!glob_match("eth?", "eth"));
+		assert!(!glob_match("eth?", "eth10"));
+		assert!(glob_match("enp?s*", "enp3s0"));
+	}
+
+	#[test]
+	fn glob_mismatches() {
+		assert!(glob_match("eth0", "eth0"));
+		assert!(!glob_match("eth0", "eth1"));
+		assert!(!glob_match("eth", "eth0"));
+		assert!(!glob_match("*0", "eth1"));
+		assert!(!glob_match("wlan*", "eth0"));
+	}
+
+	#[test]
+	fn path_components() {
+		let path = "/sys/devices/pci0000:00/0000:00:1c.0/0000:03:00.0/net/eth0";
+		assert!(path_matches("0000:03:00.0", path));
+		assert!(path_matches("*03:00.*", path));
+		assert!(path_matches("/sys/devices/pci0000:00/*", path));
+		assert!(!path_matches("0000:04:00.0", path));
+	}
+}
@@ -1,4 +1,22 @@
+pub mod dad;
+pub mod dhcp;
+pub mod dhcp6;
+pub mod dhcp6c;
 pub mod dhcpc;
 pub mod dhcpd;
+pub mod dhcrelay;
+pub mod dns;
+pub mod dnsd;
+pub mod ethtool;
+pub mod genl;
+pub mod health;
 pub mod interface;
+pub mod matching;
+pub mod monitor;
+pub mod ndp;
+pub mod radv;
+pub mod resolver;
 pub mod routing;
+pub mod slaac;
+pub mod uplink;
+pub mod vrrp;
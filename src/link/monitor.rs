@@ -0,0 +1,75 @@
+use futures::{channel::mpsc::UnboundedReceiver, StreamExt};
+use netlink_packet_core::{NetlinkMessage, NetlinkPayload};
+use netlink_packet_route::{
+	link::{LinkAttribute, LinkMessage},
+	RouteNetlinkMessage,
+};
+use netlink_sys::{AsyncSocket, SocketAddr};
+use rtnetlink::{constants::RTMGRP_LINK, new_connection};
+
+pub enum LinkEvent {
+	/// A link was created, renamed or changed state. Can be sent multiple times for the same link
+	Added {
+		index: u32,
+		name: String,
+	},
+	Removed {
+		index: u32,
+		name: String,
+	},
+}
+
+fn link_name(message: &LinkMessage) -> Option<String> {
+	message
+		.attributes
+		.iter()
+		.find_map(|attribute| match attribute {
+			LinkAttribute::IfName(name) => Some(name.clone()),
+			_ => None,
+		})
+}
+
+pub struct LinkMonitor {
+	messages: UnboundedReceiver<(NetlinkMessage<RouteNetlinkMessage>, SocketAddr)>,
+}
+
+impl LinkMonitor {
+	/// Subscribe to link notifications from the kernel.
+	/// Events are buffered from this point on, so subscribe before looking at the current state
+	pub fn subscribe() -> LinkMonitor {
+		let (mut connection, _, messages) =
+			new_connection().expect("Failed to open netlink connection");
+		connection
+			.socket_mut()
+			.socket_mut()
+			.bind(&SocketAddr::new(0, RTMGRP_LINK))
+			.expect("Failed to subscribe to link notifications");
+		tokio::spawn(connection);
+		LinkMonitor { messages }
+	}
+
+	/// Wait for the next link event
+	pub async fn next(&mut self) -> Option<LinkEvent> {
+		while let Some((message, _)) = self.messages.next().await {
+			let event = match message.payload {
+				NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewLink(link)) => {
+					link_name(&link).map(|name| LinkEvent::Added {
+						index: link.header.index,
+						name,
+					})
+				}
+				NetlinkPayload::InnerMessage(RouteNetlinkMessage::DelLink(link)) => {
+					link_name(&link).map(|name| LinkEvent::Removed {
+						index: link.header.index,
+						name,
+					})
+				}
+				_ => None,
+			};
+			if event.is_some() {
+				return event;
+			}
+		}
+		None
+	}
+}
@@ -0,0 +1,419 @@
+use std::{
+	io,
+	mem::{size_of, MaybeUninit},
+	net::{Ipv6Addr, SocketAddrV6},
+	os::fd::AsRawFd,
+};
+
+use pnet::util::MacAddr;
+use socket2::{Domain, MaybeUninitSlice, MsgHdrMut, Protocol, SockAddr, Socket, Type};
+use tokio::io::unix::AsyncFd;
+
+use crate::link::dhcp6::{decode_domain_list, encode_domain_list, read_ipv6, read_u32};
+
+pub const ROUTER_SOLICITATION: u8 = 133;
+pub const ROUTER_ADVERTISEMENT: u8 = 134;
+
+pub const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+pub const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+/// Neighbor discovery option types (RFC 4861, RFC 8106)
+mod options {
+	pub const SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+	pub const PREFIX_INFORMATION: u8 = 3;
+	pub const MTU: u8 = 5;
+	pub const RDNSS: u8 = 25;
+	pub const DNSSL: u8 = 31;
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Prefix {
+	pub prefix: Ipv6Addr,
+	pub length: u8,
+	/// Hosts may consider addresses in the prefix to be on the link
+	pub on_link: bool,
+	/// Hosts may configure addresses in the prefix themselves (SLAAC)
+	pub autonomous: bool,
+	pub valid: u32,
+	pub preferred: u32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct RouterAdvertisement {
+	pub managed: bool,
+	pub other: bool,
+	/// Seconds hosts may use the sender as default router, 0 means it is not a default router
+	pub router_lifetime: u16,
+	pub mac: Option<MacAddr>,
+	pub mtu: Option<u32>,
+	pub prefixes: Vec<Prefix>,
+	pub dns: Vec<Ipv6Addr>,
+	pub domains: Vec<String>,
+	/// Lifetime of the DNS servers and search domains
+	pub dns_lifetime: u32,
+}
+
+/// Encodes domain names in DNS wire format, padded to a multiple of 8 bytes
+fn encode_domains(domains: &[String]) -> Vec<u8> {
+	let mut data = encode_domain_list(domains);
+	data.resize(data.len().div_ceil(8) * 8, 0);
+	data
+}
+
+fn push_option(data: &mut Vec<u8>, option: u8, body: &[u8]) {
+	// The length is in units of 8 bytes and includes the type and length fields
+	data.push(option);
+	data.push(((body.len() + 2) / 8) as u8);
+	data.extend_from_slice(body);
+}
+
+/// Splits neighbor discovery options into their type and body
+fn decode_options(data: &[u8]) -> Vec<(u8, &[u8])> {
+	let mut options = vec![];
+	let mut i = 0;
+	while i + 2 <= data.len() {
+		let len = data[i + 1] as usize * 8;
+		// A zero length is invalid and would loop forever
+		let Some(option) = data.get(i..i + len).filter(|_| len > 0) else {
+			break;
+		};
+		options.push((option[0], &option[2..]));
+		i += len;
+	}
+	options
+}
+
+impl RouterAdvertisement {
+	/// Encodes the ICMPv6 message, the kernel fills in the checksum
+	pub fn encode(&self) -> Vec<u8> {
+		let mut flags = 0;
+		if self.managed {
+			flags |= 0x80;
+		}
+		if self.other {
+			flags |= 0x40;
+		}
+		// Type, code, checksum, current hop limit (unspecified), flags, router lifetime, reachable time, retransmit timer
+		let mut data = vec![ROUTER_ADVERTISEMENT, 0, 0, 0, 0, flags];
+		data.extend_from_slice(&self.router_lifetime.to_be_bytes());
+		data.extend_from_slice(&[0; 8]);
+
+		if let Some(mac) = self.mac {
+			push_option(&mut data, options::SOURCE_LINK_LAYER_ADDRESS, &mac.octets());
+		}
+		if let Some(mtu) = self.mtu {
+			let mut body = vec![0, 0];
+			body.extend_from_slice(&mtu.to_be_bytes());
+			push_option(&mut data, options::MTU, &body);
+		}
+		for prefix in &self.prefixes {
+			let mut flags = 0;
+			if prefix.on_link {
+				flags |= 0x80;
+			}
+			if prefix.autonomous {
+				flags |= 0x40;
+			}
+			let mut body = vec![prefix.length, flags];
+			body.extend_from_slice(&prefix.valid.to_be_bytes());
+			body.extend_from_slice(&prefix.preferred.to_be_bytes());
+			body.extend_from_slice(&[0; 4]);
+			body.extend_from_slice(&prefix.prefix.octets());
+			push_option(&mut data, options::PREFIX_INFORMATION, &body);
+		}
+		if !self.dns.is_empty() {
+			let mut body = vec![0, 0];
+			body.extend_from_slice(&self.dns_lifetime.to_be_bytes());
+			for dns in &self.dns {
+				body.extend_from_slice(&dns.octets());
+			}
+			push_option(&mut data, options::RDNSS, &body);
+		}
+		if !self.domains.is_empty() {
+			let mut body = vec![0, 0];
+			body.extend_from_slice(&self.dns_lifetime.to_be_bytes());
+			body.extend_from_slice(&encode_domains(&self.domains));
+			push_option(&mut data, options::DNSSL, &body);
+		}
+		data
+	}
+
+	pub fn decode(data: &[u8]) -> Option<RouterAdvertisement> {
+		if *data.first()? != ROUTER_ADVERTISEMENT || data.len() < 16 {
+			return None;
+		}
+		let mut advertisement = RouterAdvertisement {
+			managed: data[5] & 0x80 != 0,
+			other: data[5] & 0x40 != 0,
+			router_lifetime: u16::from_be_bytes([data[6], data[7]]),
+			..Default::default()
+		};
+		for (option, body) in decode_options(&data[16..]) {
+			match option {
+				options::SOURCE_LINK_LAYER_ADDRESS if body.len() >= 6 => {
+					advertisement.mac = Some(MacAddr::new(
+						body[0], body[1], body[2], body[3], body[4], body[5],
+					));
+				}
+				options::MTU => advertisement.mtu = read_u32(body, 2),
+				options::PREFIX_INFORMATION => {
+					if let (
+						Some(&length @ 0..=128),
+						Some(&flags),
+						Some(valid),
+						Some(preferred),
+						Some(prefix),
+					) = (
+						body.first(),
+						body.get(1),
+						read_u32(body, 2),
+						read_u32(body, 6),
+						read_ipv6(body, 14),
+					) {
+						advertisement.prefixes.push(Prefix {
+							prefix,
+							length,
+							on_link: flags & 0x80 != 0,
+							autonomous: flags & 0x40 != 0,
+							valid,
+							preferred,
+						});
+					}
+				}
+				options::RDNSS => {
+					advertisement.dns_lifetime = read_u32(body, 2).unwrap_or_default();
+					advertisement.dns.extend(
+						(0..body.len().saturating_sub(6) / 16)
+							.filter_map(|i| read_ipv6(body, 6 + i * 16)),
+					);
+				}
+				options::DNSSL => {
+					if let Some(domains) = body.get(6..) {
+						advertisement.domains.extend(decode_domain_list(domains));
+					}
+				}
+				_ => {}
+			}
+		}
+		Some(advertisement)
+	}
+}
+
+/// Encodes a router solicitation
+pub fn router_solicitation(mac: Option<MacAddr>) -> Vec<u8> {
+	// Type, code, checksum, reserved
+	let mut data = vec![ROUTER_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+	if let Some(mac) = mac {
+		push_option(&mut data, options::SOURCE_LINK_LAYER_ADDRESS, &mac.octets());
+	}
+	data
+}
+
+/// Opens a raw ICMPv6 socket on the interface that receives messages sent to `group`
+pub fn open_socket(ifname: &str, ifindex: u32, group: Ipv6Addr) -> io::Result<AsyncFd<Socket>> {
+	let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?;
+	socket.bind_device(Some(ifname.as_bytes()))?;
+	// Neighbor discovery messages must have a hop limit of 255 so hosts know they are from the local link
+	socket.set_multicast_hops_v6(255)?;
+	socket.set_unicast_hops_v6(255)?;
+	socket.set_multicast_if_v6(ifindex)?;
+	socket.set_multicast_loop_v6(false)?;
+	set_recv_hop_limit(&socket)?;
+	if group != ALL_NODES {
+		socket.join_multicast_v6(&group, ifindex)?;
+	}
+	socket.set_nonblocking(true)?;
+	AsyncFd::new(socket)
+}
+
+/// Has the kernel report the hop limit of received messages (RFC 3542 6.3)
+fn set_recv_hop_limit(socket: &Socket) -> io::Result<()> {
+	let enabled: libc::c_int = 1;
+	// SAFETY: the option value is a c_int living through the call
+	let result = unsafe {
+		libc::setsockopt(
+			socket.as_raw_fd(),
+			libc::IPPROTO_IPV6,
+			libc::IPV6_RECVHOPLIMIT,
+			(&enabled as *const libc::c_int).cast(),
+			size_of::<libc::c_int>() as libc::socklen_t,
+		)
+	};
+	if result != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+/// Finds the hop limit among the control messages of a received message
+fn hop_limit(control: &[u8]) -> Option<u8> {
+	// SAFETY: CMSG_LEN only computes a length
+	let header_len = unsafe { libc::CMSG_LEN(0) } as usize;
+	let mut offset = 0;
+	while offset + header_len <= control.len() {
+		// SAFETY: the header lies within the buffer, which needs not be aligned for it
+		let header: libc::cmsghdr =
+			unsafe { std::ptr::read_unaligned(control[offset..].as_ptr().cast()) };
+		let len = header.cmsg_len as usize;
+		if len < header_len || offset + len > control.len() {
+			return None;
+		}
+		if header.cmsg_level == libc::IPPROTO_IPV6 && header.cmsg_type == libc::IPV6_HOPLIMIT {
+			let value = control.get(offset + header_len..offset + header_len + 4)?;
+			return u8::try_from(i32::from_ne_bytes(value.try_into().ok()?)).ok();
+		}
+		offset += len.next_multiple_of(size_of::<usize>());
+	}
+	None
+}
+
+/// Waits for the next ICMPv6 message of the given type, returning it with its source address.
+/// Raw ICMPv6 sockets receive the message without the IPv6 header.
+/// Messages that crossed a router, whose hop limit is below 255, are dropped (RFC 4861 6.1)
+pub async fn receive(
+	socket: &AsyncFd<Socket>,
+	message_type: u8,
+) -> io::Result<(Vec<u8>, Ipv6Addr)> {
+	let mut buffer = [MaybeUninit::<u8>::uninit(); 1500];
+	let mut control = [MaybeUninit::<u8>::uninit(); 64];
+	loop {
+		let mut guard = socket.readable().await?;
+		let mut source = SockAddr::from(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0));
+		let Ok(result) = guard.try_io(|socket| {
+			let mut buffers = [MaybeUninitSlice::new(&mut buffer)];
+			let mut message = MsgHdrMut::new()
+				.with_addr(&mut source)
+				.with_buffers(&mut buffers)
+				.with_control(&mut control);
+			let len = socket.get_ref().recvmsg(&mut message, 0)?;
+			Ok((len, message.control_len()))
+		}) else {
+			continue;
+		};
+		let (len, control_len) = result?;
+		// SAFETY: recvmsg initialized the first len bytes and control_len bytes of control
+		let data: Vec<u8> = buffer[..len]
+			.iter()
+			.map(|byte| unsafe { byte.assume_init() })
+			.collect();
+		let control: Vec<u8> = control[..control_len]
+			.iter()
+			.map(|byte| unsafe { byte.assume_init() })
+			.collect();
+		let Some(source) = source.as_socket_ipv6() else {
+			continue;
+		};
+		if data.first() == Some(&message_type) && hop_limit(&control) == Some(255) {
+			return Ok((data, *source.ip()));
+		}
+	}
+}
+
+/// Sends an ICMPv6 message to a link-local multicast group
+pub async fn send(
+	socket: &AsyncFd<Socket>,
+	ifindex: u32,
+	message: &[u8],
+	destination: Ipv6Addr,
+) -> io::Result<usize> {
+	let destination = SockAddr::from(SocketAddrV6::new(destination, 0, 0, ifindex));
+	loop {
+		let mut guard = socket.writable().await?;
+		if let Ok(result) = guard.try_io(|socket| socket.get_ref().send_to(message, &destination)) {
+			return result;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn advertisement() -> RouterAdvertisement {
+		RouterAdvertisement {
+			managed: true,
+			other: false,
+			router_lifetime: 1800,
+			mac: Some(MacAddr::new(2, 0, 0, 0, 0, 1)),
+			mtu: Some(1480),
+			prefixes: vec![Prefix {
+				prefix: "2001:db8:1::".parse().unwrap(),
+				length: 64,
+				on_link: true,
+				autonomous: true,
+				valid: 86400,
+				preferred: 14400,
+			}],
+			dns: vec!["2001:db8:1::1".parse().unwrap()],
+			domains: vec!["lan".to_string(), "example.com".to_string()],
+			dns_lifetime: 600,
+		}
+	}
+
+	#[test]
+	fn round_trip() {
+		let data = advertisement().encode();
+		// Every option is a multiple of 8 bytes
+		assert_eq!((data.len() - 16) % 8, 0);
+		let decoded = RouterAdvertisement::decode(&data).unwrap();
+		let expected = advertisement();
+		assert!(decoded.managed && !decoded.other);
+		assert_eq!(decoded.router_lifetime, expected.router_lifetime);
+		assert_eq!(decoded.mac, expected.mac);
+		assert_eq!(decoded.mtu, expected.mtu);
+		assert_eq!(decoded.prefixes, expected.prefixes);
+		assert_eq!(decoded.dns, expected.dns);
+		assert_eq!(decoded.domains, expected.domains);
+		assert_eq!(decoded.dns_lifetime, expected.dns_lifetime);
+	}
+
+	#[test]
+	fn rejects_malformed_messages() {
+		let data = advertisement().encode();
+		assert!(RouterAdvertisement::decode(&data[..15]).is_none());
+		assert!(RouterAdvertisement::decode(&router_solicitation(None)).is_none());
+		assert!(RouterAdvertisement::decode(&[]).is_none());
+	}
+
+	#[test]
+	fn skips_malformed_options() {
+		let mut advertisement = advertisement();
+		advertisement.prefixes[0].length = 129;
+		let decoded = RouterAdvertisement::decode(&advertisement.encode()).unwrap();
+		assert!(decoded.prefixes.is_empty());
+		assert_eq!(decoded.mtu, Some(1480));
+
+		// A zero length option ends the options instead of looping
+		let mut data = RouterAdvertisement::default().encode();
+		data.extend_from_slice(&[options::MTU, 0, 0, 0, 0, 0, 5, 0]);
+		assert_eq!(RouterAdvertisement::decode(&data).unwrap().mtu, None);
+
+		// An option running past the end is ignored
+		let mut data = RouterAdvertisement::default().encode();
+		data.extend_from_slice(&[options::MTU, 2, 0, 0, 0, 0, 5, 0]);
+		assert_eq!(RouterAdvertisement::decode(&data).unwrap().mtu, None);
+	}
+
+	#[test]
+	fn hop_limit_control_message() {
+		// SAFETY: CMSG_SPACE and CMSG_LEN only compute lengths, a zeroed cmsghdr is valid
+		let (space, len, header_len, mut header) = unsafe {
+			(
+				libc::CMSG_SPACE(size_of::<libc::c_int>() as u32) as usize,
+				libc::CMSG_LEN(size_of::<libc::c_int>() as u32) as usize,
+				libc::CMSG_LEN(0) as usize,
+				std::mem::zeroed::<libc::cmsghdr>(),
+			)
+		};
+		let mut control = vec![0u8; space];
+		header.cmsg_len = len as _;
+		header.cmsg_level = libc::IPPROTO_IPV6;
+		header.cmsg_type = libc::IPV6_HOPLIMIT;
+		// SAFETY: the buffer is large enough for the header
+		unsafe { std::ptr::write_unaligned(control.as_mut_ptr().cast(), header) };
+		control[header_len..header_len + 4].copy_from_slice(&255i32.to_ne_bytes());
+		assert_eq!(hop_limit(&control), Some(255));
+		assert_eq!(hop_limit(&control[..header_len - 1]), None);
+		assert_eq!(hop_limit(&[]), None);
+	}
+}
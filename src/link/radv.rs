@@ -0,0 +1,260 @@
+use std::{
+	collections::BTreeMap,
+	net::Ipv6Addr,
+	str::FromStr,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use pnet::util::MacAddr;
+use rand::{thread_rng, Rng};
+use socket2::Socket;
+use tokio::{
+	io::unix::AsyncFd,
+	sync::Notify,
+	task::JoinHandle,
+	time::{sleep_until, Instant},
+};
+
+use crate::{
+	config::RouterAdvertisementConfig,
+	link::{
+		dhcp6c,
+		interface::Interface,
+		ndp::{self, Prefix, RouterAdvertisement, ALL_NODES, ALL_ROUTERS, ROUTER_SOLICITATION},
+	},
+};
+
+/// The first few advertisements are sent faster so hosts pick up the router quickly (RFC 4861 6.2.4)
+const MAX_INITIAL_RTR_ADVERTISEMENTS: u32 = 3;
+const MAX_INITIAL_RTR_ADVERT_INTERVAL: Duration = Duration::from_secs(16);
+const MIN_DELAY_BETWEEN_RAS: Duration = Duration::from_secs(3);
+
+/// Parses a prefix like `2001:db8::/64`
+fn parse_prefix(prefix: &str) -> Option<(Ipv6Addr, u8)> {
+	let (address, length) = prefix.split_once('/')?;
+	Some((Ipv6Addr::from_str(address).ok()?, length.parse().ok()?))
+}
+
+/// Running advertisers by interface name, the notify triggers an immediate advertisement
+#[allow(clippy::type_complexity)]
+static ADVERTISERS: Mutex<
+	BTreeMap<String, (JoinHandle<()>, Arc<Notify>, RouterAdvertisementConfig)>,
+> = Mutex::new(BTreeMap::new());
+
+struct Advertiser {
+	ifname: String,
+	ifindex: u32,
+	socket: AsyncFd<Socket>,
+	config: RouterAdvertisementConfig,
+	mac: Option<MacAddr>,
+	/// Delegated prefixes that were advertised before, to deprecate them when the delegated prefix changes
+	delegated: Vec<Prefix>,
+	/// Prefixes that are no longer valid, with the number of advertisements left to announce that in
+	deprecated: Vec<(Prefix, u32)>,
+}
+
+impl Advertiser {
+	fn advertisement(&mut self, router_lifetime: u16) -> RouterAdvertisement {
+		let mut prefixes: Vec<Prefix> = self
+			.config
+			.prefixes
+			.iter()
+			.filter_map(|prefix| {
+				let parsed = parse_prefix(prefix);
+				if parsed.is_none() {
+					println!("[{}] Invalid prefix to advertise: {prefix}", self.ifname);
+				}
+				parsed
+			})
+			.map(|(prefix, length)| Prefix {
+				prefix,
+				length,
+				on_link: true,
+				autonomous: self.config.autonomous,
+				valid: self.config.valid_lifetime,
+				preferred: self.config.preferred_lifetime,
+			})
+			.collect();
+
+		if self.config.delegated {
+			let delegated: Vec<Prefix> = dhcp6c::get_subnet(&self.ifname)
+				.map(|subnet| Prefix {
+					prefix: subnet.prefix,
+					length: subnet.length,
+					on_link: true,
+					autonomous: self.config.autonomous,
+					valid: subnet.valid.min(self.config.valid_lifetime),
+					preferred: subnet.preferred.min(self.config.preferred_lifetime),
+				})
+				.into_iter()
+				.collect();
+			for previous in &self.delegated {
+				if !delegated
+					.iter()
+					.any(|prefix| prefix.prefix == previous.prefix)
+				{
+					self.deprecated
+						.retain(|(prefix, _)| prefix.prefix != previous.prefix);
+					self.deprecated.push((
+						Prefix {
+							valid: 0,
+							preferred: 0,
+							..previous.clone()
+						},
+						MAX_INITIAL_RTR_ADVERTISEMENTS,
+					));
+				}
+			}
+			self.deprecated
+				.retain(|(deprecated, _)| !delegated.iter().any(|p| p.prefix == deprecated.prefix));
+			self.delegated = delegated.clone();
+			prefixes.extend(delegated);
+		}
+
+		for (prefix, remaining) in &mut self.deprecated {
+			prefixes.push(prefix.clone());
+			*remaining -= 1;
+		}
+		self.deprecated.retain(|(_, remaining)| *remaining > 0);
+
+		RouterAdvertisement {
+			managed: self.config.managed,
+			other: self.config.other,
+			router_lifetime,
+			mac: self.mac,
+			mtu: self.config.mtu,
+			prefixes,
+			dns: self
+				.config
+				.dns
+				.iter()
+				.filter_map(|dns| Ipv6Addr::from_str(dns).ok())
+				.collect(),
+			domains: self.config.domains.clone(),
+			// Recommended to be at least three times the maximum advertisement interval (RFC 8106 5.1)
+			dns_lifetime: (self.config.interval * 3).min(u32::MAX as u64) as u32,
+		}
+	}
+
+	async fn send(&mut self, router_lifetime: u16) -> bool {
+		let message = self.advertisement(router_lifetime).encode();
+		let result = ndp::send(&self.socket, self.ifindex, &message, ALL_NODES).await;
+		if let Err(err) = &result {
+			// Usually the link-local address is not ready yet
+			println!(
+				"[{}] Failed to send router advertisement: {err}",
+				self.ifname
+			);
+		}
+		result.is_ok()
+	}
+
+	async fn run(mut self, notify: Arc<Notify>) {
+		let interval = Duration::from_secs(self.config.interval.max(4));
+		let mut sent = 0;
+		let mut last_sent = Instant::now() - MIN_DELAY_BETWEEN_RAS;
+		let mut next = Instant::now();
+		loop {
+			let solicited = tokio::select! {
+				_ = sleep_until(next) => false,
+				_ = notify.notified() => false,
+				result = ndp::receive(&self.socket, ROUTER_SOLICITATION) => {
+					if let Err(err) = result {
+						println!("[{}] Failed to receive router solicitation: {err}", self.ifname);
+						return;
+					}
+					true
+				}
+			};
+			// Answers to solicitations are multicast, but not more often than every few seconds
+			if solicited && last_sent.elapsed() < MIN_DELAY_BETWEEN_RAS {
+				next = next.min(last_sent + MIN_DELAY_BETWEEN_RAS);
+				continue;
+			}
+
+			if !self.send(self.config.router_lifetime).await {
+				next = Instant::now() + Duration::from_secs(1);
+				continue;
+			}
+			sent += 1;
+			last_sent = Instant::now();
+
+			// Unsolicited advertisements are sent at a random time between a third of the interval and the interval
+			let mut delay = interval.mul_f64(thread_rng().gen_range(1.0 / 3.0..=1.0));
+			if sent < MAX_INITIAL_RTR_ADVERTISEMENTS {
+				delay = delay.min(MAX_INITIAL_RTR_ADVERT_INTERVAL);
+			}
+			next = last_sent + delay;
+		}
+	}
+}
+
+/// Starts sending router advertisements on the interface and answering router solicitations
+pub async fn start(interface: &Interface, config: &RouterAdvertisementConfig) {
+	stop(interface).await;
+	let ifindex = interface.get_index();
+	let socket = match ndp::open_socket(&interface.name, ifindex, ALL_ROUTERS) {
+		Ok(socket) => socket,
+		Err(err) => {
+			println!(
+				"[{}] Could not start router advertisements: {err}",
+				interface.name
+			);
+			return;
+		}
+	};
+	let advertiser = Advertiser {
+		ifname: interface.name.clone(),
+		ifindex,
+		socket,
+		config: config.clone(),
+		mac: MacAddr::from_str(&interface.get_mac().await).ok(),
+		delegated: vec![],
+		deprecated: vec![],
+	};
+	let notify = Arc::new(Notify::new());
+	let task = tokio::spawn(advertiser.run(notify.clone()));
+	ADVERTISERS
+		.lock()
+		.unwrap()
+		.insert(interface.name.clone(), (task, notify, config.clone()));
+}
+
+/// Advertise right away, e.g. because the delegated prefix changed
+pub fn update(ifname: &str) {
+	if let Some((_, notify, _)) = ADVERTISERS.lock().unwrap().get(ifname) {
+		notify.notify_one();
+	}
+}
+
+/// Stops the advertisements and tells hosts to stop using this router
+pub async fn stop(interface: &Interface) {
+	let advertiser = ADVERTISERS.lock().unwrap().remove(&interface.name);
+	let Some((task, _, config)) = advertiser else {
+		return;
+	};
+	task.abort();
+	if !interface.exists().await {
+		return;
+	}
+	let ifindex = interface.get_index();
+	let Ok(socket) = ndp::open_socket(&interface.name, ifindex, ALL_ROUTERS) else {
+		return;
+	};
+	println!("[{}] Sending final router advertisement", interface.name);
+	let mut advertiser = Advertiser {
+		ifname: interface.name.clone(),
+		ifindex,
+		socket,
+		// The prefixes are about to be removed from the interface, so hosts should stop using them
+		config: RouterAdvertisementConfig {
+			preferred_lifetime: 0,
+			..config
+		},
+		mac: MacAddr::from_str(&interface.get_mac().await).ok(),
+		delegated: vec![],
+		deprecated: vec![],
+	};
+	advertiser.send(0).await;
+}
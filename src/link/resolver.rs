@@ -0,0 +1,210 @@
+use std::{
+	collections::{BTreeMap, HashSet},
+	io::Write as _,
+	net::IpAddr,
+	process::{Command, Stdio},
+	str::FromStr,
+	sync::Mutex,
+};
+
+use crate::{
+	config::{ResolvConfConfig, ResolvConfMode, ResolverConfig},
+	link::{dhcp6c, dhcpc, slaac},
+};
+
+/// The name netd registers its configuration under with resolvconf
+const RESOLVCONF_NAME: &str = "netd";
+
+struct InterfaceResolver {
+	servers: Vec<IpAddr>,
+	search: Vec<String>,
+	learned: bool,
+	/// Whether the DNS servers of the DHCP lease are used, `use_dns` of the DHCP client
+	use_dhcp: bool,
+	priority: u32,
+}
+
+/// Interfaces contributing to the resolver configuration, by name
+static INTERFACES: Mutex<BTreeMap<String, InterfaceResolver>> = Mutex::new(BTreeMap::new());
+struct Output {
+	config: ResolvConfConfig,
+	/// What was written last, empty until netd wrote something
+	written: String,
+	/// The file as it was before netd first wrote it, `None` when there was none
+	original: Option<String>,
+}
+
+/// How the configuration is written and what was written last
+static OUTPUT: Mutex<Option<Output>> = Mutex::new(None);
+
+/// Sets how the resolver configuration is written
+pub fn configure(config: &ResolvConfConfig) {
+	*OUTPUT.lock().unwrap() = Some(Output {
+		config: config.clone(),
+		written: String::new(),
+		original: None,
+	});
+}
+
+/// Adds the static and learned resolver settings of an interface
+pub fn add_interface(ifname: &str, config: &ResolverConfig, use_dhcp: bool) {
+	let servers = config
+		.servers
+		.iter()
+		.filter_map(|server| {
+			let parsed = IpAddr::from_str(server).ok();
+			if parsed.is_none() {
+				println!("[{ifname}] Invalid DNS server: {server}");
+			}
+			parsed
+		})
+		.collect();
+	INTERFACES.lock().unwrap().insert(
+		ifname.to_string(),
+		InterfaceResolver {
+			servers,
+			search: config.search.clone(),
+			learned: config.learned,
+			use_dhcp,
+			priority: config.priority,
+		},
+	);
+	update();
+}
+
+pub fn remove_interface(ifname: &str) {
+	let removed = INTERFACES.lock().unwrap().remove(ifname);
+	if removed.is_some() {
+		update();
+	}
+}
+
+/// The servers and search domains of all interfaces, ordered by priority and without duplicates
+fn collect() -> (Vec<String>, Vec<String>) {
+	let interfaces = INTERFACES.lock().unwrap();
+	let mut ordered: Vec<_> = interfaces.iter().collect();
+	ordered.sort_by_key(|(_, interface)| interface.priority);
+
+	let mut servers = vec![];
+	let mut search = vec![];
+	for (ifname, interface) in ordered {
+		let mut addresses = interface.servers.clone();
+		search.extend(interface.search.iter().cloned());
+		if interface.learned {
+			if let (true, Some(lease)) = (interface.use_dhcp, dhcpc::get_lease(ifname)) {
+				addresses.extend(lease.dns.into_iter().map(IpAddr::from));
+				search.extend(lease.domain);
+			}
+			if let Some(lease) = dhcp6c::get_lease(ifname) {
+				addresses.extend(lease.dns.into_iter().map(IpAddr::from));
+				search.extend(lease.domains);
+			}
+			if let Some(status) = slaac::get_status(ifname) {
+				addresses.extend(status.dns.into_iter().map(IpAddr::from));
+				search.extend(status.domains);
+			}
+		}
+		servers.extend(addresses.into_iter().map(|address| match address {
+			// Link-local servers are only reachable through the interface they were learned on
+			IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 == 0xfe80 => format!("{v6}%{ifname}"),
+			_ => address.to_string(),
+		}));
+	}
+	let mut seen = HashSet::new();
+	servers.retain(|server| seen.insert(server.clone()));
+	seen.clear();
+	search.retain(|domain| seen.insert(domain.clone()));
+	(servers, search)
+}
+
+/// Replaces the file atomically. A symlink, e.g. into /run, is followed instead of being replaced
+fn write_file(path: &str, contents: &str) -> std::io::Result<()> {
+	let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.into());
+	let temp_path = format!("{}.tmp", path.display());
+	std::fs::write(&temp_path, contents).and_then(|_| std::fs::rename(&temp_path, &path))
+}
+
+/// Puts back the file netd found, once there is nothing left to write
+fn restore_file(path: &str, original: &Option<String>) -> std::io::Result<()> {
+	match original {
+		Some(original) => write_file(path, original),
+		None => std::fs::remove_file(std::fs::canonicalize(path).unwrap_or_else(|_| path.into())),
+	}
+}
+
+fn run_resolvconf(contents: &str) -> std::io::Result<()> {
+	let status = if contents.is_empty() {
+		Command::new("resolvconf")
+			.args(["-d", RESOLVCONF_NAME])
+			.status()?
+	} else {
+		let mut child = Command::new("resolvconf")
+			.args(["-a", RESOLVCONF_NAME])
+			.stdin(Stdio::piped())
+			.spawn()?;
+		if let Some(mut stdin) = child.stdin.take() {
+			stdin.write_all(contents.as_bytes())?;
+		}
+		child.wait()?
+	};
+	if !status.success() {
+		return Err(std::io::Error::other(format!(
+			"resolvconf failed with {status}"
+		)));
+	}
+	Ok(())
+}
+
+/// Writes the resolver configuration if it changed.
+/// Called whenever the static configuration or a lease or router advertisement changes
+pub fn update() {
+	let (servers, search) = collect();
+	let mut output = OUTPUT.lock().unwrap();
+	let Some(Output {
+		config,
+		written,
+		original,
+	}) = output.as_mut()
+	else {
+		return;
+	};
+	// Whatever is there is kept until netd learned something to replace it with
+	if config.mode == ResolvConfMode::Disabled || (written.is_empty() && servers.is_empty()) {
+		return;
+	}
+	let mut contents = String::new();
+	if !servers.is_empty() {
+		contents.push_str("# Generated by netd\n");
+		if !search.is_empty() {
+			contents.push_str(&format!("search {}\n", search.join(" ")));
+		}
+		for server in &servers {
+			contents.push_str(&format!("nameserver {server}\n"));
+		}
+		for option in &config.options {
+			contents.push_str(&format!("options {option}\n"));
+		}
+	}
+	if contents == *written {
+		return;
+	}
+	if config.mode != ResolvConfMode::Resolvconf && written.is_empty() {
+		*original = std::fs::read_to_string(&config.path).ok();
+	}
+	let result = match config.mode {
+		ResolvConfMode::Resolvconf => run_resolvconf(&contents),
+		_ if contents.is_empty() => restore_file(&config.path, original),
+		_ => write_file(&config.path, &contents),
+	};
+	match result {
+		Ok(_) if contents.is_empty() => {
+			println!("Restored the original resolver configuration");
+			*written = contents;
+		}
+		Ok(_) => {
+			println!("Updated resolver configuration: {}", servers.join(", "));
+			*written = contents;
+		}
+		Err(err) => println!("Failed to update resolver configuration: {err}"),
+	}
+}
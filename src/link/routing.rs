@@ -12,3 +12,195 @@ pub async fn add_route_via(net: &str, route: &str) {
 		.expect("Failed to execute command");
 	assert!(output.status.success());
 }
+
+/// Add or replace a route via a gateway on a specific device.
+/// Returns the error of `ip`, as the gateway usually comes from the network
+pub async fn replace_route_via(net: &str, route: &str, dev: &str) -> Result<(), String> {
+	let output = Command::new("ip")
+		.arg("route")
+		.arg("replace")
+		.arg(route)
+		.arg("via")
+		.arg(net)
+		.arg("dev")
+		.arg(dev)
+		.output()
+		.await
+		.expect("Failed to execute command");
+	command_result(output)
+}
+
+/// Delete a route via a gateway on a specific device, if it exists
+pub async fn delete_route_via(net: &str, route: &str, dev: &str) {
+	let _ = Command::new("ip")
+		.arg("route")
+		.arg("delete")
+		.arg(route)
+		.arg("via")
+		.arg(net)
+		.arg("dev")
+		.arg(dev)
+		.output()
+		.await
+		.expect("Failed to execute command");
+}
+
+/// Add or replace a route via a gateway on a specific device that the kernel removes after `expires` seconds
+pub async fn replace_route_via_expires(net: &str, route: &str, dev: &str, expires: u32) {
+	let output = Command::new("ip")
+		.arg("route")
+		.arg("replace")
+		.arg(route)
+		.arg("via")
+		.arg(net)
+		.arg("dev")
+		.arg(dev)
+		.arg("expires")
+		.arg(expires.to_string())
+		.output()
+		.await
+		.expect("Failed to execute command");
+	assert!(output.status.success());
+}
+
+/// Add or replace a route that is reachable directly on a device, without a gateway.
+/// Returns the error of `ip`, as the route usually comes from the network
+pub async fn replace_route_dev(route: &str, dev: &str) -> Result<(), String> {
+	let output = Command::new("ip")
+		.arg("route")
+		.arg("replace")
+		.arg(route)
+		.arg("dev")
+		.arg(dev)
+		.output()
+		.await
+		.expect("Failed to execute command");
+	command_result(output)
+}
+
+/// Add or replace a route that is reachable directly on a device that the kernel removes after `expires` seconds
+pub async fn replace_route_dev_expires(route: &str, dev: &str, expires: u32) {
+	let output = Command::new("ip")
+		.arg("route")
+		.arg("replace")
+		.arg(route)
+		.arg("dev")
+		.arg(dev)
+		.arg("expires")
+		.arg(expires.to_string())
+		.output()
+		.await
+		.expect("Failed to execute command");
+	assert!(output.status.success());
+}
+
+/// Delete a route that is reachable directly on a device, if it exists
+pub async fn delete_route_dev(route: &str, dev: &str) {
+	let _ = Command::new("ip")
+		.arg("route")
+		.arg("delete")
+		.arg(route)
+		.arg("dev")
+		.arg(dev)
+		.output()
+		.await
+		.expect("Failed to execute command");
+}
+
+/// Add or replace an unreachable route, so traffic to unused parts of a prefix is not sent back upstream
+pub async fn replace_unreachable_route(route: &str) {
+	let output = Command::new("ip")
+		.arg("route")
+		.arg("replace")
+		.arg("unreachable")
+		.arg(route)
+		.output()
+		.await
+		.expect("Failed to execute command");
+	assert!(output.status.success());
+}
+
+/// Delete an unreachable route, if it exists
+pub async fn delete_unreachable_route(route: &str) {
+	let _ = Command::new("ip")
+		.arg("route")
+		.arg("delete")
+		.arg("unreachable")
+		.arg(route)
+		.output()
+		.await
+		.expect("Failed to execute command");
+}
+
+/// Add or replace a route via a gateway on a specific device with a metric.
+/// Returns the error of `ip` instead of panicking, as the device may not be configured yet
+pub async fn replace_route_via_metric(
+	net: &str,
+	route: &str,
+	dev: &str,
+	metric: u32,
+) -> Result<(), String> {
+	let output = Command::new("ip")
+		.arg("route")
+		.arg("replace")
+		.arg(route)
+		.arg("via")
+		.arg(net)
+		.arg("dev")
+		.arg(dev)
+		.arg("metric")
+		.arg(metric.to_string())
+		.output()
+		.await
+		.expect("Failed to execute command");
+	command_result(output)
+}
+
+/// Add or replace a route spreading traffic over several gateways, given with their devices and weights.
+/// Returns the error of `ip` instead of panicking, as the devices may not be configured yet
+pub async fn replace_multipath_route(
+	route: &str,
+	nexthops: &[(String, String, u16)],
+	metric: u32,
+) -> Result<(), String> {
+	let mut command = Command::new("ip");
+	command
+		.arg("route")
+		.arg("replace")
+		.arg(route)
+		.arg("metric")
+		.arg(metric.to_string());
+	for (net, dev, weight) in nexthops {
+		command
+			.arg("nexthop")
+			.arg("via")
+			.arg(net)
+			.arg("dev")
+			.arg(dev)
+			.arg("weight")
+			.arg(weight.to_string());
+	}
+	let output = command.output().await.expect("Failed to execute command");
+	command_result(output)
+}
+
+/// Delete the route with a metric, if it exists
+pub async fn delete_route_metric(route: &str, metric: u32) {
+	let _ = Command::new("ip")
+		.arg("route")
+		.arg("delete")
+		.arg(route)
+		.arg("metric")
+		.arg(metric.to_string())
+		.output()
+		.await
+		.expect("Failed to execute command");
+}
+
+fn command_result(output: std::process::Output) -> Result<(), String> {
+	if output.status.success() {
+		Ok(())
+	} else {
+		Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+	}
+}
@@ -0,0 +1,384 @@
+use std::{
+	collections::BTreeMap,
+	net::Ipv6Addr,
+	str::FromStr,
+	sync::Mutex,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pnet::util::MacAddr;
+use serde::Serialize;
+use socket2::Socket;
+use tokio::{
+	io::unix::AsyncFd,
+	task::JoinHandle,
+	time::{sleep_until, Instant},
+};
+
+use crate::{
+	config::{SlaacConfig, SlaacMode},
+	link::{
+		interface::Interface,
+		ndp::{self, RouterAdvertisement, ALL_NODES, ALL_ROUTERS, ROUTER_ADVERTISEMENT},
+		resolver, routing,
+	},
+};
+
+/// Solicit advertisements a few times when starting, instead of waiting for the next unsolicited one (RFC 4861 6.3.7)
+const MAX_RTR_SOLICITATIONS: u32 = 3;
+const RTR_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Valid lifetimes below this are only accepted if they extend the lifetime (RFC 4862 5.5.3)
+const TWO_HOURS: u32 = 7200;
+
+#[derive(Serialize, Clone)]
+pub struct LearnedRouter {
+	pub address: Ipv6Addr,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mac: Option<String>,
+	pub lifetime: u16,
+	/// When the last advertisement was received, in seconds since the epoch
+	pub received: u64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LearnedPrefix {
+	pub prefix: Ipv6Addr,
+	pub length: u8,
+	pub autonomous: bool,
+	pub valid: u32,
+	pub preferred: u32,
+	pub received: u64,
+	/// The address netd configured in the prefix
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub address: Option<Ipv6Addr>,
+}
+
+impl LearnedPrefix {
+	/// Seconds until the prefix is no longer valid
+	fn remaining(&self, now: u64) -> u32 {
+		(self.received + self.valid as u64).saturating_sub(now) as u32
+	}
+}
+
+/// What was learned from router advertisements on an interface
+#[derive(Serialize, Clone, Default)]
+pub struct SlaacStatus {
+	pub routers: Vec<LearnedRouter>,
+	pub prefixes: Vec<LearnedPrefix>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub dns: Vec<Ipv6Addr>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub domains: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mtu: Option<u32>,
+	pub managed: bool,
+	pub other: bool,
+}
+
+impl SlaacStatus {
+	/// Drops routers and prefixes whose lifetime ran out
+	fn prune(&mut self, now: u64) {
+		self.routers
+			.retain(|router| router.received + router.lifetime as u64 > now);
+		self.prefixes.retain(|prefix| prefix.remaining(now) > 0);
+	}
+}
+
+/// Listeners by interface name
+static LISTENERS: Mutex<BTreeMap<String, (JoinHandle<()>, SlaacConfig)>> =
+	Mutex::new(BTreeMap::new());
+static LEARNED: Mutex<BTreeMap<String, SlaacStatus>> = Mutex::new(BTreeMap::new());
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs()
+}
+
+/// Get what was learned from router advertisements on the interface
+pub fn get_status(ifname: &str) -> Option<SlaacStatus> {
+	let mut learned = LEARNED.lock().unwrap();
+	let status = learned.get_mut(ifname)?;
+	status.prune(now());
+	Some(status.clone())
+}
+
+/// The modified EUI-64 interface identifier of a MAC address (RFC 4291 appendix A)
+fn eui64_address(prefix: Ipv6Addr, mac: MacAddr) -> Ipv6Addr {
+	let mut octets = prefix.octets();
+	octets[8..].copy_from_slice(&[mac.0 ^ 0x02, mac.1, mac.2, 0xff, 0xfe, mac.3, mac.4, mac.5]);
+	Ipv6Addr::from(octets)
+}
+
+struct Listener {
+	ifname: String,
+	ifindex: u32,
+	socket: AsyncFd<Socket>,
+	config: SlaacConfig,
+	mac: Option<MacAddr>,
+}
+
+impl Listener {
+	/// Records the advertisement and, in netd mode, configures addresses and routes from it
+	async fn process(&self, advertisement: RouterAdvertisement, router: Ipv6Addr) {
+		let now = now();
+		let previous = get_status(&self.ifname).unwrap_or_default();
+		let mut status = previous.clone();
+
+		status.routers.retain(|learned| learned.address != router);
+		if advertisement.router_lifetime > 0 {
+			status.routers.push(LearnedRouter {
+				address: router,
+				mac: advertisement.mac.map(|mac| mac.to_string()),
+				lifetime: advertisement.router_lifetime,
+				received: now,
+			});
+		}
+		if !advertisement.dns.is_empty() {
+			status.dns = advertisement.dns.clone();
+		}
+		if !advertisement.domains.is_empty() {
+			status.domains = advertisement.domains.clone();
+		}
+		status.mtu = advertisement.mtu.or(status.mtu);
+		status.managed = advertisement.managed;
+		status.other = advertisement.other;
+
+		for prefix in &advertisement.prefixes {
+			if prefix.preferred > prefix.valid || prefix.prefix.segments()[0] & 0xffc0 == 0xfe80 {
+				continue;
+			}
+			let existing = status.prefixes.iter().position(|learned| {
+				learned.prefix == prefix.prefix && learned.length == prefix.length
+			});
+			let mut valid = prefix.valid;
+			if let Some(existing) = existing {
+				// Keeps a spoofed advertisement from invalidating addresses right away
+				let remaining = status.prefixes[existing].remaining(now);
+				if valid <= TWO_HOURS && valid <= remaining {
+					valid = remaining.min(TWO_HOURS);
+				}
+				status.prefixes.remove(existing);
+			}
+			let mut learned = LearnedPrefix {
+				prefix: prefix.prefix,
+				length: prefix.length,
+				autonomous: prefix.autonomous,
+				valid,
+				preferred: prefix.preferred.min(valid),
+				received: now,
+				address: None,
+			};
+
+			if self.config.mode == SlaacMode::Netd {
+				let interface = Interface::get_from_name(&self.ifname);
+				let route = format!("{}/{}", prefix.prefix, prefix.length);
+				match (
+					self.config.autoconf && prefix.autonomous && prefix.length == 64,
+					self.mac,
+				) {
+					(true, Some(mac)) => {
+						let address = eui64_address(prefix.prefix, mac);
+						if valid == 0 {
+							interface.delete_address(&address.to_string(), 64).await;
+						} else {
+							match interface
+								.replace_address_with_lifetime(
+									&address.to_string(),
+									64,
+									valid,
+									learned.preferred,
+									// Hosts of a prefix that is not on-link are reached through the router
+									prefix.on_link,
+								)
+								.await
+							{
+								Ok(()) => learned.address = Some(address),
+								Err(err) => {
+									println!("[{}] Could not assign {address}: {err}", self.ifname)
+								}
+							}
+						}
+					}
+					_ if prefix.on_link => {
+						if valid == 0 {
+							routing::delete_route_dev(&route, &self.ifname).await;
+						} else {
+							routing::replace_route_dev_expires(&route, &self.ifname, valid).await;
+						}
+					}
+					_ => {}
+				}
+			}
+			if valid > 0 {
+				status.prefixes.push(learned);
+			}
+		}
+
+		if self.config.mode == SlaacMode::Netd {
+			let router_address = router.to_string();
+			if self.config.default_route {
+				if advertisement.router_lifetime > 0 {
+					routing::replace_route_via_expires(
+						&router_address,
+						"default",
+						&self.ifname,
+						advertisement.router_lifetime as u32,
+					)
+					.await;
+				} else if previous
+					.routers
+					.iter()
+					.any(|learned| learned.address == router)
+				{
+					println!(
+						"[{}] Router {router} is no longer a default router",
+						self.ifname
+					);
+					routing::delete_route_via(&router_address, "default", &self.ifname).await;
+				}
+			}
+			if let (true, Some(mtu)) = (self.config.use_mtu, advertisement.mtu) {
+				if previous.mtu != Some(mtu) && (1280..=u16::MAX as u32).contains(&mtu) {
+					println!(
+						"[{}] Setting MTU {mtu} from router advertisement",
+						self.ifname
+					);
+					if let Err(error) = Interface::get_from_name(&self.ifname)
+						.set_mtu(mtu as u16)
+						.await
+					{
+						println!("[{}] Could not set MTU {mtu}: {error}", self.ifname);
+					}
+				}
+			}
+		}
+
+		let resolver_changed = status.dns != previous.dns || status.domains != previous.domains;
+		LEARNED.lock().unwrap().insert(self.ifname.clone(), status);
+		if resolver_changed {
+			resolver::update();
+		}
+	}
+
+	async fn run(self) {
+		let mut solicitations = 0;
+		let mut next_solicitation = Instant::now();
+		loop {
+			tokio::select! {
+				_ = sleep_until(next_solicitation), if solicitations < MAX_RTR_SOLICITATIONS => {
+					let solicitation = ndp::router_solicitation(self.mac);
+					match ndp::send(&self.socket, self.ifindex, &solicitation, ALL_ROUTERS).await {
+						Ok(_) => {
+							solicitations += 1;
+							next_solicitation = Instant::now() + RTR_SOLICITATION_INTERVAL;
+						}
+						// Usually the link-local address is not ready yet
+						Err(_) => next_solicitation = Instant::now() + Duration::from_secs(1),
+					}
+				}
+				result = ndp::receive(&self.socket, ROUTER_ADVERTISEMENT) => {
+					let (data, source) = match result {
+						Ok(received) => received,
+						Err(err) => {
+							println!("[{}] Failed to receive router advertisement: {err}", self.ifname);
+							return;
+						}
+					};
+					// Advertisements always come from the router's link-local address
+					if source.segments()[0] & 0xffc0 != 0xfe80 {
+						continue;
+					}
+					let Some(advertisement) = RouterAdvertisement::decode(&data) else {
+						continue;
+					};
+					if solicitations < MAX_RTR_SOLICITATIONS {
+						println!("[{}] Received router advertisement from {source}", self.ifname);
+						solicitations = MAX_RTR_SOLICITATIONS;
+					}
+					self.process(advertisement, source).await;
+				}
+			}
+		}
+	}
+}
+
+/// Sets up how the kernel handles router advertisements and starts listening for them
+pub async fn start(interface: &Interface, config: &SlaacConfig) {
+	stop(interface).await;
+	let on_off = |value: bool| if value { "1" } else { "0" };
+	match config.mode {
+		SlaacMode::Kernel => {
+			// 2 accepts advertisements even when forwarding is enabled, which it is on a router
+			interface.set_ipv6_sysctl("accept_ra", "2");
+			interface.set_ipv6_sysctl("autoconf", on_off(config.autoconf));
+			interface.set_ipv6_sysctl("accept_ra_defrtr", on_off(config.default_route));
+			interface.set_ipv6_sysctl("accept_ra_mtu", on_off(config.use_mtu));
+		}
+		SlaacMode::Netd | SlaacMode::Disabled => {
+			interface.set_ipv6_sysctl("accept_ra", "0");
+			interface.set_ipv6_sysctl("autoconf", "0");
+		}
+	}
+	if config.mode == SlaacMode::Disabled {
+		return;
+	}
+
+	let ifindex = interface.get_index();
+	let socket = match ndp::open_socket(&interface.name, ifindex, ALL_NODES) {
+		Ok(socket) => socket,
+		Err(err) => {
+			println!(
+				"[{}] Could not listen for router advertisements: {err}",
+				interface.name
+			);
+			return;
+		}
+	};
+	let listener = Listener {
+		ifname: interface.name.clone(),
+		ifindex,
+		socket,
+		config: config.clone(),
+		mac: MacAddr::from_str(&interface.get_mac().await).ok(),
+	};
+	let task = tokio::spawn(listener.run());
+	LISTENERS
+		.lock()
+		.unwrap()
+		.insert(interface.name.clone(), (task, config.clone()));
+}
+
+/// Stops listening for router advertisements, removing what netd configured from them
+pub async fn stop(interface: &Interface) {
+	let listener = LISTENERS.lock().unwrap().remove(&interface.name);
+	let learned = LEARNED.lock().unwrap().remove(&interface.name);
+	let Some((task, config)) = listener else {
+		return;
+	};
+	task.abort();
+	resolver::update();
+	let (SlaacMode::Netd, Some(learned)) = (config.mode, learned) else {
+		return;
+	};
+	if !interface.exists().await {
+		return;
+	}
+	for prefix in &learned.prefixes {
+		match prefix.address {
+			Some(address) => interface.delete_address(&address.to_string(), 64).await,
+			None => {
+				routing::delete_route_dev(
+					&format!("{}/{}", prefix.prefix, prefix.length),
+					&interface.name,
+				)
+				.await
+			}
+		}
+	}
+	for router in &learned.routers {
+		routing::delete_route_via(&router.address.to_string(), "default", &interface.name).await;
+	}
+}
@@ -0,0 +1,345 @@
+use std::{collections::BTreeMap, net::Ipv4Addr, str::FromStr, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+use tokio::{
+	sync::mpsc::{self, UnboundedSender},
+	task::JoinHandle,
+};
+
+use crate::{
+	config::{UplinkGroupConfig, UplinkMemberConfig},
+	hooks::run_hook_with_env,
+	link::{dhcpc, health::HealthCheck, interface::Interface, routing},
+};
+
+#[derive(Serialize, Clone)]
+pub struct UplinkStatus {
+	pub group: String,
+	/// Whether the health check succeeds
+	pub up: bool,
+	/// Whether the default route of the group goes through this uplink
+	pub active: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub gateway: Option<Ipv4Addr>,
+}
+
+struct Group {
+	tasks: Vec<JoinHandle<()>>,
+	/// Metrics of the default routes of the group and its members
+	metrics: Vec<u32>,
+}
+
+/// How long to wait before adding a route the kernel refused again
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Running groups, by name
+static GROUPS: Mutex<BTreeMap<String, Group>> = Mutex::new(BTreeMap::new());
+/// The state of every member, by interface name
+static STATUS: Mutex<BTreeMap<String, UplinkStatus>> = Mutex::new(BTreeMap::new());
+/// Static gateways of member interfaces, which leave the default route to their group
+static GATEWAYS: Mutex<BTreeMap<String, Ipv4Addr>> = Mutex::new(BTreeMap::new());
+
+pub fn get_status(ifname: &str) -> Option<UplinkStatus> {
+	STATUS.lock().unwrap().get(ifname).cloned()
+}
+
+/// Whether the interface is an uplink, whose default route is managed by its group
+pub fn is_member(ifname: &str) -> bool {
+	STATUS.lock().unwrap().contains_key(ifname)
+}
+
+/// Hands the static gateway of a member interface to its group instead of adding a default route
+pub fn set_gateway(ifname: &str, gateway: &str) {
+	match Ipv4Addr::from_str(gateway) {
+		Ok(gateway) => {
+			GATEWAYS.lock().unwrap().insert(ifname.to_string(), gateway);
+		}
+		Err(_) => println!("[{ifname}] Invalid gateway: {gateway}"),
+	}
+}
+
+/// Starts checking the members of every group. Called before the interfaces are configured,
+/// so they know to leave their default routes to the group
+pub fn start(groups: &BTreeMap<String, UplinkGroupConfig>) {
+	for (name, config) in groups {
+		let (events, mut received) = mpsc::unbounded_channel();
+		let mut tasks = vec![];
+		let mut metrics = vec![config.metric];
+		for (index, member) in config.members.iter().enumerate() {
+			let metric = config.metric + 1 + index as u32;
+			metrics.push(metric);
+			STATUS.lock().unwrap().insert(
+				member.interface.clone(),
+				UplinkStatus {
+					group: name.clone(),
+					up: false,
+					active: false,
+					gateway: None,
+				},
+			);
+			tasks.push(tokio::spawn(watch_member(
+				index,
+				member.clone(),
+				metric,
+				events.clone(),
+			)));
+		}
+		let name_ = name.clone();
+		let config = config.clone();
+		tasks.push(tokio::spawn(async move {
+			let mut gateways = vec![None; config.members.len()];
+			let mut active = vec![];
+			let mut failed = false;
+			loop {
+				// A route the kernel refused is tried again, even without news from the members
+				let event = if failed {
+					tokio::time::timeout(RETRY_INTERVAL, received.recv())
+						.await
+						.ok()
+				} else {
+					Some(received.recv().await)
+				};
+				match event {
+					Some(None) => break,
+					Some(Some((index, gateway))) => {
+						gateways[index] = gateway;
+						member_changed(&name_, &config.members[index], gateway);
+					}
+					None => {}
+				}
+				match update_route(&name_, &config, &gateways, &active).await {
+					Some(selected) => {
+						active = selected;
+						failed = false;
+					}
+					None => failed = true,
+				}
+			}
+		}));
+		println!("Monitoring uplink group {name}");
+		GROUPS
+			.lock()
+			.unwrap()
+			.insert(name.clone(), Group { tasks, metrics });
+	}
+}
+
+/// Stops checking the uplinks and removes their default routes
+pub async fn stop() {
+	let groups = std::mem::take(&mut *GROUPS.lock().unwrap());
+	for group in groups.into_values() {
+		for task in group.tasks {
+			task.abort();
+		}
+		for metric in group.metrics {
+			routing::delete_route_metric("default", metric).await;
+		}
+	}
+	STATUS.lock().unwrap().clear();
+	GATEWAYS.lock().unwrap().clear();
+}
+
+/// The configured gateway, the static one of the interface or the router of the DHCP lease
+fn member_gateway(member: &UplinkMemberConfig) -> Option<Ipv4Addr> {
+	if let Some(gateway) = &member.gateway {
+		return Ipv4Addr::from_str(gateway).ok();
+	}
+	if let Some(gateway) = GATEWAYS.lock().unwrap().get(&member.interface) {
+		return Some(*gateway);
+	}
+	dhcpc::get_lease(&member.interface).and_then(|lease| lease.router)
+}
+
+/// Checks a member and reports its gateway while it is up, or `None` once it goes down
+async fn watch_member(
+	index: usize,
+	member: UplinkMemberConfig,
+	metric: u32,
+	events: UnboundedSender<(usize, Option<Ipv4Addr>)>,
+) {
+	let ifname = member.interface.clone();
+	let interface = Interface::get_from_name(&ifname);
+	loop {
+		let Some(gateway) = member_gateway(&member) else {
+			tokio::time::sleep(Duration::from_secs(1)).await;
+			continue;
+		};
+		// Lets the health check reach targets beyond the gateway, even while the group does not use it
+		if let Err(error) =
+			routing::replace_route_via_metric(&gateway.to_string(), "default", &ifname, metric)
+				.await
+		{
+			println!("[{ifname}] Could not add the uplink route via {gateway}: {error}");
+			tokio::time::sleep(RETRY_INTERVAL).await;
+			continue;
+		}
+		let mut check =
+			HealthCheck::new(&interface, &member.health_check, &[gateway.into()], false);
+		while member_gateway(&member) == Some(gateway) {
+			if check.check().await {
+				let _ = events.send((index, check.is_up().then_some(gateway)));
+			}
+			tokio::time::sleep(check.interval()).await;
+		}
+		println!("[{ifname}] Uplink gateway changed");
+		routing::delete_route_metric("default", metric).await;
+		if check.is_up() {
+			let _ = events.send((index, None));
+		}
+	}
+}
+
+fn member_changed(group: &str, member: &UplinkMemberConfig, gateway: Option<Ipv4Addr>) {
+	let ifname = &member.interface;
+	if let Some(status) = STATUS.lock().unwrap().get_mut(ifname) {
+		status.up = gateway.is_some();
+		status.gateway = gateway;
+	}
+	let state = if gateway.is_some() { "up" } else { "down" };
+	println!("[{ifname}] Uplink is {state}");
+	run_hook_with_env(
+		format!("uplink-{state}.{ifname}"),
+		&[
+			("UPLINK_GROUP".to_string(), group.to_string()),
+			("UPLINK_INTERFACE".to_string(), ifname.clone()),
+			(
+				"UPLINK_GATEWAY".to_string(),
+				gateway
+					.map(|gateway| gateway.to_string())
+					.unwrap_or_default(),
+			),
+		],
+	);
+}
+
+/// The members that are up with the lowest priority, `gateways` has the gateway of every member that is up
+fn select_members(config: &UplinkGroupConfig, gateways: &[Option<Ipv4Addr>]) -> Vec<usize> {
+	let up: Vec<usize> = (0..gateways.len())
+		.filter(|index| gateways[*index].is_some())
+		.collect();
+	let best = up.iter().map(|index| config.members[*index].priority).min();
+	up.into_iter()
+		.filter(|index| Some(config.members[*index].priority) == best)
+		.collect()
+}
+
+/// The gateway, interface and weight of each selected member
+fn nexthops(
+	config: &UplinkGroupConfig,
+	gateways: &[Option<Ipv4Addr>],
+	selected: &[usize],
+) -> Vec<(String, String, u16)> {
+	selected
+		.iter()
+		.filter_map(|index| {
+			let member = &config.members[*index];
+			Some((
+				gateways[*index]?.to_string(),
+				member.interface.clone(),
+				member.weight.clamp(1, 256),
+			))
+		})
+		.collect()
+}
+
+/// Points the default route of the group at the members with the lowest priority that are up.
+/// Returns the members now carrying traffic, or `None` when the route could not be replaced
+async fn update_route(
+	group: &str,
+	config: &UplinkGroupConfig,
+	gateways: &[Option<Ipv4Addr>],
+	active: &[usize],
+) -> Option<Vec<usize>> {
+	let selected = select_members(config, gateways);
+	let nexthops = nexthops(config, gateways, &selected);
+
+	// Replacing the route also picks up gateways that changed
+	if nexthops.is_empty() {
+		routing::delete_route_metric("default", config.metric).await;
+	} else if let Err(error) =
+		routing::replace_multipath_route("default", &nexthops, config.metric).await
+	{
+		println!("Could not replace the default route of uplink group {group}: {error}");
+		return None;
+	}
+	if selected == active {
+		return Some(selected);
+	}
+
+	let interfaces: Vec<String> = nexthops.into_iter().map(|(_, dev, _)| dev).collect();
+	{
+		let mut status = STATUS.lock().unwrap();
+		for (index, member) in config.members.iter().enumerate() {
+			if let Some(status) = status.get_mut(&member.interface) {
+				status.active = selected.contains(&index);
+			}
+		}
+	}
+	if interfaces.is_empty() {
+		println!("Uplink group {group} has no uplink left");
+	} else {
+		println!("Uplink group {group} uses {}", interfaces.join(", "));
+	}
+	run_hook_with_env(
+		format!("uplink-change.{group}"),
+		&[
+			("UPLINK_GROUP".to_string(), group.to_string()),
+			("UPLINK_ACTIVE".to_string(), interfaces.join(" ")),
+		],
+	);
+	Some(selected)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn group() -> UplinkGroupConfig {
+		toml::from_str(
+			r#"[[members]]
+			interface = "wan0"
+			priority = 10
+			weight = 3
+
+			[[members]]
+			interface = "wan1"
+			priority = 10
+			weight = 0
+
+			[[members]]
+			interface = "lte0"
+			priority = 20"#,
+		)
+		.unwrap()
+	}
+
+	const GATEWAY: Option<Ipv4Addr> = Some(Ipv4Addr::new(192, 0, 2, 1));
+
+	#[test]
+	fn selects_lowest_priority_members_that_are_up() {
+		let config = group();
+		assert_eq!(
+			select_members(&config, &[GATEWAY, GATEWAY, GATEWAY]),
+			[0, 1]
+		);
+		assert_eq!(select_members(&config, &[None, GATEWAY, GATEWAY]), [1]);
+		// The backup only takes over when every preferred member is down
+		assert_eq!(select_members(&config, &[None, None, GATEWAY]), [2]);
+		assert!(select_members(&config, &[None, None, None]).is_empty());
+	}
+
+	#[test]
+	fn nexthops_of_selected_members() {
+		let config = group();
+		let gateways = [GATEWAY, Some(Ipv4Addr::new(198, 51, 100, 1)), GATEWAY];
+		let selected = select_members(&config, &gateways);
+		// A weight of 0 is raised to the lowest the kernel accepts
+		assert_eq!(
+			nexthops(&config, &gateways, &selected),
+			[
+				("192.0.2.1".to_string(), "wan0".to_string(), 3),
+				("198.51.100.1".to_string(), "wan1".to_string(), 1),
+			]
+		);
+	}
+}
@@ -0,0 +1,646 @@
+use std::{
+	collections::BTreeMap,
+	io,
+	mem::MaybeUninit,
+	net::{Ipv4Addr, SocketAddrV4},
+	str::FromStr,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use pnet::{packet::arp::ArpOperations, util::MacAddr};
+use serde::Serialize;
+use socket2::{Domain, InterfaceIndexOrAddress, Protocol, SockAddr, Socket, Type};
+use tokio::{
+	io::unix::AsyncFd,
+	task::JoinHandle,
+	time::{sleep_until, Instant},
+};
+
+use crate::{
+	arp::{ArpListener, ArpMessage},
+	config::VrrpConfig,
+	hooks::run_hook_with_env,
+	link::interface::Interface,
+};
+
+/// The IP protocol number of VRRP
+const PROTOCOL: i32 = 112;
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 18);
+/// Version 3, type 1 (advertisement)
+const VERSION_TYPE: u8 = 0x31;
+/// The priority of the router that owns the addresses
+const OWNER_PRIORITY: u8 = 255;
+/// Sent by a master that stops, so a backup takes over right away
+const RESIGN_PRIORITY: u8 = 0;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum VrrpState {
+	Backup,
+	Master,
+}
+
+#[derive(Serialize, Clone)]
+pub struct VrrpStatus {
+	pub vrid: u8,
+	pub state: VrrpState,
+	pub priority: u8,
+	/// The address of the current master
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub master: Option<Ipv4Addr>,
+}
+
+struct Advertisement {
+	vrid: u8,
+	priority: u8,
+	/// In centiseconds
+	interval: u16,
+	addresses: Vec<Ipv4Addr>,
+}
+
+/// The internet checksum (RFC 1071)
+pub fn checksum(data: &[u8]) -> u16 {
+	let mut sum: u32 = data
+		.chunks(2)
+		.map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32)
+		.sum();
+	while sum >> 16 != 0 {
+		sum = (sum & 0xffff) + (sum >> 16);
+	}
+	!(sum as u16)
+}
+
+/// The checksum of VRRPv3 covers an IPv4 pseudo header (RFC 5798 5.2.8)
+fn pseudo_header(source: Ipv4Addr, destination: Ipv4Addr, len: usize) -> Vec<u8> {
+	let mut header = source.octets().to_vec();
+	header.extend_from_slice(&destination.octets());
+	header.extend_from_slice(&[0, PROTOCOL as u8]);
+	header.extend_from_slice(&(len as u16).to_be_bytes());
+	header
+}
+
+fn read_ipv4(data: &[u8], offset: usize) -> Option<Ipv4Addr> {
+	Some(Ipv4Addr::from(
+		<[u8; 4]>::try_from(data.get(offset..offset + 4)?).ok()?,
+	))
+}
+
+impl Advertisement {
+	fn encode(&self, source: Ipv4Addr) -> Vec<u8> {
+		let mut data = vec![
+			VERSION_TYPE,
+			self.vrid,
+			self.priority,
+			self.addresses.len() as u8,
+		];
+		data.extend_from_slice(&(self.interval & 0x0fff).to_be_bytes());
+		data.extend_from_slice(&[0, 0]);
+		for address in &self.addresses {
+			data.extend_from_slice(&address.octets());
+		}
+		let sum = checksum(&[pseudo_header(source, GROUP, data.len()), data.clone()].concat());
+		data[6..8].copy_from_slice(&sum.to_be_bytes());
+		data
+	}
+
+	/// Decodes an advertisement from a whole IPv4 packet, as raw sockets receive them, together with its sender
+	fn decode(packet: &[u8]) -> Option<(Advertisement, Ipv4Addr)> {
+		let header_len = (*packet.first()? & 0x0f) as usize * 4;
+		// Advertisements that were routed did not come from the local link (RFC 5798 7.1)
+		if *packet.get(8)? != 255 {
+			return None;
+		}
+		let source = read_ipv4(packet, 12)?;
+		let destination = read_ipv4(packet, 16)?;
+		let data = packet.get(header_len..)?;
+		if data.len() < 8 || data[0] != VERSION_TYPE {
+			return None;
+		}
+		if checksum(
+			&[
+				pseudo_header(source, destination, data.len()),
+				data.to_vec(),
+			]
+			.concat(),
+		) != 0
+		{
+			return None;
+		}
+		let addresses = (0..data[3] as usize)
+			.map(|i| read_ipv4(data, 8 + i * 4))
+			.collect::<Option<_>>()?;
+		let advertisement = Advertisement {
+			vrid: data[1],
+			priority: data[2],
+			interval: u16::from_be_bytes([data[4], data[5]]) & 0x0fff,
+			addresses,
+		};
+		Some((advertisement, source))
+	}
+}
+
+/// Opens a raw socket receiving the advertisements on the interface
+fn open_receiver(ifname: &str, ifindex: u32) -> io::Result<AsyncFd<Socket>> {
+	let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::from(PROTOCOL)))?;
+	socket.bind_device(Some(ifname.as_bytes()))?;
+	socket.join_multicast_v4_n(&GROUP, &InterfaceIndexOrAddress::Index(ifindex))?;
+	socket.set_nonblocking(true)?;
+	AsyncFd::new(socket)
+}
+
+/// Opens a raw socket sending advertisements from `address` out of the interface
+fn open_sender(ifname: &str, address: Ipv4Addr) -> io::Result<AsyncFd<Socket>> {
+	let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::from(PROTOCOL)))?;
+	socket.bind_device(Some(ifname.as_bytes()))?;
+	// Advertisements must have a TTL of 255 so receivers know they are from the local link
+	socket.set_multicast_ttl_v4(255)?;
+	socket.set_multicast_loop_v4(false)?;
+	socket.bind(&SocketAddrV4::new(address, 0).into())?;
+	socket.set_nonblocking(true)?;
+	AsyncFd::new(socket)
+}
+
+/// Waits for the next packet, raw IPv4 sockets receive it with the IP header
+async fn receive(socket: &AsyncFd<Socket>) -> io::Result<Vec<u8>> {
+	let mut buffer = [MaybeUninit::<u8>::uninit(); 1500];
+	loop {
+		let mut guard = socket.readable().await?;
+		let Ok(result) = guard.try_io(|socket| socket.get_ref().recv(&mut buffer)) else {
+			continue;
+		};
+		let len = result?;
+		// SAFETY: recv initialized the first len bytes
+		return Ok(buffer[..len]
+			.iter()
+			.map(|byte| unsafe { byte.assume_init() })
+			.collect());
+	}
+}
+
+/// Converts centiseconds, the unit of VRRP intervals
+fn centiseconds(value: u32) -> Duration {
+	Duration::from_millis(value as u64 * 10)
+}
+
+struct Router {
+	ifname: String,
+	vrid: u8,
+	priority: u8,
+	/// In centiseconds
+	interval: u16,
+	preempt: bool,
+	addresses: Vec<(Ipv4Addr, u8)>,
+	/// The router's own address on the interface, the source of its advertisements
+	primary: Ipv4Addr,
+	/// The interface the addresses are put on, a macvlan with the virtual MAC address or the interface itself
+	vinterface: String,
+	virtual_mac: bool,
+	mac: MacAddr,
+	receiver: AsyncFd<Socket>,
+	sender: AsyncFd<Socket>,
+	status: Mutex<VrrpStatus>,
+}
+
+impl Router {
+	fn state(&self) -> VrrpState {
+		self.status.lock().unwrap().state
+	}
+
+	fn set_status(&self, state: VrrpState, master: Option<Ipv4Addr>) {
+		let mut status = self.status.lock().unwrap();
+		status.state = state;
+		status.master = master;
+	}
+
+	/// How long a backup waits before it takes over, lower priorities wait longer (RFC 5798 6.1)
+	fn skew_time(&self, master_interval: u16) -> Duration {
+		centiseconds((256 - self.priority as u32) * master_interval as u32 / 256)
+	}
+
+	fn master_down_interval(&self, master_interval: u16) -> Duration {
+		centiseconds(3 * master_interval as u32) + self.skew_time(master_interval)
+	}
+
+	async fn send(&self, priority: u8) {
+		let advertisement = Advertisement {
+			vrid: self.vrid,
+			priority,
+			interval: self.interval,
+			addresses: self.addresses.iter().map(|(address, _)| *address).collect(),
+		};
+		let data = advertisement.encode(self.primary);
+		let destination = SockAddr::from(SocketAddrV4::new(GROUP, 0));
+		loop {
+			let Ok(mut guard) = self.sender.writable().await else {
+				return;
+			};
+			if let Ok(result) = guard.try_io(|socket| socket.get_ref().send_to(&data, &destination))
+			{
+				if let Err(err) = result {
+					println!(
+						"[{}] Failed to send VRRP {} advertisement: {err}",
+						self.ifname, self.vrid
+					);
+				}
+				return;
+			}
+		}
+	}
+
+	fn run_hook(&self, state: &str) {
+		let addresses: Vec<String> = self
+			.addresses
+			.iter()
+			.map(|(address, _)| address.to_string())
+			.collect();
+		run_hook_with_env(
+			format!("vrrp-{state}.{}", self.ifname),
+			&[
+				("VRRP_INTERFACE".to_string(), self.ifname.clone()),
+				("VRRP_VRID".to_string(), self.vrid.to_string()),
+				("VRRP_ADDRESSES".to_string(), addresses.join(" ")),
+			],
+		);
+	}
+
+	async fn become_master(&self) {
+		println!("[{}] VRRP {}: becoming master", self.ifname, self.vrid);
+		self.set_status(VrrpState::Master, Some(self.primary));
+		let vinterface = Interface::get_from_name(&self.vinterface);
+		for (address, prefix) in &self.addresses {
+			vinterface
+				.replace_address(&address.to_string(), *prefix)
+				.await;
+		}
+		self.send(self.priority).await;
+		// Hosts and switches learn where the addresses are now
+		if let Some(mut listener) = ArpListener::open(&vinterface).await {
+			for (address, _) in &self.addresses {
+				let announcement = ArpMessage {
+					operation: ArpOperations::Request,
+					sender_mac: self.mac,
+					sender_ip: *address,
+					target_mac: MacAddr::broadcast(),
+					target_ip: *address,
+				};
+				listener.send(&announcement, MacAddr::broadcast());
+			}
+		}
+		self.run_hook("master");
+	}
+
+	async fn become_backup(&self, master: Option<Ipv4Addr>) {
+		if self.state() == VrrpState::Master {
+			println!("[{}] VRRP {}: becoming backup", self.ifname, self.vrid);
+		}
+		self.set_status(VrrpState::Backup, master);
+		self.remove_addresses().await;
+		self.run_hook("backup");
+	}
+
+	async fn remove_addresses(&self) {
+		// The owner has the addresses configured on the interface itself
+		if self.priority == OWNER_PRIORITY {
+			return;
+		}
+		let vinterface = Interface::get_from_name(&self.vinterface);
+		for (address, prefix) in &self.addresses {
+			vinterface
+				.delete_address(&address.to_string(), *prefix)
+				.await;
+		}
+	}
+
+	/// Lets a backup take over right away and gives up the addresses
+	async fn resign(&self) {
+		if self.state() == VrrpState::Master {
+			self.send(RESIGN_PRIORITY).await;
+			self.remove_addresses().await;
+		}
+	}
+
+	/// The state machine of RFC 5798 6.4
+	async fn run(self: Arc<Self>) {
+		let mut master_interval = self.interval;
+		let mut timer = if self.priority == OWNER_PRIORITY {
+			self.become_master().await;
+			Instant::now() + centiseconds(self.interval as u32)
+		} else {
+			self.become_backup(None).await;
+			Instant::now() + self.master_down_interval(master_interval)
+		};
+		loop {
+			let master = self.state() == VrrpState::Master;
+			tokio::select! {
+				_ = sleep_until(timer) => {
+					if master {
+						self.send(self.priority).await;
+					} else {
+						self.become_master().await;
+					}
+					timer = Instant::now() + centiseconds(self.interval as u32);
+				}
+				result = receive(&self.receiver) => {
+					let packet = match result {
+						Ok(packet) => packet,
+						Err(err) => {
+							println!("[{}] VRRP {} stopped: {err}", self.ifname, self.vrid);
+							return;
+						}
+					};
+					let Some((advertisement, source)) = Advertisement::decode(&packet) else {
+						continue;
+					};
+					if advertisement.vrid != self.vrid || source == self.primary {
+						continue;
+					}
+					if advertisement.addresses.len() != self.addresses.len() {
+						println!(
+							"[{}] VRRP {}: {source} advertises different addresses",
+							self.ifname, self.vrid
+						);
+					}
+					let interval = advertisement.interval.max(1);
+					if master {
+						if advertisement.priority == RESIGN_PRIORITY {
+							self.send(self.priority).await;
+							timer = Instant::now() + centiseconds(self.interval as u32);
+						} else if advertisement.priority > self.priority
+							|| (advertisement.priority == self.priority && source > self.primary)
+						{
+							master_interval = interval;
+							self.become_backup(Some(source)).await;
+							timer = Instant::now() + self.master_down_interval(master_interval);
+						}
+					} else if advertisement.priority == RESIGN_PRIORITY {
+						timer = Instant::now() + self.skew_time(master_interval);
+					} else if !self.preempt || advertisement.priority >= self.priority {
+						master_interval = interval;
+						self.set_status(VrrpState::Backup, Some(source));
+						timer = Instant::now() + self.master_down_interval(master_interval);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Running virtual routers by interface name
+#[allow(clippy::type_complexity)]
+static ROUTERS: Mutex<BTreeMap<String, Vec<(JoinHandle<()>, Arc<Router>)>>> =
+	Mutex::new(BTreeMap::new());
+/// The arp_ignore setting interfaces had before a virtual MAC router changed it, by interface name
+static ARP_IGNORE: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Get the state of the virtual routers on the interface
+pub fn get_status(ifname: &str) -> Vec<VrrpStatus> {
+	ROUTERS
+		.lock()
+		.unwrap()
+		.get(ifname)
+		.map(|routers| {
+			routers
+				.iter()
+				.map(|(_, router)| router.status.lock().unwrap().clone())
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+/// Parses an address like `10.1.0.1/24`, without a prefix length it is a /32
+fn parse_address(address: &str) -> Option<(Ipv4Addr, u8)> {
+	match address.split_once('/') {
+		Some((address, prefix)) => Some((
+			Ipv4Addr::from_str(address).ok()?,
+			prefix.parse().ok().filter(|prefix| *prefix <= 32)?,
+		)),
+		None => Some((Ipv4Addr::from_str(address).ok()?, 32)),
+	}
+}
+
+/// Sets up a virtual router, returns None if the configuration is invalid
+async fn create_router(interface: &Interface, config: &VrrpConfig) -> Option<Router> {
+	let ifname = &interface.name;
+	if config.vrid == 0 || config.priority == RESIGN_PRIORITY {
+		println!("[{ifname}] VRRP virtual router id and priority must be between 1 and 255");
+		return None;
+	}
+	let mut addresses = vec![];
+	for address in &config.addresses {
+		match parse_address(address) {
+			Some(address) => addresses.push(address),
+			None => println!("[{ifname}] Invalid VRRP address: {address}"),
+		}
+	}
+	if addresses.is_empty() {
+		println!("[{ifname}] VRRP {} has no valid addresses", config.vrid);
+		return None;
+	}
+	let own_addresses = interface.get_ipv4_addresses().await;
+	let primary = own_addresses
+		.iter()
+		.map(|(address, _)| *address)
+		.find(|address| !addresses.iter().any(|(shared, _)| shared == address))
+		.or(own_addresses.first().map(|(address, _)| *address));
+	let Some(primary) = primary else {
+		println!("[{ifname}] VRRP requires an address on the interface");
+		return None;
+	};
+
+	let (vinterface, mac) = if config.virtual_mac {
+		let mac = format!("00:00:5e:00:01:{:02x}", config.vrid);
+		let name: String = format!("vr{}.{ifname}", config.vrid)
+			.chars()
+			.take(15)
+			.collect();
+		let existing = Interface::get_from_name(&name);
+		if existing.exists().await {
+			existing.delete().await;
+		}
+		let vinterface = match Interface::create_macvlan(&name, ifname, &mac).await {
+			Ok(vinterface) => vinterface,
+			Err(err) => {
+				println!(
+					"[{ifname}] VRRP {}: failed to create {name}: {err}",
+					config.vrid
+				);
+				return None;
+			}
+		};
+		// Both routers have the same MAC on the macvlan, so it must not get a link-local address
+		vinterface.set_ipv6_sysctl("disable_ipv6", "1");
+		// Only the interface that has an address answers ARP for it, so the addresses are answered with the virtual MAC
+		vinterface.set_ipv4_sysctl("arp_ignore", "1");
+		// The first router on the interface keeps the setting it had, to put it back when stopping
+		if let Some(previous) = interface.get_ipv4_sysctl("arp_ignore") {
+			ARP_IGNORE
+				.lock()
+				.unwrap()
+				.entry(ifname.clone())
+				.or_insert(previous);
+		}
+		interface.set_ipv4_sysctl("arp_ignore", "1");
+		vinterface.up().await;
+		(name, MacAddr::from_str(&mac).ok()?)
+	} else {
+		(
+			ifname.clone(),
+			MacAddr::from_str(&interface.get_mac().await).ok()?,
+		)
+	};
+
+	let sockets = open_receiver(ifname, interface.get_index())
+		.and_then(|receiver| Ok((receiver, open_sender(&vinterface, primary)?)));
+	let (receiver, sender) = match sockets {
+		Ok(sockets) => sockets,
+		Err(err) => {
+			println!("[{ifname}] Could not start VRRP {}: {err}", config.vrid);
+			return None;
+		}
+	};
+	println!(
+		"[{ifname}] Starting VRRP {} with priority {} for {}",
+		config.vrid,
+		config.priority,
+		config.addresses.join(", ")
+	);
+	Some(Router {
+		ifname: ifname.clone(),
+		vrid: config.vrid,
+		priority: config.priority,
+		interval: (config.advert_interval / 10).clamp(1, 0x0fff) as u16,
+		preempt: config.preempt || config.priority == OWNER_PRIORITY,
+		addresses,
+		primary,
+		vinterface,
+		virtual_mac: config.virtual_mac,
+		mac,
+		receiver,
+		sender,
+		status: Mutex::new(VrrpStatus {
+			vrid: config.vrid,
+			state: VrrpState::Backup,
+			priority: config.priority,
+			master: None,
+		}),
+	})
+}
+
+/// Starts the virtual routers of the interface
+pub async fn start(interface: &Interface, configs: &[VrrpConfig]) {
+	stop(interface).await;
+	let mut routers = vec![];
+	for config in configs {
+		if let Some(router) = create_router(interface, config).await {
+			let router = Arc::new(router);
+			routers.push((tokio::spawn(Arc::clone(&router).run()), router));
+		}
+	}
+	ROUTERS
+		.lock()
+		.unwrap()
+		.insert(interface.name.clone(), routers);
+}
+
+/// Stops the virtual routers of the interface, a master hands over to a backup right away
+pub async fn stop(interface: &Interface) {
+	let routers = ROUTERS.lock().unwrap().remove(&interface.name);
+	for (task, router) in routers.unwrap_or_default() {
+		task.abort();
+		println!("[{}] Stopping VRRP {}", interface.name, router.vrid);
+		if !interface.exists().await {
+			continue;
+		}
+		router.resign().await;
+		if router.virtual_mac {
+			Interface::get_from_name(&router.vinterface).delete().await;
+		}
+	}
+	let previous = ARP_IGNORE.lock().unwrap().remove(&interface.name);
+	if let Some(previous) = previous {
+		if interface.exists().await {
+			interface.set_ipv4_sysctl("arp_ignore", &previous);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Wraps an advertisement in the IPv4 header raw sockets receive it with
+	fn packet(source: Ipv4Addr, ttl: u8, data: &[u8]) -> Vec<u8> {
+		let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, ttl, PROTOCOL as u8, 0, 0];
+		packet.extend_from_slice(&source.octets());
+		packet.extend_from_slice(&GROUP.octets());
+		packet.extend_from_slice(data);
+		packet
+	}
+
+	#[test]
+	fn internet_checksum() {
+		// The example of RFC 1071 3
+		assert_eq!(
+			checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]),
+			!0xddf2
+		);
+		// An odd length is padded with a zero
+		assert_eq!(checksum(&[0x01]), !0x0100);
+		assert_eq!(checksum(&[]), 0xffff);
+		// A message including its checksum sums to zero
+		let mut data = vec![0x45, 0x00, 0x00, 0x1c, 0x12, 0x34, 0, 0];
+		let sum = checksum(&data);
+		data.extend_from_slice(&sum.to_be_bytes());
+		assert_eq!(checksum(&data), 0);
+	}
+
+	#[test]
+	fn advertisement_round_trip() {
+		let source = Ipv4Addr::new(192, 168, 1, 2);
+		let advertisement = Advertisement {
+			vrid: 7,
+			priority: 200,
+			interval: 100,
+			addresses: vec![
+				Ipv4Addr::new(192, 168, 1, 1),
+				Ipv4Addr::new(192, 168, 1, 254),
+			],
+		};
+		let data = advertisement.encode(source);
+		let (decoded, sender) = Advertisement::decode(&packet(source, 255, &data)).unwrap();
+		assert_eq!(sender, source);
+		assert_eq!(decoded.vrid, 7);
+		assert_eq!(decoded.priority, 200);
+		assert_eq!(decoded.interval, 100);
+		assert_eq!(decoded.addresses, advertisement.addresses);
+	}
+
+	#[test]
+	fn rejects_malformed_advertisements() {
+		let source = Ipv4Addr::new(192, 168, 1, 2);
+		let advertisement = Advertisement {
+			vrid: 7,
+			priority: 100,
+			interval: 100,
+			addresses: vec![Ipv4Addr::new(192, 168, 1, 1)],
+		};
+		let data = advertisement.encode(source);
+		// Routed
+		assert!(Advertisement::decode(&packet(source, 64, &data)).is_none());
+		// Sent from another address than the checksum covers
+		assert!(
+			Advertisement::decode(&packet(Ipv4Addr::new(192, 168, 1, 3), 255, &data)).is_none()
+		);
+		let mut corrupted = data.clone();
+		corrupted[2] = 101;
+		assert!(Advertisement::decode(&packet(source, 255, &corrupted)).is_none());
+		// Fewer addresses than announced
+		let mut truncated = data[..8].to_vec();
+		truncated[6..8].copy_from_slice(&[0, 0]);
+		let sum = checksum(&[pseudo_header(source, GROUP, 8), truncated.clone()].concat());
+		truncated[6..8].copy_from_slice(&sum.to_be_bytes());
+		assert!(Advertisement::decode(&packet(source, 255, &truncated)).is_none());
+		assert!(Advertisement::decode(&packet(source, 255, &data[..7])).is_none());
+	}
+}
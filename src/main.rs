@@ -3,20 +3,34 @@ mod config;
 pub mod hooks;
 mod interface;
 mod link;
+mod state;
+mod status;
 
 use std::{
+	collections::{BTreeMap, BTreeSet, HashSet},
 	io::{Read as _, Write as _},
+	net::Shutdown,
 	os::unix::net::{UnixListener, UnixStream},
-	sync::Arc,
+	sync::{Arc, Mutex},
+	time::Duration,
 };
 
-use clap::{arg, command, Parser, Subcommand};
-use config::Config;
+use clap::{Parser, Subcommand};
+use config::{Config, InterfaceConfig};
 use futures::future::join_all;
 use hooks::run_hook;
-use interface::{bridge::BridgeInterface, ethernet::EthernetInterface};
-use link::interface::Interface;
-use tokio::{process::Command, sync::Mutex};
+use interface::{
+	bridge::BridgeInterface, ethernet::EthernetInterface, failover, generic::ConfigureStatus,
+	rename,
+};
+use link::{
+	dad, dhcp6c, dhcpc, dhcpd, dhcrelay, dnsd,
+	interface::Interface,
+	matching::list_links,
+	monitor::{LinkEvent, LinkMonitor},
+	radv, resolver, slaac, uplink, vrrp,
+};
+use tokio::{process::Command, task::JoinHandle};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -31,20 +45,32 @@ struct Args {
 #[derive(Subcommand)]
 enum Commands {
 	/// Launch the TUI
-	TUI {},
+	Tui {},
 
 	/// Run (do not use, service only)
-	RUN {},
+	Run {},
 
 	/// Reset networking
-	RESET {},
+	Reset {},
 
 	/// Reload
-	RELOAD {
+	Reload {
 		/// Confirm the reload
 		#[arg(short, long)]
 		yes: bool,
 	},
+
+	/// Show the status of all configured interfaces
+	Status {},
+
+	/// Show the leases handed out by the DHCP servers
+	Leases {},
+
+	/// Revoke a lease handed out by a DHCP server
+	Revoke {
+		/// The leased address
+		address: String,
+	},
 }
 
 #[tokio::main]
@@ -52,13 +78,13 @@ async fn main() {
 	let args: Args = Args::parse();
 
 	match args.command {
-		Commands::TUI {} => {
+		Commands::Tui {} => {
 			unimplemented!("TUI not implemented yet");
 		}
-		Commands::RUN {} => {
+		Commands::Run {} => {
 			run().await;
 		}
-		Commands::RESET {} => {
+		Commands::Reset {} => {
 			// reset().await;
 			let confirm = dialoguer::Confirm::new()
 				.with_prompt("Are you sure you want to reset? All interfaces will be brought down!")
@@ -70,11 +96,11 @@ async fn main() {
 			let mut unix_stream = UnixStream::connect("/tmp/netd.sock")
 				.expect("Could not connect to daemon socket. Is netd running?");
 			unix_stream
-				.write(b"reset")
+				.write_all(b"reset")
 				.expect("Failed to write to unix stream");
 			println!("Sent reset command to daemon");
 		}
-		Commands::RELOAD { yes } => {
+		Commands::Reload { yes } => {
 			// Confirm that the user wants to reload
 			if !yes {
 				let confirm = dialoguer::Confirm::new()
@@ -90,20 +116,65 @@ async fn main() {
 			let mut unix_stream = UnixStream::connect("/tmp/netd.sock")
 				.expect("Could not connect to daemon socket. Is netd running?");
 			unix_stream
-				.write(b"reload")
+				.write_all(b"reload")
 				.expect("Failed to write to unix stream");
 			println!("Sent reload command to daemon");
 		}
+		Commands::Status {} => {
+			print!("{}", send_command("status"));
+		}
+		Commands::Leases {} => {
+			print!("{}", send_command("leases"));
+		}
+		Commands::Revoke { address } => {
+			print!("{}", send_command(&format!("revoke {address}")));
+		}
 	}
 }
 
+/// Sends a command to the daemon and waits for its response
+fn send_command(command: &str) -> String {
+	let mut unix_stream = UnixStream::connect("/tmp/netd.sock")
+		.expect("Could not connect to daemon socket. Is netd running?");
+	unix_stream
+		.write_all(command.as_bytes())
+		.expect("Failed to write to unix stream");
+	unix_stream
+		.shutdown(Shutdown::Write)
+		.expect("Failed to shut down unix stream");
+	let mut response = String::new();
+	unix_stream
+		.read_to_string(&mut response)
+		.expect("Failed at reading the unix stream");
+	response
+}
+
+/// The configuration in use. Only held to take or replace a snapshot, never while configuring
+type SharedConfig = Arc<Mutex<Arc<Config>>>;
+
+/// Interfaces being configured, by name
+static CONFIGURING: Mutex<BTreeMap<String, JoinHandle<()>>> = Mutex::new(BTreeMap::new());
+/// Ethernet interfaces found and taken over by a configuration attempt, so that a link
+/// appearing while the interfaces are configured is not configured twice
+static CLAIMED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+/// Interfaces whose configuration was given up because a dependency did not exist.
+/// They are configured again by the link monitor once the dependency appears
+static WAITING_FOR_DEPENDS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+fn snapshot(config: &SharedConfig) -> Arc<Config> {
+	Arc::clone(&config.lock().unwrap())
+}
+
 async fn run() {
-	let current_config = Arc::new(Mutex::new(Config::load()));
+	let current_config: SharedConfig = Arc::new(Mutex::new(Arc::new(Config::load())));
 	let configure_thread_cfg = Arc::clone(&current_config);
 	let socket_cfg = Arc::clone(&current_config);
+	// Subscribe before configuring, so links appearing in the meantime are not missed
+	let monitor = LinkMonitor::subscribe();
+	let present: HashSet<u32> = list_links().iter().map(|link| link.index).collect();
+	tokio::spawn(watch_links(monitor, present, Arc::clone(&current_config)));
 	tokio::spawn(async move {
-		let config = configure_thread_cfg.lock().await;
-		configure(&config).await;
+		configure(snapshot(&configure_thread_cfg)).await;
 	});
 	let socket_path = "/tmp/netd.sock";
 	let unix_listener = UnixListener::bind(socket_path)
@@ -116,7 +187,7 @@ async fn run() {
 	}
 }
 
-async fn handle_stream(mut stream: &UnixStream, config: &Arc<Mutex<Config>>) {
+async fn handle_stream(stream: &mut UnixStream, config: &SharedConfig) {
 	let mut message = String::new();
 	stream
 		.read_to_string(&mut message)
@@ -124,28 +195,44 @@ async fn handle_stream(mut stream: &UnixStream, config: &Arc<Mutex<Config>>) {
 	let message = message.trim();
 	println!("Received message: {:?}", message);
 	if message == "reload" {
-		let mut config_guard = config.lock().await;
-		reset(&config_guard).await;
-		*config_guard = Config::load();
-		configure(&config_guard).await;
+		reset(&snapshot(config)).await;
+		let new_config = Arc::new(Config::load());
+		*config.lock().unwrap() = Arc::clone(&new_config);
+		configure(new_config).await;
 	} else if message == "reset" {
-		let config_guard = config.lock().await;
-		reset(&config_guard).await;
+		reset(&snapshot(config)).await;
+	} else if message == "status" {
+		let status = status::get_status(&snapshot(config)).await;
+		stream
+			.write_all(status.as_bytes())
+			.expect("Failed to write to unix stream");
+	} else if message == "leases" {
+		let leases = status::get_leases(&snapshot(config));
+		stream
+			.write_all(leases.as_bytes())
+			.expect("Failed to write to unix stream");
+	} else if let Some(address) = message.strip_prefix("revoke ") {
+		let response = match address.trim().parse() {
+			Ok(address) => match dhcpd::revoke_lease(address) {
+				Some(ifname) => format!("Revoked lease of {address} on {ifname}\n"),
+				None => format!("No lease for {address}\n"),
+			},
+			Err(_) => format!("Invalid address: {address}\n"),
+		};
+		stream
+			.write_all(response.as_bytes())
+			.expect("Failed to write to unix stream");
 	}
 }
 
-async fn configure(config: &Config) {
-	if config.renames.len() != 0 {
-		println!("Renaming {} interfaces!", config.renames.len());
-		for entry in config.renames.clone() {
-			let old_name = entry.0;
-			let new_name = entry.1;
-			let mut interface = Interface::get_from_name(&old_name);
-			if !interface.exists().await {
-				panic!("Interface {old_name} does not exist!");
-			}
-			interface.rename(&new_name).await;
-		}
+/// Starts configuring the interfaces, each in its own task so that none of them
+/// waiting for a link, a lease or a router holds up the others or the daemon
+async fn configure(config: Arc<Config>) {
+	resolver::configure(&config.resolv_conf);
+	uplink::start(&config.uplinks);
+
+	if !config.renames.is_empty() {
+		rename::apply_renames(&config.renames).await;
 	}
 
 	if std::env::var("NO_LO_UP").is_err() {
@@ -156,139 +243,265 @@ async fn configure(config: &Config) {
 		lo.up().await;
 	}
 
+	// Started first, the upstream servers the DHCP clients learn are looked up for every query
+	if let Some(dns) = &config.dns {
+		dnsd::start(dns).await;
+	}
+
 	println!("Configuring {} interfaces!", config.interfaces.len());
-	let futures: Vec<_> = config
-		.interfaces
-		.iter()
-		.map(|entry| {
-			let ifconfig = entry.1;
-			let name = entry.0.clone();
-			async move {
-				println!("Configuring interface: {:?}", &name);
-
-				// Wait for all depends to be CONFIGURED
-				if let Some(depends) = &ifconfig.shared.depends {
-					for depend in depends {
-						let depend_interface = Interface::get_from_name(&depend);
-						if !depend_interface.exists().await {
-							panic!("Interface {depend} does not exist!");
-						}
-						while depend_interface.get_description().await != "CONFIGURED" {
-							println!("[{name}] Waiting for {depend} to be CONFIGURED");
-							tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-						}
-					}
-				}
+	for name in config.interfaces.keys() {
+		spawn_configure_interface(name.clone(), Arc::clone(&config));
+	}
+}
 
-				match &ifconfig.specific {
-					config::InterfaceTypeConfig::Ethernet(specific) => {
-						let interface = Interface::get_from_name(&name);
-						if !interface.exists().await {
-							panic!("Interface {name} does not exist!");
-						}
-						EthernetInterface::configure(&interface, &specific).await;
-					}
-					config::InterfaceTypeConfig::Bridge(specific) => {
-						BridgeInterface::configure(&name, &specific).await;
-					}
-				}
+/// Configures an interface of the configuration in the background, replacing an earlier attempt
+fn spawn_configure_interface(name: String, config: Arc<Config>) {
+	let ifname = name.clone();
+	let task = tokio::spawn(async move {
+		configure_interface(ifname.clone(), &config.interfaces[&ifname]).await;
+	});
+	if let Some(previous) = CONFIGURING.lock().unwrap().insert(name, task) {
+		previous.abort();
+	}
+}
+
+async fn configure_interface(name: String, ifconfig: &InterfaceConfig) {
+	println!("Configuring interface: {:?}", &name);
 
-				// Start services
-				for service in &ifconfig.shared.services {
-					println!("[{name}] Starting service: {service}");
-					let output = Command::new("rc-service")
-						.arg(service)
-						.arg("start")
-						.output()
-						.await
-						.expect("Failed to execute command");
+	// Wait for all depends to be CONFIGURED
+	if let Some(depends) = &ifconfig.shared.depends {
+		let timeout = Duration::from_secs(ifconfig.shared.wait_timeout.unwrap_or(0));
+		for depend in depends {
+			let depend_interface = Interface::get_from_name(depend);
+			// Marked before looking, so a dependency appearing in between is not missed
+			WAITING_FOR_DEPENDS.lock().unwrap().insert(name.clone());
+			if !depend_interface.wait_until_exists(timeout).await {
+				println!(
+					"[{name}] Dependency {depend} does not exist, it will be configured when it appears"
+				);
+				return;
+			}
+			// Already restarted by the link monitor
+			if !WAITING_FOR_DEPENDS.lock().unwrap().remove(&name) {
+				return;
+			}
+			while depend_interface.get_description().await != "CONFIGURED" {
+				println!("[{name}] Waiting for {depend} to be CONFIGURED");
+				tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+			}
+		}
+	}
+
+	let status = match &ifconfig.specific {
+		config::InterfaceTypeConfig::Ethernet(specific) => {
+			let interface = Interface::get_from_name(&name);
+			let timeout = Duration::from_secs(ifconfig.shared.wait_timeout.unwrap_or(0));
+			if !interface.wait_until_exists(timeout).await {
+				if ifconfig.shared.optional {
+					println!(
+						"[{name}] Interface does not exist, it will be configured when it appears"
+					);
+				} else {
 					println!(
-						"[{name}] RC-Service log: {}",
-						String::from_utf8_lossy(&output.stdout)
+						"[{name}] Required interface did not appear within {}s, it will be configured when it appears",
+						timeout.as_secs()
 					);
 				}
+				return;
 			}
-		})
-		.collect();
+			if !CLAIMED.lock().unwrap().insert(name.clone()) {
+				println!("[{name}] Interface is already being configured");
+				return;
+			}
+			EthernetInterface::configure(&interface, specific).await
+		}
+		config::InterfaceTypeConfig::Bridge(specific) => {
+			BridgeInterface::configure(&name, specific).await
+		}
+	};
 
-	join_all(futures).await;
+	// Services can't bind to an address that is not assigned
+	match status {
+		ConfigureStatus::Configured => {}
+		ConfigureStatus::Refused => {
+			println!("[{name}] Not starting services without an address");
+			return;
+		}
+		ConfigureStatus::Delayed(assigned) => {
+			println!("[{name}] Starting services once the address is assigned");
+			if assigned.await.is_err() {
+				return;
+			}
+		}
+	}
+
+	// Start services
+	for service in &ifconfig.shared.services {
+		println!("[{name}] Starting service: {service}");
+		let output = Command::new("rc-service")
+			.arg(service)
+			.arg("start")
+			.output()
+			.await
+			.expect("Failed to execute command");
+		println!(
+			"[{name}] RC-Service log: {}",
+			String::from_utf8_lossy(&output.stdout)
+		);
+	}
 }
 
 async fn reset(config: &Config) {
+	dnsd::stop().await;
+	uplink::stop().await;
+
 	println!("Resetting {} interfaces!", config.interfaces.len());
 
 	let futures: Vec<_> = config
 		.interfaces
 		.iter()
-		.map(|entry| {
-			let ifconfig = entry.1;
-			let name = entry.0.clone();
-			async move {
-				println!("Resetting interface: {:?}", &name);
-
-				run_hook(format!("pre-down.{name}"));
-
-				// Stop services
-				for service in &ifconfig.shared.services {
-					println!("[{name}] Stopping service: {service}");
-					let output = Command::new("rc-service")
-						.arg(service)
-						.arg("stop")
-						.output()
-						.await
-						.expect("Failed to execute command");
-					println!(
-						"[{name}] RC-Service log: {}",
-						String::from_utf8_lossy(&output.stdout)
-					);
+		.map(|entry| reset_interface(entry.0.clone(), entry.1))
+		.collect();
+
+	join_all(futures).await;
+
+	rename::revert_renames(&config.renames).await;
+}
+
+async fn reset_interface(name: String, ifconfig: &InterfaceConfig) {
+	println!("Resetting interface: {:?}", &name);
+
+	if let Some(task) = CONFIGURING.lock().unwrap().remove(&name) {
+		task.abort();
+	}
+	CLAIMED.lock().unwrap().remove(&name);
+	WAITING_FOR_DEPENDS.lock().unwrap().remove(&name);
+
+	run_hook(format!("pre-down.{name}"));
+
+	failover::stop(&Interface::get_from_name(&name));
+	dad::stop(&Interface::get_from_name(&name));
+	vrrp::stop(&Interface::get_from_name(&name)).await;
+	radv::stop(&Interface::get_from_name(&name)).await;
+	dhcpd::stop_server(&Interface::get_from_name(&name)).await;
+	dhcrelay::stop_relay(&Interface::get_from_name(&name)).await;
+	dhcpc::stop_client(&Interface::get_from_name(&name)).await;
+	dhcp6c::stop_client(&Interface::get_from_name(&name)).await;
+	dhcp6c::remove_downstream(&name).await;
+	slaac::stop(&Interface::get_from_name(&name)).await;
+	resolver::remove_interface(&name);
+
+	// Stop services
+	for service in &ifconfig.shared.services {
+		println!("[{name}] Stopping service: {service}");
+		let output = Command::new("rc-service")
+			.arg(service)
+			.arg("stop")
+			.output()
+			.await
+			.expect("Failed to execute command");
+		println!(
+			"[{name}] RC-Service log: {}",
+			String::from_utf8_lossy(&output.stdout)
+		);
+	}
+
+	match &ifconfig.specific {
+		config::InterfaceTypeConfig::Ethernet(_) => {
+			let interface = Interface::get_from_name(&name);
+			// Optional or unplugged interfaces have nothing left to reset
+			if interface.exists().await {
+				interface.down().await;
+				interface.flush_addresses().await;
+				interface.set_description("").await;
+			}
+		}
+		config::InterfaceTypeConfig::Bridge(specific) => {
+			let bridge = Interface::get_from_name(&name);
+			if !bridge.exists().await {
+				return;
+			}
+			// Bring all subinterfaces down
+			for ifname in &specific.interfaces {
+				let iface = Interface::get_from_name(ifname);
+				if !iface.exists().await {
+					continue;
 				}
+				iface.set_nomaster().await;
+				iface.down().await;
+			}
+			bridge.down().await;
+			bridge.flush_addresses().await;
+			bridge.set_description("").await;
+			bridge.delete().await;
+		}
+	}
 
-				match &ifconfig.specific {
-					config::InterfaceTypeConfig::Ethernet(_) => {
-						let interface = Interface::get_from_name(&name);
-						if !interface.exists().await {
-							panic!("Interface {name} does not exist!");
-						}
-						interface.down().await;
-						interface.flush_addresses().await;
-						interface.set_description("").await;
+	run_hook(format!("post-down.{name}"));
+}
+
+/// Configures physical interfaces and bridge members when they are plugged in, and tears them down when they are removed.
+/// Links are tracked by index, as renaming a link is reported just like a new one.
+/// Bridges are created and deleted by netd itself, so they are not watched
+/// `present` are the links that existed when the monitor was subscribed
+async fn watch_links(mut monitor: LinkMonitor, mut present: HashSet<u32>, config: SharedConfig) {
+	while let Some(event) = monitor.next().await {
+		match event {
+			LinkEvent::Added { index, name } => {
+				if !present.insert(index) {
+					continue;
+				}
+				let config_guard = snapshot(&config);
+				let name = rename::rename_if_matches(&name, &config_guard.renames)
+					.await
+					.unwrap_or(name);
+				for (dependent, ifconfig) in &config_guard.interfaces {
+					let depends_on_link = ifconfig
+						.shared
+						.depends
+						.as_ref()
+						.is_some_and(|depends| depends.contains(&name));
+					if depends_on_link && WAITING_FOR_DEPENDS.lock().unwrap().remove(dependent) {
+						println!("[{dependent}] Dependency {name} appeared");
+						spawn_configure_interface(dependent.clone(), Arc::clone(&config_guard));
 					}
-					config::InterfaceTypeConfig::Bridge(specific) => {
-						let bridge = Interface::get_from_name(&name);
-						if !bridge.exists().await {
-							return;
-						}
-						// Bring all subinterfaces down
-						for ifname in &specific.interfaces {
-							let iface = Interface::get_from_name(ifname);
-							if !iface.exists().await {
-								continue;
-							}
-							iface.set_nomaster().await;
-							iface.down().await;
+				}
+				if let Some(ifconfig) = config_guard.interfaces.get(&name) {
+					if let config::InterfaceTypeConfig::Ethernet(_) = ifconfig.specific {
+						println!("[{name}] Interface appeared");
+						if !CLAIMED.lock().unwrap().contains(&name) {
+							spawn_configure_interface(name, Arc::clone(&config_guard));
 						}
-						bridge.down().await;
-						bridge.flush_addresses().await;
-						bridge.set_description("").await;
-						bridge.delete().await;
+					}
+					continue;
+				}
+				for (bridge_name, ifconfig) in &config_guard.interfaces {
+					let config::InterfaceTypeConfig::Bridge(specific) = &ifconfig.specific else {
+						continue;
+					};
+					let bridge = Interface::get_from_name(bridge_name);
+					if specific.interfaces.contains(&name) && bridge.exists().await {
+						println!(
+							"[{bridge_name}] Subinterface {name} appeared, adding it to the bridge"
+						);
+						let member = Interface::get_from_name(&name);
+						member.up().await;
+						member.set_master(bridge_name).await;
+					}
+				}
+			}
+			LinkEvent::Removed { index, name } => {
+				if !present.remove(&index) {
+					continue;
+				}
+				let config_guard = snapshot(&config);
+				if let Some(ifconfig) = config_guard.interfaces.get(&name) {
+					if let config::InterfaceTypeConfig::Ethernet(_) = ifconfig.specific {
+						println!("[{name}] Interface was removed");
+						reset_interface(name, ifconfig).await;
 					}
 				}
-
-				run_hook(format!("post-down.{name}"));
 			}
-		})
-		.collect();
-
-	join_all(futures).await;
-
-	println!("Renaming {} interfaces!", config.renames.len());
-	for entry in &config.renames {
-		let old_name = entry.0;
-		let new_name = entry.1;
-		let mut interface = Interface::get_from_name(&new_name);
-		if !interface.exists().await {
-			println!("Interface {new_name} does not exist, ignoring");
 		}
-		interface.rename(&old_name).await;
 	}
 }
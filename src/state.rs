@@ -0,0 +1,34 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Where netd keeps state that has to survive restarts and reloads
+const STATE_DIR: &str = "/var/lib/netd";
+
+fn state_path(name: &str) -> String {
+	format!("{STATE_DIR}/{name}.toml")
+}
+
+/// Save state under the given name, e.g. `dhcpc/wan`.
+/// The file is written to a temporary file first and then renamed, so it is never left half written
+pub fn save<T: Serialize>(name: &str, value: &T) {
+	let path = state_path(name);
+	let contents = toml::to_string(value).expect("Failed to serialize state");
+	if let Some(parent) = std::path::Path::new(&path).parent() {
+		let _ = std::fs::create_dir_all(parent);
+	}
+	let temp_path = format!("{path}.tmp");
+	if let Err(err) =
+		std::fs::write(&temp_path, contents).and_then(|_| std::fs::rename(&temp_path, &path))
+	{
+		println!("Failed to save state {name}: {err}");
+	}
+}
+
+/// Load state saved under the given name, if it exists and is valid
+pub fn load<T: DeserializeOwned>(name: &str) -> Option<T> {
+	let contents = std::fs::read_to_string(state_path(name)).ok()?;
+	toml::from_str(&contents).ok()
+}
+
+pub fn remove(name: &str) {
+	let _ = std::fs::remove_file(state_path(name));
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+	config::{Config, InterfaceTypeConfig},
+	link::{
+		dad::{self, AddressConflict},
+		dhcp6c::{self, Dhcpv6Lease, Subnet},
+		dhcpc::{self, DhcpLease},
+		dhcpd::{self, ServerLease},
+		ethtool::{self, EthtoolStatus},
+		interface::Interface,
+		slaac::{self, SlaacStatus},
+		uplink::{self, UplinkStatus},
+		vrrp::{self, VrrpStatus},
+	},
+};
+
+#[derive(Serialize, Default)]
+pub struct InterfaceStatus {
+	pub exists: bool,
+	pub state: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ethtool: Option<EthtoolStatus>,
+	/// Another host found using the static address
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub address_conflict: Option<AddressConflict>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub dhcp_lease: Option<DhcpLease>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub dhcpv6_lease: Option<Dhcpv6Lease>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub delegated_prefix: Option<Subnet>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub slaac: Option<SlaacStatus>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub vrrp: Vec<VrrpStatus>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub uplink: Option<UplinkStatus>,
+}
+
+/// Collects the status of all configured interfaces and renders it as TOML
+pub async fn get_status(config: &Config) -> String {
+	let mut status = BTreeMap::new();
+	for (name, ifconfig) in &config.interfaces {
+		let interface = Interface::get_from_name(name);
+		let mut ifstatus = InterfaceStatus {
+			exists: interface.exists().await,
+			..Default::default()
+		};
+		if ifstatus.exists {
+			ifstatus.state = interface.get_description().await;
+			ifstatus.address_conflict = dad::get_conflict(name);
+			ifstatus.dhcp_lease = dhcpc::get_lease(name);
+			ifstatus.dhcpv6_lease = dhcp6c::get_lease(name);
+			ifstatus.delegated_prefix = dhcp6c::get_subnet(name);
+			ifstatus.slaac = slaac::get_status(name);
+			ifstatus.vrrp = vrrp::get_status(name);
+			ifstatus.uplink = uplink::get_status(name);
+			if let InterfaceTypeConfig::Ethernet(specific) = &ifconfig.specific {
+				if let Some(ethtool_config) = &specific.ethtool {
+					ifstatus.ethtool = Some(ethtool::get_status(&interface, ethtool_config).await);
+				}
+			}
+		} else if !ifconfig.shared.optional {
+			// A required interface that did not appear, it is configured once it does
+			ifstatus.state = "MISSING".to_string();
+		}
+		status.insert(name.clone(), ifstatus);
+	}
+	toml::to_string(&status).expect("Failed to serialize status")
+}
+
+/// Collects the leases handed out by the DHCP servers and renders them as TOML
+pub fn get_leases(config: &Config) -> String {
+	let leases: BTreeMap<&String, Vec<ServerLease>> = config
+		.interfaces
+		.keys()
+		.map(|name| (name, dhcpd::get_leases(name)))
+		.filter(|(_, leases)| !leases.is_empty())
+		.collect();
+	toml::to_string(&leases).expect("Failed to serialize leases")
+}